@@ -18,7 +18,12 @@ impl AABB {
         self.max
     }
 
-    pub fn hit(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> bool {
+    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.hit_interval(r, t_min, t_max).is_some()
+    }
+
+    // the part of [t_min, t_max] that is inside the box, if any
+    pub fn hit_interval(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> Option<(f64, f64)> {
         for a in 0..3 {
             let inv_direction = 1. / r.direction()[a];
             let mut t0 = (self.min()[a] - r.origin()[a]) * inv_direction;
@@ -29,10 +34,10 @@ impl AABB {
             t_min = if t0 > t_min { t0 } else { t_min };
             t_max = if t1 < t_max { t1 } else { t_max };
             if t_max <= t_min {
-                return false;
+                return None;
             }
         }
-        return true;
+        Some((t_min, t_max))
     }
 
     pub fn combine(&self, other: AABB) -> Self {
@@ -50,4 +55,9 @@ impl AABB {
 
         return Self::new(small, big);
     }
+
+    pub fn surface_area(&self) -> f64 {
+        let extent = self.max() - self.min();
+        2. * (extent.x() * extent.y() + extent.y() * extent.z() + extent.z() * extent.x())
+    }
 }
@@ -0,0 +1,184 @@
+use crate::{
+    aabb::AABB,
+    hittable::{hit_accepted, HitRecord, Hittable},
+    ray::Ray,
+    texture::Texture,
+    utils::random_in_01,
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaMode {
+    // surfaces with alpha below the threshold are cut away, the rest is opaque
+    Cutoff(f64),
+    // rays pass through with probability 1 - alpha, which averages out to partial coverage
+    Stochastic,
+}
+
+// Opacity of a surface from the first channel of a texture (e.g. an ImageTexture of a leaf),
+// for cutting shapes out of simple geometry such as foliage cards and fences
+#[derive(Clone, Debug)]
+pub struct Alpha {
+    texture: Arc<dyn Texture>,
+    mode: AlphaMode,
+}
+
+impl Alpha {
+    pub fn new(texture: Arc<dyn Texture>, mode: AlphaMode) -> Self {
+        Self { texture, mode }
+    }
+
+    // false if a ray should ignore the surface at this point
+    pub fn is_opaque(&self, u: f64, v: f64, p: Vec3) -> bool {
+        let alpha = self.texture.value(u, v, p).x();
+        match self.mode {
+            AlphaMode::Cutoff(threshold) => alpha >= threshold,
+            AlphaMode::Stochastic => alpha >= 1. || (alpha > 0. && random_in_01() <= alpha),
+        }
+    }
+}
+
+// Applies an Alpha to any Hittable: transparent hits are skipped and the object is searched
+// again beyond them. Hits that are rejected here are invisible to everything that intersects
+// the world, including BVH traversal and shadow rays. TriangleMesh::with_alpha does the same
+// test inside each triangle.
+#[derive(Clone, Debug)]
+pub struct AlphaMasked {
+    object: Arc<dyn Hittable>,
+    alpha: Alpha,
+}
+
+impl AlphaMasked {
+    pub fn new(object: Arc<dyn Hittable>, alpha: Alpha) -> Self {
+        Self { object, alpha }
+    }
+}
+
+impl Hittable for AlphaMasked {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        hit_accepted(self.object.as_ref(), r, t_min, t_max, |rec| {
+            self.alpha.is_opaque(rec.u, rec.v, rec.p)
+        })
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.object.bounding_box(t0, t1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        diffuse::Lambertian, hittable_list::HittableList, mesh::TriangleMesh, sphere::Sphere,
+        texture::SolidColor,
+    };
+
+    // opaque on the upper half (y > 0) of space, transparent below
+    #[derive(Debug)]
+    struct UpperHalf;
+
+    impl Texture for UpperHalf {
+        fn value(&self, _u: f64, _v: f64, p: Vec3) -> Vec3 {
+            if p.y() > 0. {
+                Vec3::new(1., 1., 1.)
+            } else {
+                Vec3::new(0., 0., 0.)
+            }
+        }
+    }
+
+    // alpha equal to the texture coordinate u
+    #[derive(Debug)]
+    struct RampU;
+
+    impl Texture for RampU {
+        fn value(&self, u: f64, _v: f64, _p: Vec3) -> Vec3 {
+            Vec3::new(u, u, u)
+        }
+    }
+
+    fn sphere(alpha: Alpha) -> AlphaMasked {
+        let material = Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.5, 0.5, 0.5,
+        ))));
+        AlphaMasked::new(
+            Arc::new(Sphere::new(Vec3::new(0., 0., 0.), 1., material)),
+            alpha,
+        )
+    }
+
+    #[test]
+    fn cutoff_skips_to_the_next_opaque_hit() {
+        let masked = sphere(Alpha::new(Arc::new(UpperHalf), AlphaMode::Cutoff(0.5)));
+        // from below: the near side is cut away, the far side is hit from the inside
+        let up = Ray::new(Vec3::new(0., -5., 0.), Vec3::new(0., 1., 0.), 0.);
+        let rec = masked.hit(up, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 6.).abs() < 1e-9);
+        assert!(!rec.front_face);
+        // from above: the near side is opaque
+        let down = Ray::new(Vec3::new(0., 5., 0.), Vec3::new(0., -1., 0.), 0.);
+        let rec = masked.hit(down, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 4.).abs() < 1e-9);
+        assert!(rec.front_face);
+        // the opaque hit lies beyond t_max
+        assert!(masked.hit(up, 0.001, 5.).is_none());
+        // entirely in the transparent half
+        let sideways = Ray::new(Vec3::new(-5., -0.5, 0.), Vec3::new(1., 0., 0.), 0.);
+        assert!(masked.hit(sideways, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn mesh_cutoff_uses_the_texture_coordinates() {
+        let material = Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.5, 0.5, 0.5,
+        ))));
+        let mesh = TriangleMesh::new(
+            vec![
+                Vec3::new(0., 0., 0.),
+                Vec3::new(1., 0., 0.),
+                Vec3::new(0., 1., 0.),
+            ],
+            vec![[0, 1, 2]],
+            material,
+        )
+        .with_uvs(vec![(0., 0.), (1., 0.), (0., 1.)])
+        .with_alpha(Alpha::new(Arc::new(RampU), AlphaMode::Cutoff(0.5)));
+        let triangles: HittableList = TriangleMesh::triangles(&Arc::new(mesh));
+        let down = Vec3::new(0., 0., -1.);
+        // u follows x across this triangle
+        for &(x, y) in &[(0.1, 0.1), (0.4, 0.5), (0.45, 0.1)] {
+            let r = Ray::new(Vec3::new(x, y, 1.), down, 0.);
+            assert!(triangles.hit(r, 0.001, f64::INFINITY).is_none());
+        }
+        for &(x, y) in &[(0.55, 0.1), (0.6, 0.3), (0.9, 0.05)] {
+            let r = Ray::new(Vec3::new(x, y, 1.), down, 0.);
+            let rec = triangles.hit(r, 0.001, f64::INFINITY).unwrap();
+            assert!((rec.u - x).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn stochastic_alpha_lets_rays_through_in_proportion() {
+        let alpha = 0.3;
+        let texture = Arc::new(SolidColor::new_from_rgb(alpha, alpha, alpha));
+        let masked = sphere(Alpha::new(texture, AlphaMode::Stochastic));
+        let r = Ray::new(Vec3::new(0., 0., -5.), Vec3::new(0., 0., 1.), 0.);
+        let n = 100_000;
+        let (mut near, mut far) = (0, 0);
+        for _ in 0..n {
+            match masked.hit(r, 0.001, f64::INFINITY) {
+                Some(rec) if (rec.t - 4.).abs() < 1e-9 => near += 1,
+                Some(rec) if (rec.t - 6.).abs() < 1e-9 => far += 1,
+                Some(rec) => panic!("unexpected hit at t = {}", rec.t),
+                None => {}
+            }
+        }
+        // each side stops a ray with probability alpha
+        let near = near as f64 / n as f64;
+        let far = far as f64 / n as f64;
+        assert!((near - alpha).abs() < 0.01, "near {}", near);
+        assert!((far - (1. - alpha) * alpha).abs() < 0.01, "far {}", far);
+    }
+}
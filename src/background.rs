@@ -0,0 +1,29 @@
+use crate::vec3::Vec3;
+use dyn_clone::DynClone;
+use std::fmt::Debug;
+
+// Light arriving from infinitely far away, seen by rays that leave the scene
+pub trait Background: Debug + DynClone + Sync + Send {
+    // radiance arriving along -direction, i.e. seen looking along the unit vector direction
+    fn value(&self, direction: Vec3) -> Vec3;
+}
+
+// The book's sky: a blend from bottom straight down to top straight up
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct Gradient {
+    bottom: Vec3,
+    top: Vec3,
+}
+
+impl Gradient {
+    pub fn new(bottom: Vec3, top: Vec3) -> Self {
+        Self { bottom, top }
+    }
+}
+
+impl Background for Gradient {
+    fn value(&self, direction: Vec3) -> Vec3 {
+        let t = 0.5 * (direction.y() + 1.);
+        (1. - t) * self.bottom + t * self.top
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+use std::time::Instant;
+use weekend_path_tracer::{
+    bvh_node::BvhNode, diffuse::Lambertian, hittable::Hittable, hittable_list::HittableList,
+    mesh::TriangleMesh, ray::Ray, sphere::Sphere, texture::SolidColor, utils::random_in_range,
+    vec3::Vec3, wide_bvh::WideBvh,
+};
+
+// Compares the binary BVH against the 4-wide BVH on the same scenes and rays
+
+const NUM_RAYS: usize = 100_000;
+const EPSILON: f64 = 0.001;
+
+fn gray() -> Arc<Lambertian> {
+    Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+        0.5, 0.5, 0.5,
+    ))))
+}
+
+fn sphere_field(count: usize) -> HittableList {
+    let mut world = HittableList::new();
+    let material = gray();
+    for _ in 0..count {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(
+                random_in_range(-50., 50.),
+                random_in_range(0., 10.),
+                random_in_range(-50., 50.),
+            ),
+            random_in_range(0.05, 0.5),
+            material.clone(),
+        )));
+    }
+    world
+}
+
+fn triangle_soup(count: usize) -> HittableList {
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    for i in 0..count {
+        let corner = Vec3::new(
+            random_in_range(-50., 50.),
+            random_in_range(0., 10.),
+            random_in_range(-50., 50.),
+        );
+        vertices.push(corner);
+        vertices.push(corner + Vec3::random_in_range(-0.5, 0.5));
+        vertices.push(corner + Vec3::random_in_range(-0.5, 0.5));
+        indices.push([3 * i, 3 * i + 1, 3 * i + 2]);
+    }
+    TriangleMesh::triangles(&Arc::new(TriangleMesh::new(vertices, indices, gray())))
+}
+
+fn random_rays() -> Vec<Ray> {
+    let origin = Vec3::new(0., 20., 80.);
+    (0..NUM_RAYS)
+        .map(|_| {
+            let target = Vec3::new(
+                random_in_range(-50., 50.),
+                random_in_range(0., 10.),
+                random_in_range(-50., 50.),
+            );
+            Ray::new(origin, target - origin, 0.)
+        })
+        .collect()
+}
+
+// returns the hit distances and the number of rays per second
+fn trace(accel: &dyn Hittable, rays: &[Ray]) -> (Vec<Option<f64>>, f64) {
+    let start = Instant::now();
+    let hits: Vec<Option<f64>> = rays
+        .iter()
+        .map(|r| accel.hit(*r, EPSILON, f64::INFINITY).map(|h| h.t))
+        .collect();
+    let elapsed = start.elapsed().as_secs_f64();
+    (hits, rays.len() as f64 / elapsed)
+}
+
+fn bench(name: &str, world: &HittableList) {
+    let binary = BvhNode::new_from_hittable(world, 0., 1.);
+    let wide = WideBvh::from_binary(&binary, 0., 1.);
+    let wide_scalar = wide.clone().without_simd();
+    let rays = random_rays();
+
+    let (expected, binary_speed) = trace(&binary, &rays);
+    println!("{} ({} objects)", name, world.objects.len());
+    println!("  {:<14} {:>8.3} Mrays/s", "binary", binary_speed / 1e6);
+    for (label, accel) in [("4-wide scalar", &wide_scalar), ("4-wide SIMD", &wide)].iter() {
+        let (hits, speed) = trace(*accel, &rays);
+        let mismatches = hits.iter().zip(&expected).filter(|(a, b)| a != b).count();
+        println!(
+            "  {:<14} {:>8.3} Mrays/s ({:.2}x), {} mismatched hits",
+            label,
+            speed / 1e6,
+            speed / binary_speed,
+            mismatches
+        );
+    }
+}
+
+fn main() {
+    bench("sphere field", &sphere_field(100_000));
+    bench("triangle soup", &triangle_soup(100_000));
+}
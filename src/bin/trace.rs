@@ -3,17 +3,43 @@ use rayon::iter::ParallelIterator;
 use rayon::prelude::*;
 use std::sync::Arc;
 use weekend_path_tracer::{
+    aabb::AABB,
+    alpha::{Alpha, AlphaMode},
+    background::{Background, Gradient},
+    bvh_node::BvhNode,
     camera::Camera,
     canvas::Canvas,
+    cloth::Cloth,
+    coated::Coated,
+    conductor::Conductor,
     consts::{sky_blue, white},
-    dielectric::Dielectric,
+    dielectric::{Dielectric, RefractiveIndex},
     diffuse::Lambertian,
+    diffuse_light::DiffuseLight,
+    environment::EnvironmentMap,
+    fog::Fog,
+    grid_medium::{DensityGrid, GridMedium},
+    hittable::{HitRecord, Hittable},
     hittable_list::HittableList,
+    instance::Instance,
+    light::{DirectionalLight, Light, PointLight, SpotLight},
+    medium::HomogeneousMedium,
+    mesh::TriangleMesh,
     metal::Metal,
+    mix_material::MixMaterial,
     moving_sphere::MovingSphere,
+    normal_map::BumpMap,
+    phase::PhaseFunction,
     ray::Ray,
+    ray_packet::{RayPacket, PACKET_SIZE},
+    sky::PreethamSky,
+    spectrum::{xyz_to_linear_srgb, Illuminant, Radiance, SampledSpectrum, SampledWavelengths},
     sphere::Sphere,
+    subsurface::Subsurface,
     texture::{CheckerTexture, NoiseTexture, SolidColor},
+    thin_film::ThinFilm,
+    transform::Transform,
+    two_sided::{BackFaceCulled, TwoSidedMaterial},
     utils::{random_in_01, random_in_range},
     vec3::Vec3,
 };
@@ -23,37 +49,422 @@ const IMAGE_HEIGHT: usize = 500;
 const ASPECT_RATIO: f64 = IMAGE_WIDTH as f64 / IMAGE_HEIGHT as f64;
 const SAMPLES_PER_PIXEL: usize = 100;
 const MAX_DEPTH: u8 = 50;
+// scattering events inside a medium, which don't count towards MAX_DEPTH
+const MAX_VOLUME_BOUNCES: usize = 256;
 const EPSILON: f64 = 0.001;
+// trace primary rays in packets of neighbouring pixels
+const PACKET_TRACING: bool = true;
+// trace a handful of wavelengths per path instead of RGB
+const SPECTRAL_RENDERING: bool = false;
 
 fn vec_to_u32(color: Vec3) -> u32 {
     let (r, g, b) = color.to_rgb();
     ((r as u32) << 16) | ((g as u32) << 8) | (b as u32)
 }
 
-fn lerp(a: Vec3, b: Vec3, t: f64) -> Vec3 {
-    (1. - t) * a + t * b
+// what a path can run into: the objects and the fog between them, lit by the background
+// and by the lights, which are sampled at every bounce
+struct Scene<'a> {
+    world: &'a BvhNode,
+    fog: Option<Fog>,
+    background: Arc<dyn Background>,
+    lights: Vec<Arc<dyn Light>>,
 }
 
-fn ray_color(r: Ray, world: &HittableList, depth: u8) -> Vec3 {
+// Power heuristic for multiple importance sampling: weight for a sample drawn with density
+// pdf, when another strategy could have drawn it with density other_pdf
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let pdf_2 = pdf * pdf;
+    let other_pdf_2 = other_pdf * other_pdf;
+    if pdf_2 + other_pdf_2 <= 0. {
+        0.
+    } else {
+        pdf_2 / (pdf_2 + other_pdf_2)
+    }
+}
+
+// Where a path is: outside every object, in the fog if there is any, or inside an object,
+// which may be filled with a medium. Nested objects aren't tracked.
+#[derive(Clone, Debug)]
+enum Region {
+    Outside,
+    Inside(Option<HomogeneousMedium>),
+}
+
+// region is where r starts out.
+// bsdf_pdf is the density with which the last bounce picked r's direction, if that bounce
+// also sampled the lights, and 0 otherwise (camera rays, specular bounces and media).
+// R is Vec3 for RGB rendering or SampledSpectrum for spectral rendering.
+fn ray_color<R: Radiance>(
+    r: Ray,
+    scene: &Scene,
+    depth: u8,
+    region: Region,
+    bsdf_pdf: f64,
+    wavelengths: &mut SampledWavelengths,
+) -> R {
     // If we've exceeded the ray bounce limit, no more light is gathered.
     if depth <= 0 {
-        Vec3::default()
+        R::default()
     } else {
         // use EPSILON to avoid salt-and-pepper noise
-        match world.hit(r, EPSILON, std::f64::INFINITY) {
-            Some(hit) => match hit.material.scatter(r, &hit) {
-                Some((scattered, attenuation)) => {
-                    attenuation * ray_color(scattered, world, depth - 1)
+        shade(
+            r,
+            scene.world.hit(r, EPSILON, f64::INFINITY),
+            scene,
+            depth,
+            region,
+            bsdf_pdf,
+            wavelengths,
+        )
+    }
+}
+
+// Color seen along r, given its closest hit (which may come from packet traversal)
+fn shade<R: Radiance>(
+    r: Ray,
+    hit: Option<HitRecord>,
+    scene: &Scene,
+    depth: u8,
+    region: Region,
+    bsdf_pdf: f64,
+    wavelengths: &mut SampledWavelengths,
+) -> R {
+    // a scattering medium may move the path on to a different surface
+    let walk: Option<Walk<R>> = match &region {
+        Region::Inside(Some(medium)) => random_walk(r, hit, scene, medium, wavelengths),
+        Region::Inside(None) => Some(Walk::unscattered(r, hit)),
+        Region::Outside => match &scene.fog {
+            Some(fog) => fog_walk(r, hit, scene, fog, wavelengths),
+            None => Some(Walk::unscattered(r, hit)),
+        },
+    };
+    let Walk {
+        r,
+        hit,
+        weight,
+        direct: in_medium,
+        phase_pdf,
+    } = match walk {
+        Some(walk) => walk,
+        None => return R::default(),
+    };
+    // if the path scattered in a medium, that is where the lights were last sampled
+    let bsdf_pdf = phase_pdf.unwrap_or(bsdf_pdf);
+    let transmittance = R::from_reflectance(weight, wavelengths);
+    let radiance = match hit {
+        Some(hit) => {
+            let emitted = R::emitted(&hit, wavelengths);
+            if hit.material.is_wavelength_dependent() {
+                // the other wavelengths would have gone elsewhere
+                wavelengths.terminate_secondary();
+            }
+            // lights are outside of objects, so are only sampled from outside
+            let sample_lights = matches!(region, Region::Outside);
+            let direct = if sample_lights {
+                light_sample(r, &hit, scene, wavelengths)
+            } else {
+                R::default()
+            };
+            match hit.material.scatter(r, &hit) {
+                Some((scattered, attenuation, is_specular)) => {
+                    // once a path has picked a wavelength it keeps it
+                    let scattered = match r.wavelength() {
+                        Some(lambda) => scattered.with_wavelength(lambda),
+                        None => scattered,
+                    };
+                    // crossing the surface moves us into or out of the object
+                    let stays_outside = hit.material.is_volumetric()
+                        || scattered.direction().dot(hit.geometric_normal) >= 0.;
+                    let next_region = if stays_outside {
+                        region
+                    } else if hit.front_face {
+                        Region::Inside(hit.material.interior_medium())
+                    } else {
+                        Region::Outside
+                    };
+                    // a specular direction couldn't have been picked by light sampling, so
+                    // whatever it hits counts in full
+                    let next_bsdf_pdf = if sample_lights && stays_outside && !is_specular {
+                        hit.material.scattering_pdf(r, &hit, scattered.direction())
+                    } else {
+                        0.
+                    };
+                    let incoming: R = ray_color(
+                        scattered,
+                        scene,
+                        depth - 1,
+                        next_region,
+                        next_bsdf_pdf,
+                        wavelengths,
+                    );
+                    transmittance
+                        * (emitted
+                            + direct
+                            + R::from_reflectance(attenuation, wavelengths) * incoming)
                 }
-                None => Vec3::default(),
+                None => transmittance * (emitted + direct),
+            }
+        }
+        None => {
+            let direction = r.direction().norm();
+            let mut radiance = scene.background.value(direction);
+            for light in &scene.lights {
+                // weighted against light_sample() having found the same light
+                let weight = if bsdf_pdf > 0. {
+                    power_heuristic(bsdf_pdf, light.pdf(r.origin(), direction))
+                } else {
+                    1.
+                };
+                radiance += weight * light.emitted(direction);
+            }
+            transmittance * R::from_illuminant(radiance, wavelengths)
+        }
+    };
+    in_medium + radiance
+}
+
+// Next event estimation at a surface
+fn light_sample<R: Radiance>(
+    r: Ray,
+    hit: &HitRecord,
+    scene: &Scene,
+    wavelengths: &SampledWavelengths,
+) -> R {
+    let volumetric = hit.material.is_volumetric();
+    direct_light(
+        hit.p,
+        r.time(),
+        scene,
+        |direction| {
+            if !volumetric && direction.dot(hit.geometric_normal) <= 0. {
+                return None;
+            }
+            let f = hit.material.eval(r, hit, direction);
+            if f == Vec3::default() {
+                return None;
+            }
+            Some((f, hit.material.scattering_pdf(r, hit, direction)))
+        },
+        |shadow_ray, distance| match &scene.fog {
+            Some(fog) => white() * fog.transmittance(shadow_ray, distance),
+            None => white(),
+        },
+        wavelengths,
+    )
+}
+
+// Light reaching p directly from each of the scene's lights, with a shadow ray to each; the
+// shadow rays share an origin, so they are traced together in packets.
+// scattering gives how much of the light arriving from a direction is sent on along the
+// path, and the density with which the path would have picked that direction itself.
+// transmittance is the fraction getting through the fog or medium along a shadow ray.
+fn direct_light<R, S, T>(
+    p: Vec3,
+    time: f64,
+    scene: &Scene,
+    scattering: S,
+    transmittance: T,
+    wavelengths: &SampledWavelengths,
+) -> R
+where
+    R: Radiance,
+    S: Fn(Vec3) -> Option<(Vec3, f64)>,
+    T: Fn(&Ray, f64) -> Vec3,
+{
+    let mut direct = R::default();
+    for lights in scene.lights.chunks(PACKET_SIZE) {
+        let mut packet = RayPacket::default();
+        let mut t_max = [0.; PACKET_SIZE];
+        let mut contributions = [None; PACKET_SIZE];
+        for (i, light) in lights.iter().enumerate() {
+            let sample = match light.sample(p) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            if sample.pdf <= 0. {
+                continue;
+            }
+            let (f, scattering_pdf) = match scattering(sample.direction) {
+                Some(scattered) => scattered,
+                None => continue,
+            };
+            let weight = if light.is_delta() {
+                1.
+            } else {
+                power_heuristic(sample.pdf, scattering_pdf)
+            };
+            packet.rays[i] = Ray::new(p, sample.direction, time);
+            packet.active |= 1 << i;
+            t_max[i] = sample.distance - EPSILON;
+            contributions[i] = Some((sample, f * (weight / sample.pdf)));
+        }
+        if packet.active == 0 {
+            continue;
+        }
+        let occluded = scene.world.occluded_packet(&packet, EPSILON, t_max);
+        for (i, contribution) in contributions.iter().enumerate() {
+            let (sample, reflectance) = match contribution {
+                Some(contribution) if occluded & (1 << i) == 0 => contribution,
+                _ => continue,
+            };
+            direct += R::from_reflectance(
+                transmittance(&packet.rays[i], sample.distance) * *reflectance,
+                wavelengths,
+            ) * R::from_illuminant(sample.radiance, wavelengths);
+        }
+    }
+    direct
+}
+
+// How far a path got through a medium: the ray segment it ended on and that segment's hit,
+// the path weight picked up on the way, the light it gathered from the lights where it
+// scattered, and the density with which the last scattering event picked r's direction.
+struct Walk<R> {
+    r: Ray,
+    hit: Option<HitRecord>,
+    weight: Vec3,
+    direct: R,
+    phase_pdf: Option<f64>,
+}
+
+impl<R: Radiance> Walk<R> {
+    fn unscattered(r: Ray, hit: Option<HitRecord>) -> Self {
+        Self {
+            r,
+            hit,
+            weight: white(),
+            direct: R::default(),
+            phase_pdf: None,
+        }
+    }
+
+    // Scatters the path at p, sampling the lights from there before picking a new direction
+    fn scatter<T: Fn(&Ray, f64) -> Vec3>(
+        &mut self,
+        p: Vec3,
+        phase: &dyn PhaseFunction,
+        scene: &Scene,
+        transmittance: T,
+        wavelengths: &SampledWavelengths,
+    ) {
+        let incoming = self.r.direction().norm();
+        let direct: R = direct_light(
+            p,
+            self.r.time(),
+            scene,
+            |direction| {
+                let value = phase.eval(incoming, direction);
+                if value <= 0. {
+                    return None;
+                }
+                Some((white() * value, phase.pdf(incoming, direction)))
             },
-            None => {
-                let unit_direction = r.direction().norm();
-                let t = 0.5 * (unit_direction.y() + 1.);
-                lerp(white(), sky_blue(), t)
+            transmittance,
+            wavelengths,
+        );
+        self.direct += R::from_reflectance(self.weight, wavelengths) * direct;
+
+        let direction = phase.sample(incoming);
+        self.phase_pdf = Some(phase.pdf(incoming, direction));
+        let next = Ray::new(p, direction, self.r.time());
+        self.r = match self.r.wavelength() {
+            Some(lambda) => next.with_wavelength(lambda),
+            None => next,
+        };
+        self.hit = scene.world.hit(self.r, EPSILON, f64::INFINITY);
+    }
+}
+
+// Follows r through a medium until it reaches a surface (or leaves the scene), or returns
+// None if the path got lost inside the medium.
+fn random_walk<R: Radiance>(
+    r: Ray,
+    hit: Option<HitRecord>,
+    scene: &Scene,
+    medium: &HomogeneousMedium,
+    wavelengths: &SampledWavelengths,
+) -> Option<Walk<R>> {
+    let mut walk = Walk::unscattered(r, hit);
+    // like the path, the medium ends at the object's surface
+    let transmittance = |_: &Ray, distance: f64| {
+        if distance.is_finite() {
+            medium.transmittance(distance)
+        } else {
+            white()
+        }
+    };
+    for _ in 0..MAX_VOLUME_BOUNCES {
+        let distance = match &walk.hit {
+            Some(hit) => hit.t * walk.r.direction().magnitude(),
+            None => return Some(walk),
+        };
+        let (scattered_at, event_weight) = medium.sample_distance(distance);
+        walk.weight = walk.weight * event_weight;
+        match scattered_at {
+            None => return Some(walk),
+            Some(scattered_at) => {
+                let p = walk.r.at(scattered_at / walk.r.direction().magnitude());
+                walk.scatter(p, medium.phase(), scene, transmittance, wavelengths);
+            }
+        }
+    }
+    None
+}
+
+// Like random_walk, but through the scene's fog. This also covers rays heading for the sky,
+// which lose light to the fog and pick up light scattered towards them on the way.
+fn fog_walk<R: Radiance>(
+    r: Ray,
+    hit: Option<HitRecord>,
+    scene: &Scene,
+    fog: &Fog,
+    wavelengths: &SampledWavelengths,
+) -> Option<Walk<R>> {
+    let mut walk = Walk::unscattered(r, hit);
+    let transmittance =
+        |shadow_ray: &Ray, distance| white() * fog.transmittance(shadow_ray, distance);
+    for _ in 0..MAX_VOLUME_BOUNCES {
+        let t_max = walk.hit.as_ref().map_or(f64::INFINITY, |hit| hit.t);
+        match fog.sample_distance(&walk.r, t_max) {
+            None => return Some(walk),
+            Some(t) => {
+                walk.weight = walk.weight * fog.albedo();
+                let p = walk.r.at(t);
+                walk.scatter(p, fog.phase(), scene, transmittance, wavelengths);
             }
         }
     }
+    None
+}
+
+// One sample of the light arriving along a camera ray: linear sRGB, or CIE XYZ when
+// rendering spectrally
+fn trace_sample(r: Ray, hit: Option<HitRecord>, scene: &Scene) -> Vec3 {
+    if SPECTRAL_RENDERING {
+        let mut wavelengths = SampledWavelengths::sample();
+        let r = r.with_wavelength(wavelengths.hero());
+        let radiance: SampledSpectrum = shade(
+            r,
+            hit,
+            scene,
+            MAX_DEPTH,
+            Region::Outside,
+            0.,
+            &mut wavelengths,
+        );
+        wavelengths.to_xyz(radiance)
+    } else {
+        shade(
+            r,
+            hit,
+            scene,
+            MAX_DEPTH,
+            Region::Outside,
+            0.,
+            &mut SampledWavelengths::default(),
+        )
+    }
 }
 
 fn test_scene() -> HittableList {
@@ -184,6 +595,412 @@ fn two_perlin_spheres() -> HittableList {
     return world;
 }
 
+// A lumpy octahedron standing in for a loaded rock model
+fn rock_mesh() -> TriangleMesh {
+    let vertices = vec![
+        Vec3::new(1., 0., 0.),
+        Vec3::new(-1., 0., 0.),
+        Vec3::new(0., 1., 0.),
+        Vec3::new(0., -1., 0.),
+        Vec3::new(0., 0., 1.),
+        Vec3::new(0., 0., -1.),
+    ]
+    .into_iter()
+    .map(|v| v * random_in_range(0.6, 1.))
+    .collect();
+    let indices = vec![
+        [0, 2, 4],
+        [4, 2, 1],
+        [1, 2, 5],
+        [5, 2, 0],
+        [4, 3, 0],
+        [1, 3, 4],
+        [5, 3, 1],
+        [0, 3, 5],
+    ];
+    TriangleMesh::new(
+        vertices,
+        indices,
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.4, 0.4, 0.4,
+        )))),
+    )
+}
+
+// thousands of copies of one rock, sharing a single bottom-level BVH
+fn instanced_rocks() -> HittableList {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.5, 0.6, 0.3,
+        )))),
+    )));
+
+    let rock = TriangleMesh::build_bvh(&Arc::new(rock_mesh()), 0., 1.);
+    let mossy = Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+        0.2, 0.4, 0.1,
+    ))));
+    let mut instances = HittableList::new();
+    for _ in 0..5000 {
+        let transform = Transform::uniform_scaling(random_in_range(0.05, 0.3))
+            .then(Transform::rotation_y(random_in_range(0., 360.)))
+            .then(Transform::translation(Vec3::new(
+                random_in_range(-20., 20.),
+                0.,
+                random_in_range(-20., 20.),
+            )));
+        let instance = Instance::new(rock.clone(), transform, 0., 1.);
+        if random_in_01() < 0.2 {
+            instances.add(Arc::new(instance.with_material(mossy.clone())));
+        } else {
+            instances.add(Arc::new(instance));
+        }
+    }
+    world.add(Arc::new(BvhNode::new_from_hittable(&instances, 0., 1.)));
+
+    world
+}
+
+// a diamond lit by a warm blackbody lamp and a daylight lamp; best with SPECTRAL_RENDERING
+fn spectral_lights() -> HittableList {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.5, 0.5, 0.5,
+        )))),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 1., 0.),
+        1.,
+        Arc::new(Dielectric::dispersive(RefractiveIndex::diamond())),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(-3., 4., -2.),
+        0.5,
+        Arc::new(DiffuseLight::new_from_spectrum(
+            Illuminant::blackbody(2700.).scaled(8.),
+        )),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(3., 4., 2.),
+        0.5,
+        Arc::new(DiffuseLight::new_from_spectrum(
+            Illuminant::d65().scaled(8.),
+        )),
+    )));
+    world
+}
+
+// rust patches on a copper sphere next to a sphere of lacquered red paint
+fn rust_and_paint() -> HittableList {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.5, 0.5, 0.5,
+        )))),
+    )));
+
+    let rust = Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+        0.35, 0.12, 0.04,
+    ))));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(-1.2, 1., 0.),
+        1.,
+        Arc::new(MixMaterial::new(
+            Arc::new(Conductor::copper(0.2)),
+            rust,
+            Arc::new(NoiseTexture::new(4.)),
+        )),
+    )));
+
+    let paint = Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+        0.7, 0.05, 0.05,
+    ))));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(1.2, 1., 0.),
+        1.,
+        Arc::new(Coated::new(paint, 1.5)),
+    )));
+    world
+}
+
+// a hammered-looking metal sphere on a bumpy floor; the bumps only exist in the shading
+fn bumpy_spheres() -> HittableList {
+    let mut world = HittableList::new();
+    let bumps = Arc::new(NoiseTexture::new(4.));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(BumpMap::new(
+            Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+                0.5, 0.5, 0.5,
+            )))),
+            bumps.clone(),
+            0.02,
+        )),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 2., 0.),
+        2.,
+        Arc::new(BumpMap::new(Arc::new(Conductor::silver(0.1)), bumps, 0.05)),
+    )));
+    world
+}
+
+// upright quads with holes cut out by a noise alpha mask, like a row of hedges
+fn cutout_cards() -> HittableList {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.5, 0.5, 0.5,
+        )))),
+    )));
+
+    let card = TriangleMesh::new(
+        vec![
+            Vec3::new(-2., 0., 0.),
+            Vec3::new(2., 0., 0.),
+            Vec3::new(2., 3., 0.),
+            Vec3::new(-2., 3., 0.),
+        ],
+        vec![[0, 1, 2], [0, 2, 3]],
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.1, 0.4, 0.1,
+        )))),
+    )
+    .with_uvs(vec![(0., 0.), (1., 0.), (1., 1.), (0., 1.)])
+    .with_alpha(Alpha::new(
+        Arc::new(NoiseTexture::new(8.)),
+        AlphaMode::Cutoff(0.5),
+    ));
+    let card = TriangleMesh::build_bvh(&Arc::new(card), 0., 1.);
+    for i in 0..3 {
+        let transform = Transform::translation(Vec3::new(0., 0., -2. * i as f64));
+        world.add(Arc::new(Instance::new(card.clone(), transform, 0., 1.)));
+    }
+    world
+}
+
+// iridescent soap bubbles and a heat-tinted metal ball
+fn soap_bubbles() -> HittableList {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.2, 0.2, 0.2,
+        )))),
+    )));
+    for (i, thickness) in [250., 380., 520.].iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-3. + 3. * i as f64, 1.5, 0.),
+            1.,
+            Arc::new(Dielectric::new(1.).with_thin_film(ThinFilm::new(*thickness, 1.33))),
+        )));
+    }
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 0.7, 2.),
+        0.7,
+        Arc::new(Conductor::silver(0.05).with_thin_film(ThinFilm::new(300., 2.4))),
+    )));
+    world
+}
+
+// marble, wax and skin-like spheres lit by the sky
+fn subsurface_spheres() -> HittableList {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.5, 0.5, 0.5,
+        )))),
+    )));
+    let materials = [
+        Subsurface::new(Vec3::new(0.99, 0.99, 0.98), Vec3::new(0.2, 0.2, 0.2), 1.5),
+        Subsurface::new(Vec3::new(0.98, 0.9, 0.6), Vec3::new(0.5, 0.3, 0.2), 1.45),
+        Subsurface::new(Vec3::new(0.95, 0.75, 0.6), Vec3::new(0.6, 0.25, 0.12), 1.4),
+    ];
+    for (i, material) in materials.iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-2.2 + 2.2 * i as f64, 1., 0.),
+            1.,
+            Arc::new(material.clone()),
+        )));
+    }
+    world
+}
+
+// velvet and cotton-like spheres with increasing sheen roughness
+fn cloth_spheres() -> HittableList {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.5, 0.5, 0.5,
+        )))),
+    )));
+    for (i, roughness) in [0.3, 0.6, 0.9].iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-2.2 + 2.2 * i as f64, 1., 0.),
+            1.,
+            Arc::new(Cloth::new(
+                Arc::new(SolidColor::new_from_rgb(0.25, 0.02, 0.1)),
+                Arc::new(SolidColor::new_from_rgb(0.9, 0.5, 0.7)),
+                Arc::new(SolidColor::new_from_rgb(*roughness, *roughness, *roughness)),
+            )),
+        )));
+    }
+    world
+}
+
+// A cutaway room: the walls face inwards and their back faces are culled, so the camera
+// looks in through the near walls. Inside, a card with a different color on each side.
+fn cutaway_room() -> HittableList {
+    let mut world = HittableList::new();
+    let mut vertices = vec![];
+    for i in 0..8 {
+        vertices.push(Vec3::new(
+            if i & 1 == 0 { -2. } else { 2. },
+            if i & 2 == 0 { 0. } else { 2.5 },
+            if i & 4 == 0 { -2. } else { 2. },
+        ));
+    }
+    let walls = TriangleMesh::new(
+        vertices,
+        vec![
+            [0, 2, 6],
+            [0, 6, 4],
+            [1, 7, 3],
+            [1, 5, 7],
+            [0, 5, 1],
+            [0, 4, 5],
+            [2, 3, 7],
+            [2, 7, 6],
+            [0, 1, 3],
+            [0, 3, 2],
+            [4, 7, 5],
+            [4, 6, 7],
+        ],
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.8, 0.75, 0.7,
+        )))),
+    );
+    let walls = TriangleMesh::build_bvh(&Arc::new(walls), 0., 1.);
+    world.add(Arc::new(BackFaceCulled::new(walls)));
+
+    let card = TriangleMesh::new(
+        vec![
+            Vec3::new(-1., 0.2, -0.5),
+            Vec3::new(1., 0.2, 0.5),
+            Vec3::new(1., 1.8, 0.5),
+            Vec3::new(-1., 1.8, -0.5),
+        ],
+        vec![[0, 1, 2], [0, 2, 3]],
+        Arc::new(TwoSidedMaterial::new(
+            Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+                0.1, 0.4, 0.1,
+            )))),
+            Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+                0.6, 0.7, 0.4,
+            )))),
+        )),
+    );
+    world.add(TriangleMesh::build_bvh(&Arc::new(card), 0., 1.));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., 2.2, 0.),
+        0.2,
+        Arc::new(DiffuseLight::new(Arc::new(SolidColor::new_from_rgb(
+            8., 8., 8.,
+        )))),
+    )));
+    world
+}
+
+fn cloud_and_fire() -> HittableList {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.5, 0.5, 0.5,
+        )))),
+    )));
+    let cloud = GridMedium::new(
+        AABB::new(Vec3::new(-3.5, 0.5, -1.5), Vec3::new(-0.5, 3.5, 1.5)),
+        DensityGrid::from_turbulence(64, 4.),
+        6.,
+        Vec3::new(0.95, 0.95, 0.95),
+    )
+    .with_asymmetry(0.6);
+    world.add(Arc::new(cloud));
+    let smoke = DensityGrid::from_turbulence(64, 3.);
+    let fire = GridMedium::new(
+        AABB::new(Vec3::new(0.5, 0., -1.5), Vec3::new(3.5, 3., 1.5)),
+        smoke.clone(),
+        4.,
+        Vec3::new(0.3, 0.3, 0.3),
+    )
+    .with_emission(smoke.scaled(2500.), 8.);
+    world.add(Arc::new(fire));
+    world
+}
+
+// Outdoor light: a clear sky with the sun in it, which is also sampled as a light
+fn daylight() -> (Arc<dyn Background>, Vec<Arc<dyn Light>>) {
+    let sky = PreethamSky::new(30., 45., 3.);
+    (Arc::new(sky), vec![Arc::new(sky.sun())])
+}
+
+// Image based lighting from an equirectangular .hdr or .exr panorama. The map is sampled as
+// a light, which escaping rays see too, so the background itself is black.
+fn environment(path: &str) -> (Arc<dyn Background>, Vec<Arc<dyn Light>>) {
+    let black: Arc<dyn Background> = Arc::new(Gradient::new(Vec3::default(), Vec3::default()));
+    match EnvironmentMap::load(path) {
+        Ok(map) => (
+            black,
+            vec![Arc::new(map.with_rotation(0.).with_intensity(1.))],
+        ),
+        Err(e) => {
+            eprintln!("Couldn't load environment map {}: {}", path, e);
+            (black, vec![])
+        }
+    }
+}
+
+// A dark stage lit only by lights that paths can't hit: a warm point light, a spotlight from
+// above and a dim, slightly soft moonlight
+fn stage_lights() -> (Arc<dyn Background>, Vec<Arc<dyn Light>>) {
+    let black: Arc<dyn Background> = Arc::new(Gradient::new(Vec3::default(), Vec3::default()));
+    let lights: Vec<Arc<dyn Light>> = vec![
+        Arc::new(PointLight::new(
+            Vec3::new(4., 3., 4.),
+            Vec3::new(20., 15., 10.),
+        )),
+        Arc::new(SpotLight::new(
+            Vec3::new(0., 8., 0.),
+            Vec3::new(0., -1., 0.),
+            Vec3::new(60., 60., 60.),
+            25.,
+            15.,
+        )),
+        Arc::new(
+            DirectionalLight::new(Vec3::new(-1., 2., -1.), Vec3::new(0.1, 0.12, 0.2))
+                .with_angular_diameter(2.),
+        ),
+    ];
+    (black, lights)
+}
+
 fn get_background_image_data() -> Vec<u32> {
     // let world = test_scene();
     // let look_from = Vec3::new(3., 3., 2.);
@@ -202,7 +1019,33 @@ fn get_background_image_data() -> Vec<u32> {
     // );
 
     // let world = random_scene(false);
+    // let world = instanced_rocks();
+    // let world = spectral_lights();
+    // let world = rust_and_paint();
+    // let world = bumpy_spheres();
+    // let world = cutout_cards();
+    // let world = soap_bubbles();
+    // let world = subsurface_spheres();
+    // let world = cloth_spheres();
+    // let world = cutaway_room();
+    // let world = cloud_and_fire();
     let world = two_perlin_spheres();
+    let world = BvhNode::new_from_hittable(&world, 0., 1.);
+
+    // haze for big outdoor scenes such as random_scene, thickest near the ground
+    // let fog = Some(Fog::new(0.03, Vec3::new(0.9, 0.9, 0.9), 1000.).with_height_falloff(0., 0.5));
+    let fog = None;
+    let background: Arc<dyn Background> = Arc::new(Gradient::new(white(), sky_blue()));
+    let lights: Vec<Arc<dyn Light>> = vec![];
+    // let (background, lights) = daylight();
+    // let (background, lights) = environment("environment.hdr");
+    // let (background, lights) = stage_lights();
+    let scene = Scene {
+        world: &world,
+        fog,
+        background,
+        lights,
+    };
 
     let lookfrom = Vec3::new(13., 2., 3.);
     let lookat = Vec3::new(0., 0., 0.);
@@ -230,17 +1073,41 @@ fn get_background_image_data() -> Vec<u32> {
         .progress()
         .for_each(|(col_index, row)| {
             // println!("Scanlines remaining: {}", col_index);
-            for (row_index, pixel) in row.iter_mut().enumerate() {
-                let mut color = Vec3::default();
+            for (chunk_index, pixels) in row.chunks_mut(PACKET_SIZE).enumerate() {
+                let mut colors = [Vec3::default(); PACKET_SIZE];
                 for _s in 0..SAMPLES_PER_PIXEL {
-                    let u: f64 = (row_index as f64 + random_in_01()) / IMAGE_WIDTH as f64;
-                    let v: f64 = (col_index as f64 + random_in_01()) / IMAGE_HEIGHT as f64;
-                    let r = cam.get_ray(u, v);
-                    color += ray_color(r, &world, MAX_DEPTH);
+                    let mut rays = [Ray::default(); PACKET_SIZE];
+                    for (i, r) in rays.iter_mut().take(pixels.len()).enumerate() {
+                        let row_index = chunk_index * PACKET_SIZE + i;
+                        let u: f64 = (row_index as f64 + random_in_01()) / IMAGE_WIDTH as f64;
+                        let v: f64 = (col_index as f64 + random_in_01()) / IMAGE_HEIGHT as f64;
+                        *r = cam.get_ray(u, v);
+                    }
+                    let rays = &rays[..pixels.len()];
+                    if PACKET_TRACING {
+                        // primary rays are coherent, so trace them together; the bounces
+                        // after that go their own way
+                        let hits = world.hit_packet(&RayPacket::new(rays), EPSILON, f64::INFINITY);
+                        for (i, (r, hit)) in rays.iter().zip(hits.iter().cloned()).enumerate() {
+                            colors[i] += trace_sample(*r, hit, &scene);
+                        }
+                    } else {
+                        for (i, r) in rays.iter().enumerate() {
+                            let hit = world.hit(*r, EPSILON, f64::INFINITY);
+                            colors[i] += trace_sample(*r, hit, &scene);
+                        }
+                    }
                 }
-                color /= SAMPLES_PER_PIXEL as f64;
 
-                *pixel = vec_to_u32(color);
+                for (pixel, color) in pixels.iter_mut().zip(colors.iter()) {
+                    // the film collects XYZ in spectral mode
+                    let color = if SPECTRAL_RENDERING {
+                        xyz_to_linear_srgb(*color)
+                    } else {
+                        *color
+                    };
+                    *pixel = vec_to_u32(color / SAMPLES_PER_PIXEL as f64);
+                }
             }
         });
     buffer
@@ -3,103 +3,398 @@ use crate::{
     hittable::{HitRecord, Hittable},
     hittable_list::HittableList,
     ray::Ray,
-    utils::random_int_in_range,
+    ray_packet::{RayPacket, PACKET_SIZE},
+    vec3::Vec3,
 };
+use rayon::prelude::*;
 use std::cmp::Ordering::Equal;
 use std::{cmp::Ordering, sync::Arc};
 
+// relative costs used for the surface area heuristic
+const TRAVERSAL_COST: f64 = 1.;
+const INTERSECTION_COST: f64 = 1.;
+// subtrees with fewer objects than this are built on the current thread
+const PARALLEL_BUILD_THRESHOLD: usize = 4096;
+
+#[derive(Clone, Debug)]
+pub(crate) enum BvhChild {
+    Node(Box<BvhNode>),
+    // index is the object's position in the list the tree was built from
+    Leaf(usize, Arc<dyn Hittable>),
+}
+
+impl BvhChild {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        match self {
+            BvhChild::Node(node) => node.hit(r, t_min, t_max),
+            BvhChild::Leaf(_, object) => object.hit(r, t_min, t_max),
+        }
+    }
+
+    pub(crate) fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        match self {
+            BvhChild::Node(node) => Some(node.bounds),
+            BvhChild::Leaf(_, object) => object.bounding_box(t0, t1),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct BvhNode {
-    left: Arc<dyn Hittable>,
-    right: Arc<dyn Hittable>,
-    bounds: AABB,
+    pub(crate) left: BvhChild,
+    pub(crate) right: BvhChild,
+    pub(crate) bounds: AABB,
 }
 impl BvhNode {
     pub fn new_from_hittable(list: &HittableList, time_0: f64, time_1: f64) -> Self {
-        BvhNode::new(&list.objects, 0, list.objects.len(), time_0, time_1)
+        BvhNode::build(list, time_0, time_1, PARALLEL_BUILD_THRESHOLD)
+    }
+    // subtrees of at least parallel_threshold objects are split across threads
+    fn build(list: &HittableList, time_0: f64, time_1: f64, parallel_threshold: usize) -> Self {
+        let mut objects: Vec<BuildObject> = list
+            .objects
+            .par_iter()
+            .enumerate()
+            .map(|(index, object)| {
+                let bounds = object.bounding_box(time_0, time_1);
+                if bounds.is_none() {
+                    eprintln!("No bounding box in bvh_node constructor.");
+                }
+                let bounds = bounds.unwrap_or(AABB::default());
+                BuildObject {
+                    index,
+                    object: object.clone(),
+                    centroid: 0.5 * (bounds.min() + bounds.max()),
+                }
+            })
+            .collect();
+        BvhNode::new(&mut objects, time_0, time_1, parallel_threshold)
     }
+    // The split axis depends only on the input, and rayon's sort is stable, so the tree
+    // comes out the same no matter how the work is scheduled.
     fn new(
-        objects: &Vec<Arc<dyn Hittable>>,
-        start: usize,
-        end: usize,
+        objects: &mut [BuildObject],
         time_0: f64,
         time_1: f64,
+        parallel_threshold: usize,
     ) -> Self {
-        let mut objects = objects.clone();
-        let axis = random_int_in_range(0, 3);
-        let comparator = if axis == 0 {
-            box_x_compare
-        } else if axis == 1 {
-            box_y_compare
-        } else {
-            box_z_compare
-        };
+        let parallel = objects.len() >= parallel_threshold;
+        let axis = longest_axis(centroid_bounds(objects, parallel));
+        let comparator = |a: &BuildObject, b: &BuildObject| centroid_compare(a, b, axis);
 
-        let object_span = end - start;
-
-        let (left, right): (Arc<dyn Hittable>, Arc<dyn Hittable>) = if object_span == 1 {
-            let single_span = objects[start].clone();
-            (single_span.clone(), single_span.clone())
-        } else if object_span == 2 {
-            match comparator(&objects[start], &objects[start + 1]) {
-                Ordering::Greater => (objects[start].clone(), objects[start + 1].clone()),
-                _ => (objects[start + 1].clone(), objects[start].clone()),
+        let (left, right) = if objects.len() == 1 {
+            (objects[0].leaf(), objects[0].leaf())
+        } else if objects.len() == 2 {
+            match comparator(&objects[0], &objects[1]) {
+                Ordering::Greater => (objects[0].leaf(), objects[1].leaf()),
+                _ => (objects[1].leaf(), objects[0].leaf()),
             }
         } else {
-            // TODO: does this work as expected?
-            objects[start..end].sort_by(comparator);
+            if parallel {
+                objects.par_sort_by(comparator);
+            } else {
+                objects.sort_by(comparator);
+            }
 
-            let mid = start + object_span / 2;
+            let (left_objects, right_objects) = objects.split_at_mut(objects.len() / 2);
+            let (left, right) = if parallel {
+                rayon::join(
+                    || BvhNode::new(left_objects, time_0, time_1, parallel_threshold),
+                    || BvhNode::new(right_objects, time_0, time_1, parallel_threshold),
+                )
+            } else {
+                (
+                    BvhNode::new(left_objects, time_0, time_1, parallel_threshold),
+                    BvhNode::new(right_objects, time_0, time_1, parallel_threshold),
+                )
+            };
             (
-                Arc::new(BvhNode::new(&objects, start, mid, time_0, time_1)),
-                Arc::new(BvhNode::new(&objects, mid, end, time_0, time_1)),
+                BvhChild::Node(Box::new(left)),
+                BvhChild::Node(Box::new(right)),
             )
         };
 
-        let box_left = left.bounding_box(time_0, time_1);
-        let box_right = right.bounding_box(time_0, time_1);
-
-        if box_left.is_none() || box_right.is_none() {
-            eprintln!("No bounding box in BvnNode constructor")
-        }
-
-        let bounds = box_left
-            .unwrap_or(AABB::default())
-            .combine(box_right.unwrap_or(AABB::default()));
-
+        let bounds = combined_bounds(&left, &right, time_0, time_1);
         Self {
             left,
             right,
             bounds,
         }
     }
+
+    // Swap in the objects from list (which must have the same length and order as the list
+    // this tree was built from, e.g. the same scene at the next animation frame) and
+    // recompute the bounds bottom-up without changing the tree's topology.
+    pub fn refit(&mut self, list: &HittableList, time_0: f64, time_1: f64) {
+        self.refit_objects(&list.objects, time_0, time_1);
+    }
+
+    fn refit_objects(&mut self, objects: &[Arc<dyn Hittable>], time_0: f64, time_1: f64) {
+        for child in [&mut self.left, &mut self.right].iter_mut() {
+            match child {
+                BvhChild::Node(node) => node.refit_objects(objects, time_0, time_1),
+                BvhChild::Leaf(index, object) => *object = objects[*index].clone(),
+            }
+        }
+        self.bounds = combined_bounds(&self.left, &self.right, time_0, time_1);
+    }
+
+    // Expected cost of tracing a random ray that hits the root box, according to the surface
+    // area heuristic. Lower is better; refitting after large movements makes it grow.
+    pub fn sah_cost(&self, time_0: f64, time_1: f64) -> f64 {
+        let root_area = self.bounds.surface_area();
+        if root_area <= 0. {
+            return 0.;
+        }
+        self.unnormalized_sah_cost(time_0, time_1) / root_area
+    }
+
+    fn unnormalized_sah_cost(&self, time_0: f64, time_1: f64) -> f64 {
+        let mut cost = TRAVERSAL_COST * self.bounds.surface_area();
+        for child in [&self.left, &self.right].iter() {
+            cost += match child {
+                BvhChild::Node(node) => node.unnormalized_sah_cost(time_0, time_1),
+                BvhChild::Leaf(_, object) => {
+                    INTERSECTION_COST
+                        * object
+                            .bounding_box(time_0, time_1)
+                            .map_or(0., |b| b.surface_area())
+                }
+            };
+        }
+        cost
+    }
 }
 
-fn box_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>, axis: usize) -> Ordering {
-    let box_a = a.bounding_box(0., 0.);
-    let box_b = b.bounding_box(0., 0.);
+// Packet traversal: every node is fetched once for all of the packet's rays, and only the
+// rays whose current closest hit is still beyond the node's box continue into it.
+impl BvhNode {
+    pub fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: f64,
+        t_max: f64,
+    ) -> [Option<HitRecord>; PACKET_SIZE] {
+        let mut closest_so_far = [t_max; PACKET_SIZE];
+        let mut hits: [Option<HitRecord>; PACKET_SIZE] = Default::default();
+        self.traverse_packet(
+            packet,
+            &PacketData::new(packet),
+            packet.active,
+            t_min,
+            &mut closest_so_far,
+            &mut hits,
+        );
+        hits
+    }
 
-    if box_a.is_none() || box_b.is_none() {
-        eprintln!("No bounding box in bvh_node constructor.");
+    // Any-hit query for shadow rays; returns a mask of the rays that hit something before
+    // their t_max. Rays drop out of the packet as soon as they are known to be blocked.
+    pub fn occluded_packet(&self, packet: &RayPacket, t_min: f64, t_max: [f64; PACKET_SIZE]) -> u8 {
+        self.traverse_occluded(
+            packet,
+            &PacketData::new(packet),
+            packet.active,
+            t_min,
+            &t_max,
+        )
     }
-    let box_a = box_a.unwrap_or(AABB::default());
-    let box_b = box_b.unwrap_or(AABB::default());
 
-    box_a.min().data[axis]
-        .partial_cmp(&box_b.min().data[axis])
-        .unwrap_or(Equal)
+    // single-object nodes store the same leaf on both sides; it only needs visiting once
+    fn distinct_children(&self) -> ([&BvhChild; 2], usize) {
+        let duplicate_leaf = match (&self.left, &self.right) {
+            (BvhChild::Leaf(a, _), BvhChild::Leaf(b, _)) => a == b,
+            _ => false,
+        };
+        let count = if duplicate_leaf { 1 } else { 2 };
+        ([&self.left, &self.right], count)
+    }
+
+    fn traverse_packet(
+        &self,
+        packet: &RayPacket,
+        data: &PacketData,
+        mask: u8,
+        t_min: f64,
+        t_max: &mut [f64; PACKET_SIZE],
+        hits: &mut [Option<HitRecord>; PACKET_SIZE],
+    ) {
+        let entering = data.hit_box(&self.bounds, mask, t_min, t_max);
+        if entering == 0 {
+            return;
+        }
+
+        let (children, count) = self.distinct_children();
+        for child in &children[..count] {
+            match child {
+                BvhChild::Node(node) => {
+                    node.traverse_packet(packet, data, entering, t_min, t_max, hits)
+                }
+                BvhChild::Leaf(_, object) => {
+                    let mut remaining = entering;
+                    while remaining != 0 {
+                        let i = remaining.trailing_zeros() as usize;
+                        remaining &= remaining - 1;
+                        if let Some(hit) = object.hit(packet.rays[i], t_min, t_max[i]) {
+                            t_max[i] = hit.t;
+                            hits[i] = Some(hit);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // returns the rays in mask that are blocked by something in this subtree
+    fn traverse_occluded(
+        &self,
+        packet: &RayPacket,
+        data: &PacketData,
+        mask: u8,
+        t_min: f64,
+        t_max: &[f64; PACKET_SIZE],
+    ) -> u8 {
+        let mut entering = data.hit_box(&self.bounds, mask, t_min, t_max);
+        let mut occluded = 0;
+        let (children, count) = self.distinct_children();
+        for child in &children[..count] {
+            if entering == 0 {
+                break;
+            }
+            match child {
+                BvhChild::Node(node) => {
+                    let blocked = node.traverse_occluded(packet, data, entering, t_min, t_max);
+                    occluded |= blocked;
+                    entering &= !blocked;
+                }
+                BvhChild::Leaf(_, object) => {
+                    let mut remaining = entering;
+                    while remaining != 0 {
+                        let i = remaining.trailing_zeros() as usize;
+                        remaining &= remaining - 1;
+                        if object.hit(packet.rays[i], t_min, t_max[i]).is_some() {
+                            occluded |= 1 << i;
+                            entering &= !(1 << i);
+                        }
+                    }
+                }
+            }
+        }
+        occluded
+    }
+}
+
+// Per-packet values shared by every box test, stored as one array per axis so that the
+// loops over the packet's rays compile to SIMD
+struct PacketData {
+    origin: [[f64; PACKET_SIZE]; 3],
+    inv_direction: [[f64; PACKET_SIZE]; 3],
+}
+
+impl PacketData {
+    fn new(packet: &RayPacket) -> Self {
+        let mut data = Self {
+            origin: [[0.; PACKET_SIZE]; 3],
+            inv_direction: [[0.; PACKET_SIZE]; 3],
+        };
+        for (i, r) in packet.rays.iter().enumerate() {
+            for axis in 0..3 {
+                data.origin[axis][i] = r.origin()[axis];
+                data.inv_direction[axis][i] = 1. / r.direction()[axis];
+            }
+        }
+        data
+    }
+
+    // Slab test of every ray in the packet against bounds at once. Returns the rays in mask
+    // that enter the box before their t_max.
+    fn hit_box(&self, bounds: &AABB, mask: u8, t_min: f64, t_max: &[f64; PACKET_SIZE]) -> u8 {
+        let mut t_near = [t_min; PACKET_SIZE];
+        let mut t_far = *t_max;
+        for axis in 0..3 {
+            let (min, max) = (bounds.min()[axis], bounds.max()[axis]);
+            let (origin, inv_direction) = (&self.origin[axis], &self.inv_direction[axis]);
+            for i in 0..PACKET_SIZE {
+                let t0 = (min - origin[i]) * inv_direction[i];
+                let t1 = (max - origin[i]) * inv_direction[i];
+                let (t0, t1) = if inv_direction[i] < 0. {
+                    (t1, t0)
+                } else {
+                    (t0, t1)
+                };
+                t_near[i] = if t0 > t_near[i] { t0 } else { t_near[i] };
+                t_far[i] = if t1 < t_far[i] { t1 } else { t_far[i] };
+            }
+        }
+        let mut hit = 0;
+        for i in 0..PACKET_SIZE {
+            if t_near[i] < t_far[i] {
+                hit |= 1 << i;
+            }
+        }
+        hit & mask
+    }
+}
+
+fn combined_bounds(left: &BvhChild, right: &BvhChild, time_0: f64, time_1: f64) -> AABB {
+    let box_left = left.bounding_box(time_0, time_1);
+    let box_right = right.bounding_box(time_0, time_1);
+
+    if box_left.is_none() || box_right.is_none() {
+        eprintln!("No bounding box in BvnNode constructor")
+    }
+
+    box_left
+        .unwrap_or(AABB::default())
+        .combine(box_right.unwrap_or(AABB::default()))
 }
 
-fn box_x_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>) -> Ordering {
-    box_compare(a, b, 0)
+struct BuildObject {
+    index: usize,
+    object: Arc<dyn Hittable>,
+    centroid: Vec3,
 }
 
-fn box_y_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>) -> Ordering {
-    box_compare(a, b, 1)
+impl BuildObject {
+    fn leaf(&self) -> BvhChild {
+        BvhChild::Leaf(self.index, self.object.clone())
+    }
 }
 
-fn box_z_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>) -> Ordering {
-    box_compare(a, b, 2)
+fn centroid_bounds(objects: &[BuildObject], parallel: bool) -> AABB {
+    let point_box = |o: &BuildObject| AABB::new(o.centroid, o.centroid);
+    let combined = if parallel {
+        objects
+            .par_iter()
+            .map(point_box)
+            .reduce_with(|a, b| a.combine(b))
+    } else {
+        objects
+            .iter()
+            .map(point_box)
+            .fold(None, |acc, b| match acc {
+                None => Some(b),
+                Some(a) => Some(a.combine(b)),
+            })
+    };
+    combined.unwrap_or(AABB::default())
+}
+
+fn longest_axis(bounds: AABB) -> usize {
+    let extent = bounds.max() - bounds.min();
+    if extent.x() >= extent.y() && extent.x() >= extent.z() {
+        0
+    } else if extent.y() >= extent.z() {
+        1
+    } else {
+        2
+    }
+}
+
+fn centroid_compare(a: &BuildObject, b: &BuildObject, axis: usize) -> Ordering {
+    a.centroid[axis]
+        .partial_cmp(&b.centroid[axis])
+        .unwrap_or(Equal)
 }
 
 impl Hittable for BvhNode {
@@ -125,3 +420,256 @@ impl Hittable for BvhNode {
         return Some(self.bounds);
     }
 }
+
+// A BVH for animated scenes. Each frame the tree is refit to the moved objects, which is
+// much cheaper than rebuilding it, but the tree degrades as objects drift away from the
+// positions it was built for. Once the SAH cost has grown by more than max_degradation
+// times the cost of the last full build, the tree is rebuilt from scratch instead.
+#[derive(Clone, Debug)]
+pub struct DynamicBvh {
+    root: BvhNode,
+    num_objects: usize,
+    build_cost: f64,
+    max_degradation: f64,
+}
+
+impl DynamicBvh {
+    pub fn new(list: &HittableList, time_0: f64, time_1: f64, max_degradation: f64) -> Self {
+        let root = BvhNode::new_from_hittable(list, time_0, time_1);
+        let build_cost = root.sah_cost(time_0, time_1);
+        Self {
+            root,
+            num_objects: list.objects.len(),
+            build_cost,
+            max_degradation,
+        }
+    }
+
+    // returns true if the tree had to be rebuilt
+    pub fn update(&mut self, list: &HittableList, time_0: f64, time_1: f64) -> bool {
+        if list.objects.len() != self.num_objects {
+            self.rebuild(list, time_0, time_1);
+            return true;
+        }
+        self.root.refit(list, time_0, time_1);
+        if self.root.sah_cost(time_0, time_1) > self.max_degradation * self.build_cost {
+            self.rebuild(list, time_0, time_1);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn rebuild(&mut self, list: &HittableList, time_0: f64, time_1: f64) {
+        self.root = BvhNode::new_from_hittable(list, time_0, time_1);
+        self.num_objects = list.objects.len();
+        self.build_cost = self.root.sah_cost(time_0, time_1);
+    }
+
+    pub fn root(&self) -> &BvhNode {
+        &self.root
+    }
+}
+
+impl Hittable for DynamicBvh {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.root.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.root.bounding_box(t0, t1)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{diffuse::Lambertian, material::Material, sphere::Sphere, texture::SolidColor};
+
+    // spheres scattered through a 100-unit cube; offset moves every seventh one a bit further
+    pub(crate) fn spheres(count: usize, offset: f64) -> HittableList {
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new(Arc::new(
+            SolidColor::new_from_rgb(1., 1., 1.),
+        )));
+        let mut list = HittableList::new();
+        for i in 0..count {
+            let i = i as f64;
+            let center = Vec3::new(
+                50. * (7.3 * i).sin() + offset * (i % 7.),
+                50. * (3.1 * i).cos(),
+                50. * (1.7 * i).sin(),
+            );
+            list.add(Arc::new(Sphere::new(center, 0.5, material.clone())));
+        }
+        list
+    }
+
+    pub(crate) fn rays(count: usize) -> impl Iterator<Item = Ray> {
+        (0..count).map(|i| {
+            let i = i as f64;
+            let origin = 60. * Vec3::new((0.37 * i).sin(), (0.71 * i).cos(), (0.13 * i).sin());
+            // aim near a point inside the cube so that most rays hit something
+            let target = 30. * Vec3::new((1.9 * i).cos(), (2.3 * i).sin(), (0.5 * i).cos());
+            Ray::new(origin, target - origin, 0.)
+        })
+    }
+
+    pub(crate) fn closest(object: &dyn Hittable, r: Ray) -> Option<f64> {
+        object.hit(r, 0.001, f64::INFINITY).map(|hit| hit.t)
+    }
+
+    // the brute-force answer
+    fn closest_in_list(list: &HittableList, r: Ray) -> Option<f64> {
+        list.hit(r, 0.001, f64::INFINITY).map(|hit| hit.t)
+    }
+
+    #[test]
+    fn hits_match_the_object_list() {
+        let list = spheres(2000, 0.);
+        let bvh = BvhNode::new_from_hittable(&list, 0., 1.);
+        let mut hits = 0;
+        for r in rays(2000) {
+            let expected = closest_in_list(&list, r);
+            assert_eq!(closest(&bvh, r), expected);
+            hits += expected.is_some() as usize;
+        }
+        assert!(hits > 0);
+    }
+
+    // the tree as text: each node's bounds followed by its children, leaves by index
+    fn describe(node: &BvhNode) -> String {
+        let child = |child: &BvhChild| match child {
+            BvhChild::Node(node) => describe(node),
+            BvhChild::Leaf(index, _) => index.to_string(),
+        };
+        format!(
+            "({:?} {} {})",
+            node.bounds,
+            child(&node.left),
+            child(&node.right)
+        )
+    }
+
+    #[test]
+    fn parallel_build_matches_a_sequential_build() {
+        // enough objects for the top levels to be split across threads
+        let list = spheres(3 * PARALLEL_BUILD_THRESHOLD, 0.);
+        let parallel = BvhNode::new_from_hittable(&list, 0., 1.);
+        let sequential = BvhNode::build(&list, 0., 1., usize::MAX);
+        assert_eq!(describe(&parallel), describe(&sequential));
+        for r in rays(2000) {
+            assert_eq!(closest(&parallel, r), closest_in_list(&list, r));
+        }
+    }
+
+    #[test]
+    fn refit_tree_matches_the_moved_objects() {
+        let mut bvh = BvhNode::new_from_hittable(&spheres(2000, 0.), 0., 1.);
+        let moved = spheres(2000, 3.);
+        bvh.refit(&moved, 0., 1.);
+        for r in rays(2000) {
+            assert_eq!(closest(&bvh, r), closest_in_list(&moved, r));
+        }
+    }
+
+    #[test]
+    fn packets_match_single_rays() {
+        let bvh = BvhNode::new_from_hittable(&spheres(2000, 0.), 0., 1.);
+        let rays: Vec<Ray> = rays(8 * 300).collect();
+        for (chunk, size) in rays.chunks(PACKET_SIZE).zip((1..=PACKET_SIZE).cycle()) {
+            // partly filled packets leave their last slots inactive
+            let packet = RayPacket::new(&chunk[..size]);
+            let hits = bvh.hit_packet(&packet, 0.001, f64::INFINITY);
+            for (i, hit) in hits.iter().enumerate() {
+                let expected = if i < size {
+                    closest(&bvh, chunk[i])
+                } else {
+                    None
+                };
+                assert_eq!(hit.as_ref().map(|hit| hit.t), expected);
+            }
+        }
+    }
+
+    // shadow rays ending just short of or just past the closest hit, or at t = 1 on a miss
+    pub(crate) fn shadow_t_max(
+        object: &dyn Hittable,
+        chunk: &[Ray],
+        n: usize,
+    ) -> [f64; PACKET_SIZE] {
+        let mut t_max = [1.; PACKET_SIZE];
+        for (i, &r) in chunk.iter().enumerate() {
+            if let Some(t) = closest(object, r) {
+                t_max[i] = if (n + i) % 2 == 0 { 0.99 * t } else { 1.01 * t };
+            }
+        }
+        t_max
+    }
+
+    #[test]
+    fn occluded_packets_match_single_rays() {
+        let bvh = BvhNode::new_from_hittable(&spheres(2000, 0.), 0., 1.);
+        let rays: Vec<Ray> = rays(8 * 300).collect();
+        let mut blocked = 0;
+        for (n, chunk) in rays.chunks(PACKET_SIZE).enumerate() {
+            let size = 1 + n % PACKET_SIZE;
+            let packet = RayPacket::new(&chunk[..size]);
+            let t_max = shadow_t_max(&bvh, chunk, n);
+            let occluded = bvh.occluded_packet(&packet, 0.001, t_max);
+            for (i, &r) in chunk.iter().enumerate() {
+                let expected = i < size && bvh.hit(r, 0.001, t_max[i]).is_some();
+                assert_eq!(occluded & (1 << i) != 0, expected);
+                blocked += expected as usize;
+            }
+        }
+        assert!(blocked > 0);
+    }
+
+    #[test]
+    fn packets_sharing_an_origin() {
+        // like primary rays, which all start at the camera
+        let bvh = BvhNode::new_from_hittable(&spheres(2000, 0.), 0., 1.);
+        let origin = Vec3::new(0., 0., -80.);
+        for i in 0..200 {
+            let mut packet = [Ray::default(); PACKET_SIZE];
+            for (j, r) in packet.iter_mut().enumerate() {
+                let (x, y) = ((i % 20) as f64 - 10. + 0.1 * j as f64, (i / 20) as f64 - 5.);
+                *r = Ray::new(origin, Vec3::new(0.05 * x, 0.05 * y, 1.), 0.);
+            }
+            let hits = bvh.hit_packet(&RayPacket::new(&packet), 0.001, f64::INFINITY);
+            for (hit, &r) in hits.iter().zip(packet.iter()) {
+                assert_eq!(hit.as_ref().map(|hit| hit.t), closest(&bvh, r));
+            }
+        }
+    }
+
+    #[test]
+    fn sah_cost_of_a_single_object() {
+        // one leaf, duplicated, whose box is the root box
+        let bvh = BvhNode::new_from_hittable(&spheres(1, 0.), 0., 1.);
+        let expected = TRAVERSAL_COST + 2. * INTERSECTION_COST;
+        assert!((bvh.sah_cost(0., 1.) - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn dynamic_bvh_rebuilds_once_refitting_degrades_it() {
+        let list = spheres(500, 0.);
+        let mut dynamic = DynamicBvh::new(&list, 0., 1., 1.5);
+        // nothing moved, so refitting keeps the cost
+        assert!(!dynamic.update(&list, 0., 1.));
+
+        // spreading the spheres out makes the refit boxes overlap a lot more
+        let spread = spheres(500, 40.);
+        let mut refit = dynamic.root().clone();
+        refit.refit(&spread, 0., 1.);
+        let rebuilt = BvhNode::new_from_hittable(&spread, 0., 1.);
+        assert!(refit.sah_cost(0., 1.) > 1.5 * rebuilt.sah_cost(0., 1.));
+        assert!(dynamic.update(&spread, 0., 1.));
+        for r in rays(1000) {
+            assert_eq!(closest(&dynamic, r), closest_in_list(&spread, r));
+        }
+
+        // a different number of objects always means a rebuild
+        assert!(dynamic.update(&spheres(10, 0.), 0., 1.));
+    }
+}
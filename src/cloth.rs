@@ -0,0 +1,162 @@
+use crate::{
+    hittable::HitRecord, material::Material, onb::Onb, ray::Ray, texture::Texture,
+    utils::random_in_01, vec3::Vec3,
+};
+use std::{f64::consts::PI, sync::Arc};
+
+// Fabric: a diffuse base under the "Charlie" sheen of Estevez and Kulla 2017, with the
+// visibility term of Neubelt and Pettineo 2013. The sheen comes from fibres sticking out of
+// the surface and gives velvet and cotton their bright rims at grazing angles. Roughness
+// (first channel of its texture) spreads the rim light over more of the surface.
+#[derive(Clone, Debug)]
+pub struct Cloth {
+    base_color: Arc<dyn Texture>,
+    sheen_color: Arc<dyn Texture>,
+    roughness: Arc<dyn Texture>,
+}
+
+// half of all directions are sampled for the base and half for the sheen
+const DIFFUSE_PROBABILITY: f64 = 0.5;
+
+impl Cloth {
+    pub fn new(
+        base_color: Arc<dyn Texture>,
+        sheen_color: Arc<dyn Texture>,
+        roughness: Arc<dyn Texture>,
+    ) -> Self {
+        Self {
+            base_color,
+            sheen_color,
+            roughness,
+        }
+    }
+
+    fn local_frame(&self, r_in: Ray, hit: &HitRecord) -> (Onb, Vec3) {
+        let onb = Onb::build_from_w(hit.normal);
+        let wo = onb.to_local(-r_in.direction().norm());
+        (onb, wo)
+    }
+
+    // (f * cos, pdf) for light arriving from wi and leaving along wo
+    fn eval_and_pdf(&self, hit: &HitRecord, wo: Vec3, wi: Vec3) -> (Vec3, f64) {
+        if wo.z() <= 0. || wi.z() <= 0. {
+            return (Vec3::default(), 0.);
+        }
+        let base_color = self.base_color.value(hit.u, hit.v, hit.p);
+        let sheen_color = self.sheen_color.value(hit.u, hit.v, hit.p);
+        let alpha = self
+            .roughness
+            .value(hit.u, hit.v, hit.p)
+            .x()
+            .clamp(0.01, 1.)
+            .powi(2);
+
+        let h = (wo + wi).norm();
+        let sin_h = (1. - h.z() * h.z()).max(0.).sqrt();
+        let d = (2. + 1. / alpha) * sin_h.powf(1. / alpha) / (2. * PI);
+        let v = 1. / (4. * (wi.z() + wo.z() - wi.z() * wo.z()));
+        let f = base_color / PI + sheen_color * (d * v);
+
+        let pdf = DIFFUSE_PROBABILITY * wi.z() / PI + (1. - DIFFUSE_PROBABILITY) / (2. * PI);
+        (f * wi.z(), pdf)
+    }
+}
+
+impl Material for Cloth {
+    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3, bool)> {
+        let (onb, wo) = self.local_frame(r_in, hit);
+        if wo.z() <= 0. {
+            return None;
+        }
+        let phi = 2. * PI * random_in_01();
+        let u = random_in_01();
+        // cosine-weighted for the base, uniform over the hemisphere for the broad sheen
+        let cos_theta = if random_in_01() <= DIFFUSE_PROBABILITY {
+            (1. - u).max(0.).sqrt()
+        } else {
+            u
+        };
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let wi = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+
+        let (f_cos, pdf) = self.eval_and_pdf(hit, wo, wi);
+        if pdf <= 0. {
+            return None;
+        }
+        Some((
+            Ray::new(hit.p, onb.local(wi), r_in.time()),
+            f_cos / pdf,
+            false,
+        ))
+    }
+
+    fn eval(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        let (onb, wo) = self.local_frame(r_in, hit);
+        self.eval_and_pdf(hit, wo, onb.to_local(direction.norm())).0
+    }
+
+    fn scattering_pdf(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        let (onb, wo) = self.local_frame(r_in, hit);
+        self.eval_and_pdf(hit, wo, onb.to_local(direction.norm())).1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::tests::assert_consistent, texture::SolidColor};
+
+    fn cloth(roughness: f64) -> Arc<dyn Material> {
+        Arc::new(Cloth::new(
+            Arc::new(SolidColor::new_from_rgb(0.6, 0.2, 0.1)),
+            Arc::new(SolidColor::new_from_rgb(1., 0.9, 0.8)),
+            Arc::new(SolidColor::new_from_rgb(roughness, roughness, roughness)),
+        ))
+    }
+
+    #[test]
+    fn eval_matches_scatter() {
+        for &roughness in &[0.3, 0.8] {
+            for &incoming in &[
+                Vec3::new(0., 0., -1.),
+                Vec3::new(0.5, 0.2, -0.8).norm(),
+                Vec3::new(-0.9, 0., -0.2).norm(),
+            ] {
+                assert_consistent(cloth(roughness), incoming);
+            }
+        }
+    }
+
+    #[test]
+    fn sheen_brightens_grazing_views() {
+        let material = cloth(0.5);
+        let eval_at = |incoming: Vec3| {
+            let r_in = Ray::new(-incoming, incoming, 0.);
+            let hit = HitRecord::new(
+                1.,
+                Vec3::default(),
+                Vec3::new(0., 0., 1.),
+                r_in,
+                material.clone(),
+            );
+            // light from straight above, per unit of cosine
+            material.eval(r_in, &hit, Vec3::new(0., 0., 1.))
+        };
+        let head_on = eval_at(Vec3::new(0., 0., -1.));
+        let grazing = eval_at(Vec3::new(0.95, 0., -0.1).norm());
+        assert!(grazing.x() > head_on.x());
+        // nothing is reflected below the surface
+        let r_in = Ray::new(Vec3::new(0., 0., 1.), Vec3::new(0., 0., -1.), 0.);
+        let hit = HitRecord::new(
+            1.,
+            Vec3::default(),
+            Vec3::new(0., 0., 1.),
+            r_in,
+            material.clone(),
+        );
+        assert_eq!(
+            material.eval(r_in, &hit, Vec3::new(0., 0.3, -1.).norm()),
+            Vec3::default()
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use crate::{
+    consts::white,
+    hittable::HitRecord,
+    material::Material,
+    medium::HomogeneousMedium,
+    microfacet::fresnel_dielectric,
+    ray::Ray,
+    utils::{random_in_01, reflect},
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+// after this many reflections between the coat and the base the light is considered absorbed
+const MAX_INTERNAL_BOUNCES: usize = 16;
+
+// A thin, smooth dielectric layer (varnish, lacquer, car clear coat) over any other
+// material. Light either reflects off the coat or refracts into it, scatters off the base,
+// and then has to get back out through the coat; anything the coat reflects back down
+// meets the base again. The layer is infinitely thin, so all of this happens at the hit point.
+#[derive(Clone, Debug)]
+pub struct Coated {
+    base: Arc<dyn Material>,
+    refractive_index: f64,
+    // transmittance of one straight pass through the coat
+    color: Vec3,
+}
+
+impl Coated {
+    pub fn new(base: Arc<dyn Material>, refractive_index: f64) -> Self {
+        Self {
+            base,
+            refractive_index,
+            color: white(),
+        }
+    }
+    // A tinted coat, e.g. candy paint. Slanted passes travel further through the coat and
+    // are tinted more strongly.
+    pub fn with_color(mut self, color: Vec3) -> Self {
+        self.color = color;
+        self
+    }
+
+    // transmittance of a pass through the coat at the given cosine to the normal
+    fn pass_transmittance(&self, cos_theta: f64) -> Vec3 {
+        let exponent = 1. / cos_theta.abs().max(1e-4);
+        Vec3::new(
+            self.color.x().powf(exponent),
+            self.color.y().powf(exponent),
+            self.color.z().powf(exponent),
+        )
+    }
+
+    // The light that refracts into the coat, scatters off the base once and refracts
+    // straight back out towards direction. Returns the ray the base sees, the direction
+    // leaving the base, the tint of both passes through the coat, and the factor eval and
+    // scattering_pdf share: transmission through the coat both ways and the change in solid
+    // angle from refraction.
+    fn single_scattering(
+        &self,
+        r_in: Ray,
+        hit: &HitRecord,
+        direction: Vec3,
+    ) -> Option<(Ray, Vec3, Vec3, f64)> {
+        let unit_direction = r_in.direction().norm();
+        let wi = direction.norm();
+        let cos_o = (-unit_direction).dot(hit.normal).min(1.);
+        let cos_i = wi.dot(hit.normal).min(1.);
+        if cos_o <= 0. || cos_i <= 0. {
+            return None;
+        }
+        let down = refract(unit_direction, hit.normal, 1. / self.refractive_index);
+        let up = -refract(-wi, hit.normal, 1. / self.refractive_index);
+        let cos_up = up.dot(hit.normal);
+        if cos_up <= 0. {
+            return None;
+        }
+        let transmission = (1. - fresnel_dielectric(cos_o, self.refractive_index))
+            * (1. - fresnel_dielectric(cos_up, 1. / self.refractive_index));
+        let factor =
+            transmission * cos_i / (self.refractive_index * self.refractive_index * cos_up);
+        let tint = self.pass_transmittance(down.dot(hit.normal)) * self.pass_transmittance(cos_up);
+        Some((Ray::new(hit.p, down, r_in.time()), up, tint, factor))
+    }
+}
+
+// Snell's law for unit direction uv through a surface with normal n facing against uv
+fn refract(uv: Vec3, n: Vec3, eta_i_over_eta_t: f64) -> Vec3 {
+    let cos_theta = (-uv).dot(n).min(1.);
+    let r_out_parallel = eta_i_over_eta_t * (uv + cos_theta * n);
+    let r_out_perp = -(1. - r_out_parallel.length_squared()).max(0.).sqrt() * n;
+    r_out_parallel + r_out_perp
+}
+
+impl Material for Coated {
+    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3, bool)> {
+        let unit_direction = r_in.direction().norm();
+        let cos_theta = (-unit_direction).dot(hit.normal).min(1.);
+        if random_in_01() < fresnel_dielectric(cos_theta, self.refractive_index) {
+            let reflected = reflect(unit_direction, hit.normal);
+            return Some((Ray::new(hit.p, reflected, r_in.time()), white(), true));
+        }
+
+        let mut direction = refract(unit_direction, hit.normal, 1. / self.refractive_index);
+        let mut attenuation = self.pass_transmittance(direction.dot(hit.normal));
+        for bounce in 0..MAX_INTERNAL_BOUNCES {
+            let (scattered, base_attenuation, base_specular) = self
+                .base
+                .scatter(Ray::new(hit.p, direction, r_in.time()), hit)?;
+            attenuation = attenuation * base_attenuation;
+            let up = scattered.direction().norm();
+            let cos_up = up.dot(hit.normal);
+            if cos_up <= 0. {
+                // the base let the light through; the coat only covers its outside
+                return Some((scattered, attenuation, true));
+            }
+            attenuation = attenuation * self.pass_transmittance(cos_up);
+
+            // total internal reflection makes the Fresnel term 1
+            if random_in_01() >= fresnel_dielectric(cos_up, 1. / self.refractive_index) {
+                let out = refract(up, -hit.normal, self.refractive_index);
+                // only light that left after one bounce off the base is covered by eval
+                let is_specular = base_specular || bounce > 0;
+                return Some((Ray::new(hit.p, out, r_in.time()), attenuation, is_specular));
+            }
+            direction = reflect(up, -hit.normal);
+            attenuation = attenuation * self.pass_transmittance(cos_up);
+        }
+        None
+    }
+
+    // Reflection off the coat is specular, and light that bounces between the coat and the
+    // base more than once is left to scatter(), so these only cover single scattering.
+    fn eval(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        match self.single_scattering(r_in, hit, direction) {
+            Some((base_ray, up, tint, factor)) => tint * self.base.eval(base_ray, hit, up) * factor,
+            None => Vec3::default(),
+        }
+    }
+
+    fn scattering_pdf(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        match self.single_scattering(r_in, hit, direction) {
+            Some((base_ray, up, _, factor)) => self.base.scattering_pdf(base_ray, hit, up) * factor,
+            None => 0.,
+        }
+    }
+
+    // the coat is infinitely thin, so the base's medium is right underneath it
+    fn interior_medium(&self) -> Option<HomogeneousMedium> {
+        self.base.interior_medium()
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
+        self.base.emitted(u, v, p)
+    }
+
+    fn emitted_spectral(&self, u: f64, v: f64, p: Vec3, lambda: f64) -> f64 {
+        self.base.emitted_spectral(u, v, p, lambda)
+    }
+
+    fn is_wavelength_dependent(&self) -> bool {
+        self.base.is_wavelength_dependent()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        conductor::Conductor, diffuse::Lambertian, material::tests::assert_consistent,
+        texture::SolidColor,
+    };
+
+    #[test]
+    fn clear_coat_reflects_by_fresnel_and_keeps_white_white() {
+        let base = Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            1., 1., 1.,
+        ))));
+        let material: Arc<dyn Material> = Arc::new(Coated::new(base, 1.5));
+        let incoming = Vec3::new(0., 0., -1.);
+        let r_in = Ray::new(-incoming, incoming, 0.);
+        let hit = HitRecord::new(1., Vec3::default(), Vec3::new(0., 0., 1.), r_in, material);
+        let samples = 100_000;
+        let mut mirrored = 0;
+        let mut total = Vec3::default();
+        for _ in 0..samples {
+            if let Some((scattered, attenuation, is_specular)) = hit.material.scatter(r_in, &hit) {
+                if (scattered.direction().norm() - Vec3::new(0., 0., 1.)).magnitude() < 1e-9 {
+                    assert!(is_specular);
+                    mirrored += 1;
+                }
+                total += attenuation;
+            }
+        }
+        // an index of 1.5 reflects 4% at normal incidence
+        assert!((mirrored as f64 / samples as f64 - 0.04).abs() < 0.003);
+        // nothing absorbs, so all the light gets back out apart from the few paths cut off
+        // after MAX_INTERNAL_BOUNCES
+        let mean = total / samples as f64;
+        for axis in 0..3 {
+            assert!(mean[axis] > 0.97 && mean[axis] < 1.01, "{:?}", mean);
+        }
+    }
+
+    #[test]
+    fn eval_matches_single_scattering() {
+        let bases: [Arc<dyn Material>; 2] = [
+            Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+                0.8, 0.5, 0.2,
+            )))),
+            Arc::new(Conductor::copper(0.6)),
+        ];
+        let incoming = [
+            Vec3::new(0., 0., -1.),
+            Vec3::new(0.6, 0.1, -0.8).norm(),
+            Vec3::new(-0.9, 0.2, -0.3).norm(),
+        ];
+        for base in bases.iter() {
+            let coated = Coated::new(base.clone(), 1.5).with_color(Vec3::new(0.9, 0.8, 0.6));
+            for &d in &incoming {
+                assert_consistent(Arc::new(coated.clone()), d);
+            }
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{
+    hittable::HitRecord,
+    material::Material,
+    microfacet::{
+        fresnel_conductor, ggx_d, roughness_to_alpha, sample_visible_normal, smith_g1, smith_g2,
+        MIN_ALPHA,
+    },
+    onb::Onb,
+    ray::Ray,
+    thin_film::ThinFilm,
+    utils::random_in_01,
+    vec3::Vec3,
+};
+
+// Rough metal using the GGX microfacet model with Smith masking-shadowing. Unlike Metal,
+// it conserves energy and is importance sampled from the visible normals. Separate u and v
+// roughness give brushed looks; u runs along the surface's dpdu tangent.
+#[derive(Clone, Debug, Default)]
+pub struct Conductor {
+    // complex refractive index eta + i*k for the red, green and blue channels
+    eta: Vec3,
+    k: Vec3,
+    alpha_u: f64,
+    alpha_v: f64,
+    // oxide layer or other coating, e.g. for heat-tinted titanium
+    film: Option<ThinFilm>,
+}
+
+impl Conductor {
+    pub fn new(eta: Vec3, k: Vec3, roughness_u: f64, roughness_v: f64) -> Self {
+        Self {
+            eta,
+            k,
+            alpha_u: roughness_to_alpha(roughness_u).max(MIN_ALPHA),
+            alpha_v: roughness_to_alpha(roughness_v).max(MIN_ALPHA),
+            film: None,
+        }
+    }
+    pub fn gold(roughness: f64) -> Self {
+        Self::new(
+            Vec3::new(0.18299, 0.42108, 1.37340),
+            Vec3::new(3.42420, 2.34590, 1.77040),
+            roughness,
+            roughness,
+        )
+    }
+    pub fn copper(roughness: f64) -> Self {
+        Self::new(
+            Vec3::new(0.27105, 0.67693, 1.31640),
+            Vec3::new(3.60920, 2.62480, 2.29210),
+            roughness,
+            roughness,
+        )
+    }
+    pub fn aluminium(roughness: f64) -> Self {
+        Self::new(
+            Vec3::new(1.34560, 0.96521, 0.61722),
+            Vec3::new(7.47460, 6.39950, 5.30310),
+            roughness,
+            roughness,
+        )
+    }
+    pub fn silver(roughness: f64) -> Self {
+        Self::new(
+            Vec3::new(0.15943, 0.14512, 0.13547),
+            Vec3::new(3.92910, 3.19000, 2.38080),
+            roughness,
+            roughness,
+        )
+    }
+    pub fn with_roughness(self, roughness_u: f64, roughness_v: f64) -> Self {
+        Self {
+            film: self.film.clone(),
+            ..Self::new(self.eta, self.k, roughness_u, roughness_v)
+        }
+    }
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+
+    // The index is only known for red, green and blue, taken to be at 650nm, 550nm and
+    // 450nm; interpolate between them for the film's spectral calculation
+    fn index_at(&self, lambda: f64) -> (f64, f64) {
+        let lerp = |c: Vec3| {
+            if lambda >= 550. {
+                let t = ((lambda - 550.) / 100.).min(1.);
+                (1. - t) * c.y() + t * c.x()
+            } else {
+                let t = ((550. - lambda) / 100.).min(1.);
+                (1. - t) * c.y() + t * c.z()
+            }
+        };
+        (lerp(self.eta), lerp(self.k))
+    }
+
+    // reflectance of the (possibly coated) metal for light arriving at cos_theta to the
+    // microfacet
+    fn fresnel(&self, cos_theta: f64, r_in: Ray, hit: &HitRecord) -> Vec3 {
+        match &self.film {
+            None => fresnel_conductor(cos_theta, self.eta, self.k),
+            Some(film) => {
+                let thickness = film.thickness(hit.u, hit.v, hit.p);
+                let substrate = |lambda| self.index_at(lambda);
+                match r_in.wavelength() {
+                    Some(lambda) => {
+                        let r = film.reflectance(cos_theta, thickness, 1., substrate, lambda);
+                        Vec3::new(r, r, r)
+                    }
+                    None => film.reflectance_rgb(cos_theta, thickness, 1., substrate),
+                }
+            }
+        }
+    }
+
+    fn is_smooth(&self) -> bool {
+        self.alpha_u <= MIN_ALPHA && self.alpha_v <= MIN_ALPHA
+    }
+
+    // (shading frame, outgoing direction in that frame)
+    fn local_frame(&self, r_in: Ray, hit: &HitRecord) -> (Onb, Vec3) {
+        let onb = Onb::build_from_w_and_u(hit.normal, hit.dpdu);
+        let wo = onb.to_local(-r_in.direction().norm());
+        (onb, wo)
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3, bool)> {
+        let (onb, wo) = self.local_frame(r_in, hit);
+        if wo.z() <= 0. {
+            return None;
+        }
+
+        if self.is_smooth() {
+            let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+            let attenuation = self.fresnel(wo.z(), r_in, hit);
+            return Some((
+                Ray::new(hit.p, onb.local(wi), r_in.time()),
+                attenuation,
+                true,
+            ));
+        }
+
+        let m = sample_visible_normal(
+            wo,
+            self.alpha_u,
+            self.alpha_v,
+            random_in_01(),
+            random_in_01(),
+        );
+        let wi = -wo + 2. * wo.dot(m) * m;
+        if wi.z() <= 0. {
+            return None;
+        }
+        // f * cos / pdf; D and most of the other terms cancel
+        let attenuation = self.fresnel(wo.dot(m), r_in, hit)
+            * (smith_g2(wo, wi, self.alpha_u, self.alpha_v)
+                / smith_g1(wo, self.alpha_u, self.alpha_v));
+        Some((
+            Ray::new(hit.p, onb.local(wi), r_in.time()),
+            attenuation,
+            false,
+        ))
+    }
+
+    fn eval(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        let (onb, wo) = self.local_frame(r_in, hit);
+        let wi = onb.to_local(direction.norm());
+        if self.is_smooth() || wo.z() <= 0. || wi.z() <= 0. {
+            return Vec3::default();
+        }
+        let m = (wo + wi).norm();
+        self.fresnel(wo.dot(m), r_in, hit)
+            * (ggx_d(m, self.alpha_u, self.alpha_v) * smith_g2(wo, wi, self.alpha_u, self.alpha_v)
+                / (4. * wo.z()))
+    }
+
+    fn scattering_pdf(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        let (onb, wo) = self.local_frame(r_in, hit);
+        let wi = onb.to_local(direction.norm());
+        if self.is_smooth() || wo.z() <= 0. || wi.z() <= 0. {
+            return 0.;
+        }
+        let m = (wo + wi).norm();
+        // visible normal pdf times the Jacobian of reflection, 1 / (4 wo.m)
+        smith_g1(wo, self.alpha_u, self.alpha_v) * ggx_d(m, self.alpha_u, self.alpha_v)
+            / (4. * wo.z())
+    }
+
+    fn is_wavelength_dependent(&self) -> bool {
+        self.film.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::tests::assert_consistent;
+    use std::sync::Arc;
+
+    #[test]
+    fn eval_matches_scatter() {
+        let incoming = [
+            Vec3::new(0., 0., -1.),
+            Vec3::new(0.6, 0.2, -0.7).norm(),
+            Vec3::new(-0.8, 0.4, -0.3).norm(),
+        ];
+        for &d in &incoming {
+            assert_consistent(Arc::new(Conductor::gold(0.5)), d);
+            // stretched along the tangent
+            assert_consistent(Arc::new(Conductor::copper(0.).with_roughness(0.7, 0.4)), d);
+        }
+    }
+
+    #[test]
+    fn smooth_conductors_are_specular() {
+        let material: Arc<dyn Material> = Arc::new(Conductor::silver(0.));
+        let incoming = Vec3::new(0.6, 0., -0.8);
+        let r_in = Ray::new(-incoming, incoming, 0.);
+        let hit = HitRecord::new(1., Vec3::default(), Vec3::new(0., 0., 1.), r_in, material);
+        let (scattered, _, is_specular) = hit.material.scatter(r_in, &hit).unwrap();
+        assert!(is_specular);
+        assert!((scattered.direction().norm() - Vec3::new(0.6, 0., 0.8)).magnitude() < 1e-9);
+        assert_eq!(
+            hit.material
+                .scattering_pdf(r_in, &hit, scattered.direction()),
+            0.
+        );
+    }
+}
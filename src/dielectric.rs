@@ -1,47 +1,216 @@
 use crate::{
+    consts::white,
     hittable::HitRecord,
     material::Material,
+    medium::HomogeneousMedium,
     ray::Ray,
+    spectrum::{sample_wavelength, wavelength_to_rgb_weight},
+    thin_film::ThinFilm,
     utils::{random_in_01, reflect},
     vec3::Vec3,
 };
 
-#[derive(Clone, Copy, Debug, PartialEq, Default)]
+// Wavelength-dependent refractive index models; wavelengths are in micrometres in the
+// formulas and nanometres in the API
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RefractiveIndex {
+    Constant(f64),
+    // n = a + b / λ²
+    Cauchy { a: f64, b: f64 },
+    // n² = 1 + Σ b_i λ² / (λ² - c_i)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+// Fraunhofer d line, where catalogue refractive indices are quoted
+const NOMINAL_WAVELENGTH: f64 = 587.6;
+
+impl RefractiveIndex {
+    pub fn at(&self, wavelength: f64) -> f64 {
+        let micrometres = wavelength / 1000.;
+        let l2 = micrometres * micrometres;
+        match *self {
+            RefractiveIndex::Constant(n) => n,
+            RefractiveIndex::Cauchy { a, b } => a + b / l2,
+            RefractiveIndex::Sellmeier { b, c } => {
+                (1. + b[0] * l2 / (l2 - c[0]) + b[1] * l2 / (l2 - c[1]) + b[2] * l2 / (l2 - c[2]))
+                    .sqrt()
+            }
+        }
+    }
+    pub fn nominal(&self) -> f64 {
+        self.at(NOMINAL_WAVELENGTH)
+    }
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, RefractiveIndex::Constant(_))
+    }
+
+    // Schott N-BK7 crown glass
+    pub fn bk7() -> Self {
+        RefractiveIndex::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+    pub fn fused_silica() -> Self {
+        RefractiveIndex::Sellmeier {
+            b: [0.6961663, 0.4079426, 0.8974794],
+            c: [0.00467914826, 0.0135120631, 97.9340025],
+        }
+    }
+    pub fn diamond() -> Self {
+        RefractiveIndex::Sellmeier {
+            b: [4.3356, 0.3306, 0.],
+            c: [0.011236, 0.030625, 0.],
+        }
+    }
+}
+
+impl Default for RefractiveIndex {
+    fn default() -> Self {
+        RefractiveIndex::Constant(1.)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct Dielectric {
-    refractive_index: f64,
+    refractive_index: RefractiveIndex,
+    // absorbing medium inside the object; clear glass if None
+    interior: Option<HomogeneousMedium>,
+    // iridescent coating, on both sides of the surface
+    film: Option<ThinFilm>,
 }
 
 impl Dielectric {
     pub fn new(refractive_index: f64) -> Self {
-        Self { refractive_index }
+        Self::dispersive(RefractiveIndex::Constant(refractive_index))
+    }
+    // Splits white light into its colors (prisms, diamonds). The first dispersive hit picks
+    // a single wavelength for the rest of the path.
+    pub fn dispersive(refractive_index: RefractiveIndex) -> Self {
+        Self {
+            refractive_index,
+            interior: None,
+            film: None,
+        }
+    }
+    // Tinted glass: absorption is the Beer–Lambert coefficient per unit distance
+    pub fn with_absorption(mut self, absorption: Vec3) -> Self {
+        self.interior = Some(HomogeneousMedium::new(absorption));
+        self
+    }
+    // Tinted glass which turns white light into color after it travels distance inside
+    pub fn with_transmittance(mut self, color: Vec3, distance: f64) -> Self {
+        self.interior = Some(HomogeneousMedium::from_transmittance(color, distance));
+        self
+    }
+    // any medium, including scattering ones (see Subsurface)
+    pub fn with_medium(mut self, medium: HomogeneousMedium) -> Self {
+        self.interior = Some(medium);
+        self
+    }
+    // Soap bubbles: a Dielectric::new(1.) with a water film
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+
+    // Picks reflection or refraction through the film with the film's reflectance, returning
+    // (whether the ray reflects, attenuation of the chosen branch)
+    fn film_scatter(
+        &self,
+        film: &ThinFilm,
+        hit: &HitRecord,
+        cos_theta: f64,
+        refractive_index: f64,
+        wavelength: Option<f64>,
+    ) -> (bool, Vec3) {
+        let thickness = film.thickness(hit.u, hit.v, hit.p);
+        let (outside, substrate) = if hit.front_face {
+            (1., refractive_index)
+        } else {
+            (refractive_index, 1.)
+        };
+        let reflectance = match wavelength {
+            Some(lambda) => {
+                let r =
+                    film.reflectance(cos_theta, thickness, outside, |_| (substrate, 0.), lambda);
+                Vec3::new(r, r, r)
+            }
+            None => film.reflectance_rgb(cos_theta, thickness, outside, |_| (substrate, 0.)),
+        };
+        let probability =
+            ((reflectance.x() + reflectance.y() + reflectance.z()) / 3.).clamp(1e-4, 1. - 1e-4);
+        if random_in_01() < probability {
+            (true, reflectance / probability)
+        } else {
+            let transmittance = Vec3::new(
+                (1. - reflectance.x()).max(0.),
+                (1. - reflectance.y()).max(0.),
+                (1. - reflectance.z()).max(0.),
+            );
+            (false, transmittance / (1. - probability))
+        }
     }
-}
-fn const_attenuation() -> Vec3 {
-    Vec3::new(1., 1., 1.)
 }
 impl Material for Dielectric {
-    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3, bool)> {
+        let (refractive_index, wavelength, color) = if !self.refractive_index.is_dispersive() {
+            (self.refractive_index.nominal(), r_in.wavelength(), white())
+        } else {
+            match r_in.wavelength() {
+                Some(lambda) => (self.refractive_index.at(lambda), Some(lambda), white()),
+                None => {
+                    let lambda = sample_wavelength();
+                    (
+                        self.refractive_index.at(lambda),
+                        Some(lambda),
+                        wavelength_to_rgb_weight(lambda),
+                    )
+                }
+            }
+        };
         let eta_i_over_eta_t = if hit.front_face {
-            1. / self.refractive_index
+            1. / refractive_index
         } else {
-            self.refractive_index
+            refractive_index
         };
 
         let unit_direction = r_in.direction().norm();
         let cos_theta = (-unit_direction).dot(hit.normal).min(1.);
         let sin_theta = (1. - cos_theta * cos_theta).sqrt();
 
-        let ray_reflects = eta_i_over_eta_t * sin_theta > 1.
-            || random_in_01() < reflection_probability(cos_theta, eta_i_over_eta_t);
-        if ray_reflects {
-            let reflected = reflect(unit_direction, hit.normal);
-            let scattered = Ray::new(hit.p, reflected, r_in.time());
-            Some((scattered, const_attenuation()))
+        let cannot_refract = eta_i_over_eta_t * sin_theta > 1.;
+        let (ray_reflects, color) = match &self.film {
+            Some(film) if !cannot_refract => {
+                let (reflects, weight) =
+                    self.film_scatter(film, hit, cos_theta, refractive_index, wavelength);
+                (reflects, color * weight)
+            }
+            _ => (
+                cannot_refract
+                    || random_in_01() < reflection_probability(cos_theta, eta_i_over_eta_t),
+                color,
+            ),
+        };
+        let direction = if ray_reflects {
+            reflect(unit_direction, hit.normal)
         } else {
-            let refracted = refract(unit_direction, hit.normal, eta_i_over_eta_t);
-            let scattered = Ray::new(hit.p, refracted, r_in.time());
-            Some((scattered, const_attenuation()))
-        }
+            refract(unit_direction, hit.normal, eta_i_over_eta_t)
+        };
+        let scattered = Ray::new(hit.p, direction, r_in.time());
+        let scattered = match wavelength {
+            Some(lambda) => scattered.with_wavelength(lambda),
+            None => scattered,
+        };
+        Some((scattered, color, true))
+    }
+
+    fn interior_medium(&self) -> Option<HomogeneousMedium> {
+        self.interior.clone()
+    }
+
+    fn is_wavelength_dependent(&self) -> bool {
+        self.refractive_index.is_dispersive() || self.film.is_some()
     }
 }
 
@@ -58,3 +227,53 @@ fn reflection_probability(cosine: f64, ref_idx: f64) -> f64 {
     let r0 = r0 * r0;
     return r0 + (1. - r0) * (1. - cosine).powi(5);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fraunhofer F, d and C lines, in nanometres
+    const F: f64 = 486.1;
+    const D: f64 = 587.6;
+    const C: f64 = 656.3;
+
+    #[test]
+    fn sellmeier_glasses_match_their_catalogue_values() {
+        // (model, n_F, n_d, n_C)
+        let glasses = [
+            (RefractiveIndex::bk7(), 1.52238, 1.51680, 1.51432),
+            (RefractiveIndex::fused_silica(), 1.46313, 1.45846, 1.45637),
+        ];
+        for (glass, n_f, n_d, n_c) in glasses.iter() {
+            assert!((glass.at(F) - n_f).abs() < 1e-4, "{}", glass.at(F));
+            assert!((glass.at(D) - n_d).abs() < 1e-4, "{}", glass.at(D));
+            assert!((glass.at(C) - n_c).abs() < 1e-4, "{}", glass.at(C));
+            assert_eq!(glass.nominal(), glass.at(D));
+        }
+        assert!((RefractiveIndex::diamond().nominal() - 2.4175).abs() < 2e-3);
+    }
+
+    #[test]
+    fn cauchy_formula() {
+        let index = RefractiveIndex::Cauchy { a: 1.5, b: 0.004 };
+        // 500nm is 0.5 micrometres
+        assert!((index.at(500.) - (1.5 + 0.004 / 0.25)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn blue_light_bends_more() {
+        let models = [
+            RefractiveIndex::Cauchy { a: 1.5, b: 0.004 },
+            RefractiveIndex::bk7(),
+            RefractiveIndex::fused_silica(),
+            RefractiveIndex::diamond(),
+        ];
+        for index in models.iter() {
+            assert!(index.is_dispersive());
+            assert!(index.at(400.) > index.at(550.) && index.at(550.) > index.at(700.));
+        }
+        let constant = RefractiveIndex::Constant(1.33);
+        assert!(!constant.is_dispersive());
+        assert_eq!(constant.at(400.), constant.at(700.));
+    }
+}
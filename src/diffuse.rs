@@ -6,7 +6,7 @@ use crate::{
     utils::{random_in_range, random_in_unit_sphere},
     vec3::Vec3,
 };
-use std::sync::Arc;
+use std::{f64::consts::PI, sync::Arc};
 
 #[derive(Clone, Debug)]
 pub struct Lambertian {
@@ -20,12 +20,101 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3, bool)> {
         let scatter_direction = hit.normal + random_unit_vector();
         // Note: could also only scatter with some probability p and set attenuation to self.albedo/p.
         let scattered = Ray::new(hit.p, scatter_direction, r_in.time());
         let attenuation = self.albedo.value(hit.u, hit.v, hit.p);
-        Some((scattered, attenuation))
+        Some((scattered, attenuation, false))
+    }
+
+    fn eval(&self, _r_in: Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        let cosine = hit.normal.dot(direction.norm());
+        if cosine <= 0. {
+            return Vec3::default();
+        }
+        self.albedo.value(hit.u, hit.v, hit.p) * (cosine / PI)
+    }
+
+    // normal + random_unit_vector() is cosine distributed
+    fn scattering_pdf(&self, _r_in: Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        hit.normal.dot(direction.norm()).max(0.) / PI
+    }
+}
+
+// Rough diffuse surface (Oren and Nayar 1994) made of tiny Lambertian facets whose slopes
+// have a standard deviation of sigma degrees. Compared to Lambertian it reflects more light
+// back towards the viewer and looks flatter, like clay, concrete or the full moon. With
+// sigma = 0 it is Lambertian.
+#[derive(Clone, Debug)]
+pub struct OrenNayar {
+    albedo: Arc<dyn Texture>,
+    a: f64,
+    b: f64,
+}
+
+impl OrenNayar {
+    pub fn new(albedo: Arc<dyn Texture>, sigma: f64) -> Self {
+        let sigma_2 = sigma.to_radians().powi(2);
+        Self {
+            albedo,
+            a: 1. - sigma_2 / (2. * (sigma_2 + 0.33)),
+            b: 0.45 * sigma_2 / (sigma_2 + 0.09),
+        }
+    }
+
+    // the BRDF relative to a Lambertian one, for light leaving along wo
+    fn roughness_factor(&self, normal: Vec3, wo: Vec3, wi: Vec3) -> f64 {
+        let cos_i = normal.dot(wi);
+        let cos_o = normal.dot(wo);
+        let sin_i = (1. - cos_i * cos_i).max(0.).sqrt();
+        let sin_o = (1. - cos_o * cos_o).max(0.).sqrt();
+        // cosine of the azimuth between the two directions
+        let cos_phi = if sin_i > 1e-4 && sin_o > 1e-4 {
+            let tangent_i = (wi - cos_i * normal) / sin_i;
+            let tangent_o = (wo - cos_o * normal) / sin_o;
+            tangent_i.dot(tangent_o).max(0.)
+        } else {
+            0.
+        };
+        let (sin_alpha, tan_beta) = if cos_i.abs() > cos_o.abs() {
+            (sin_o, sin_i / cos_i.abs())
+        } else {
+            (sin_i, sin_o / cos_o.abs().max(1e-4))
+        };
+        self.a + self.b * cos_phi * sin_alpha * tan_beta
+    }
+}
+
+impl Material for OrenNayar {
+    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3, bool)> {
+        // cosine sampling as for Lambertian; the cosine and 1/PI cancel against the pdf
+        let scatter_direction = hit.normal + random_unit_vector();
+        if scatter_direction.length_squared() < 1e-12 {
+            return None;
+        }
+        let wo = -r_in.direction().norm();
+        let factor = self.roughness_factor(hit.normal, wo, scatter_direction.norm());
+        let scattered = Ray::new(hit.p, scatter_direction, r_in.time());
+        Some((
+            scattered,
+            self.albedo.value(hit.u, hit.v, hit.p) * factor,
+            false,
+        ))
+    }
+
+    fn eval(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        let wi = direction.norm();
+        let cosine = hit.normal.dot(wi);
+        if cosine <= 0. {
+            return Vec3::default();
+        }
+        let factor = self.roughness_factor(hit.normal, -r_in.direction().norm(), wi);
+        self.albedo.value(hit.u, hit.v, hit.p) * (factor * cosine / PI)
+    }
+
+    fn scattering_pdf(&self, _r_in: Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        hit.normal.dot(direction.norm()).max(0.) / PI
     }
 }
 
@@ -67,3 +156,71 @@ fn random_in_hemisphere(normal: Vec3) -> Vec3 {
         -in_unit_sphere
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::tests::assert_consistent, texture::SolidColor};
+
+    fn albedo() -> Arc<dyn Texture> {
+        Arc::new(SolidColor::new_from_rgb(0.8, 0.6, 0.3))
+    }
+
+    fn incoming() -> [Vec3; 3] {
+        [
+            Vec3::new(0., 0., -1.),
+            Vec3::new(0.6, 0.2, -0.7).norm(),
+            Vec3::new(-0.9, 0.3, -0.2).norm(),
+        ]
+    }
+
+    // (ray arriving along incoming, its hit on a surface facing +z)
+    fn hit_from(material: Arc<dyn Material>, incoming: Vec3) -> (Ray, HitRecord) {
+        let r_in = Ray::new(-incoming, incoming, 0.);
+        let hit = HitRecord::new(1., Vec3::default(), Vec3::new(0., 0., 1.), r_in, material);
+        (r_in, hit)
+    }
+
+    #[test]
+    fn eval_matches_scatter() {
+        for &d in &incoming() {
+            assert_consistent(Arc::new(Lambertian::new(albedo())), d);
+            assert_consistent(Arc::new(OrenNayar::new(albedo(), 20.)), d);
+            assert_consistent(Arc::new(OrenNayar::new(albedo(), 60.)), d);
+        }
+    }
+
+    #[test]
+    fn smooth_oren_nayar_is_lambertian() {
+        for &d in &incoming() {
+            let (r_in, hit) = hit_from(Arc::new(Lambertian::new(albedo())), d);
+            let (_, rough) = hit_from(Arc::new(OrenNayar::new(albedo(), 0.)), d);
+            for &direction in &[Vec3::new(0.3, -0.4, 0.8), Vec3::new(-0.9, 0.1, 0.1)] {
+                let expected = hit.material.eval(r_in, &hit, direction);
+                let actual = rough.material.eval(r_in, &rough, direction);
+                assert!((actual - expected).magnitude() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn oren_nayar_is_reciprocal() {
+        let material: Arc<dyn Material> = Arc::new(OrenNayar::new(albedo(), 40.));
+        let directions = [
+            Vec3::new(0., 0., 1.),
+            Vec3::new(0.5, 0.1, 0.8).norm(),
+            Vec3::new(-0.8, 0.3, 0.3).norm(),
+            Vec3::new(0.2, -0.9, 0.4).norm(),
+        ];
+        for &a in &directions {
+            for &b in &directions {
+                // eval includes the cosine at the incoming side
+                let (r_a, hit_a) = hit_from(material.clone(), -a);
+                let (r_b, hit_b) = hit_from(material.clone(), -b);
+                let f_ab = material.eval(r_a, &hit_a, b) / b.z();
+                let f_ba = material.eval(r_b, &hit_b, a) / a.z();
+                assert!((f_ab - f_ba).magnitude() < 1e-9, "{:?} {:?}", f_ab, f_ba);
+            }
+        }
+    }
+}
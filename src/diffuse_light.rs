@@ -0,0 +1,49 @@
+use crate::{
+    hittable::HitRecord,
+    material::Material,
+    ray::Ray,
+    spectrum::{rgb_to_illuminant, Illuminant},
+    texture::{SolidColor, Texture},
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+// Area light: any object with this material glows
+#[derive(Clone, Debug)]
+pub struct DiffuseLight {
+    emit: Arc<dyn Texture>,
+    // exact emission spectrum for spectral rendering; otherwise the RGB color is uplifted
+    spectrum: Option<Illuminant>,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Arc<dyn Texture>) -> Self {
+        Self {
+            emit,
+            spectrum: None,
+        }
+    }
+    pub fn new_from_spectrum(spectrum: Illuminant) -> Self {
+        Self {
+            emit: Arc::new(SolidColor::new(spectrum.to_rgb())),
+            spectrum: Some(spectrum),
+        }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _r_in: Ray, _hit: &HitRecord) -> Option<(Ray, Vec3, bool)> {
+        None
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
+        self.emit.value(u, v, p)
+    }
+
+    fn emitted_spectral(&self, u: f64, v: f64, p: Vec3, lambda: f64) -> f64 {
+        match &self.spectrum {
+            Some(spectrum) => spectrum.value(lambda),
+            None => rgb_to_illuminant(self.emitted(u, v, p), lambda),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+// Piecewise-constant distributions for importance sampling tabulated functions, such as the
+// brightness of an environment map (see pbrt, section 13.6.5)
+
+// Samples x in [0, 1) in proportion to a function given by its values over n equal steps
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Distribution1D {
+    function: Vec<f64>,
+    // cdf[i] is the probability of x < i / n
+    cdf: Vec<f64>,
+    // integral of the function over [0, 1)
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(function: Vec<f64>) -> Self {
+        // an empty function is a single step of zero
+        let function = if function.is_empty() {
+            vec![0.]
+        } else {
+            function
+        };
+        let n = function.len();
+        let mut cdf = vec![0.; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + function[i].max(0.) / n as f64;
+        }
+        let integral = if cdf[n].is_finite() { cdf[n] } else { 0. };
+        for (i, c) in cdf.iter_mut().enumerate() {
+            // fall back to uniform sampling of an all-zero (or unbounded) function
+            *c = if integral > 0. {
+                *c / integral
+            } else {
+                i as f64 / n as f64
+            };
+        }
+        Self {
+            function,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    // (x, its density, the step it is in) for u uniform in [0, 1)
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let n = self.function.len();
+        // last step starting at or before u
+        let (mut lo, mut hi) = (0, n);
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            if self.cdf[mid] <= u {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        let width = self.cdf[lo + 1] - self.cdf[lo];
+        let offset = if width > 0. {
+            ((u - self.cdf[lo]) / width).clamp(0., 1.)
+        } else {
+            0.
+        };
+        let x = ((lo as f64 + offset) / n as f64).min(1. - f64::EPSILON);
+        (x, self.density(lo), lo)
+    }
+
+    // density with which sample() picks x
+    pub fn pdf(&self, x: f64) -> f64 {
+        let n = self.function.len();
+        self.density(((x * n as f64).max(0.) as usize).min(n - 1))
+    }
+
+    fn density(&self, i: usize) -> f64 {
+        if self.integral > 0. {
+            self.function[i].max(0.) / self.integral
+        } else {
+            1.
+        }
+    }
+}
+
+// Samples (u, v) in [0, 1)^2 in proportion to a function tabulated on a grid, by picking v
+// from the marginal distribution and then u given v
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    // Function has nv rows of nu values, i.e. the value at (iu, iv) is function[iv * nu + iu].
+    // Missing values count as zero, and an empty grid as a single zero.
+    pub fn new(function: &[f64], nu: usize, nv: usize) -> Self {
+        let (nu, nv) = (nu.max(1), nv.max(1));
+        let conditional: Vec<Distribution1D> = (0..nv)
+            .map(|v| {
+                let row = (0..nu).map(|u| function.get(v * nu + u).copied().unwrap_or(0.));
+                Distribution1D::new(row.collect())
+            })
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|c| c.integral()).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    // ((u, v), density) for u1 and u2 uniform in [0, 1)
+    pub fn sample(&self, u1: f64, u2: f64) -> ((f64, f64), f64) {
+        let (v, pdf_v, iv) = self.marginal.sample(u2);
+        let (u, pdf_u, _) = self.conditional[iv].sample(u1);
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let nv = self.conditional.len();
+        let iv = ((v * nv as f64).max(0.) as usize).min(nv - 1);
+        self.marginal.pdf(v) * self.conditional[iv].pdf(u)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a function with a zero step, a negative one (treated as zero) and a narrow peak
+    fn function() -> Vec<f64> {
+        vec![1., 0., 3., -2., 0.5, 8., 2.]
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let d = Distribution1D::new(function());
+        assert!((d.integral() - 14.5 / 7.).abs() < 1e-12);
+        let steps = 7000;
+        let total: f64 = (0..steps)
+            .map(|i| d.pdf((i as f64 + 0.5) / steps as f64) / steps as f64)
+            .sum();
+        assert!((total - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn samples_follow_the_pdf() {
+        let d = Distribution1D::new(function());
+        let n = function().len();
+        let samples = 100_000;
+        let mut counts = vec![0; n];
+        for i in 0..samples {
+            let (x, pdf, step) = d.sample((i as f64 + 0.5) / samples as f64);
+            assert!((0. ..1.).contains(&x));
+            assert_eq!(step, (x * n as f64) as usize);
+            assert_eq!(pdf, d.pdf(x));
+            counts[step] += 1;
+        }
+        for (step, &count) in counts.iter().enumerate() {
+            let expected = d.pdf((step as f64 + 0.5) / n as f64) / n as f64;
+            assert!((count as f64 / samples as f64 - expected).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn zero_and_empty_functions_are_sampled_uniformly() {
+        for function in vec![vec![], vec![0.; 4], vec![1., f64::INFINITY]] {
+            let d = Distribution1D::new(function);
+            assert_eq!(d.integral(), 0.);
+            for &u in &[0., 0.3, 0.99] {
+                let (x, pdf, _) = d.sample(u);
+                assert!((x - u).abs() < 1e-12);
+                assert_eq!(pdf, 1.);
+                assert_eq!(d.pdf(x), 1.);
+            }
+        }
+    }
+
+    #[test]
+    fn samples_2d_follow_the_pdf() {
+        // 3 rows of 4, with an empty row
+        let function = [1., 2., 0., 1., 0., 0., 0., 0., 4., 0.5, 0.5, 3.];
+        let d = Distribution2D::new(&function, 4, 3);
+        let total: f64 = function.iter().sum();
+        let mut integral = 0.;
+        for iv in 0..3 {
+            for iu in 0..4 {
+                let (u, v) = ((iu as f64 + 0.5) / 4., (iv as f64 + 0.5) / 3.);
+                let expected = function[iv * 4 + iu] * 12. / total;
+                assert!((d.pdf(u, v) - expected).abs() < 1e-12);
+                integral += d.pdf(u, v) / 12.;
+            }
+        }
+        assert!((integral - 1.).abs() < 1e-12);
+        let steps = 300;
+        for i in 0..steps {
+            for j in 0..steps {
+                let u1 = (i as f64 + 0.5) / steps as f64;
+                let u2 = (j as f64 + 0.5) / steps as f64;
+                let ((u, v), pdf) = d.sample(u1, u2);
+                assert!((0. ..1.).contains(&u) && (0. ..1.).contains(&v));
+                assert!(pdf > 0.);
+                assert!((pdf - d.pdf(u, v)).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_and_short_grids_do_not_panic() {
+        let d = Distribution2D::new(&[], 0, 0);
+        assert_eq!(d.sample(0.5, 0.5), ((0.5, 0.5), 1.));
+        assert_eq!(d.pdf(0.5, 0.5), 1.);
+        // the missing second row counts as zero
+        let d = Distribution2D::new(&[1., 1.], 2, 2);
+        assert_eq!(d.pdf(0.5, 0.75), 0.);
+        assert_eq!(d.pdf(0.5, 0.25), 2.);
+    }
+}
@@ -0,0 +1,455 @@
+use crate::{
+    background::Background,
+    distribution::Distribution2D,
+    light::{Light, LightSample},
+    utils::random_in_01,
+    vec3::Vec3,
+};
+use image::hdr::HdrDecoder;
+use std::{
+    f64::consts::PI,
+    fs::{self, File},
+    io::{self, BufReader},
+    path::Path,
+    sync::Arc,
+};
+
+fn invalid_data<E: ToString>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+// Image based lighting from an equirectangular panorama of linear radiance: u runs once
+// around the horizon and v from straight up to straight down. It can be the scene's
+// background, but as a light its bright spots (like the sun) are importance sampled, and
+// escaping rays still see it, so a scene should use it as one or the other.
+#[derive(Clone, Debug)]
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    data: Arc<Vec<Vec3>>,
+    // brightness of each pixel weighted by the solid angle it covers
+    distribution: Arc<Distribution2D>,
+    // about the y axis, in radians
+    rotation: f64,
+    intensity: f64,
+}
+
+impl EnvironmentMap {
+    // pixels are in rows, top row first
+    pub fn new(width: usize, height: usize, data: Vec<Vec3>) -> Self {
+        if width == 0 || height == 0 || data.len() != width * height {
+            eprintln!(
+                "A {}x{} environment map needs {} pixels but got {}",
+                width,
+                height,
+                width * height,
+                data.len()
+            );
+            // a single black pixel keeps lookups in range
+            return Self::new(1, 1, vec![Vec3::default()]);
+        }
+        let mut weights = Vec::with_capacity(width * height);
+        for row in 0..height {
+            let sin_theta = (PI * (row as f64 + 0.5) / height as f64).sin();
+            for column in 0..width {
+                let c = data[row * width + column];
+                let luminance = 0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z();
+                weights.push(luminance.max(0.) * sin_theta);
+            }
+        }
+        Self {
+            width,
+            height,
+            data: Arc::new(data),
+            distribution: Arc::new(Distribution2D::new(&weights, width, height)),
+            rotation: 0.,
+            intensity: 1.,
+        }
+    }
+
+    // a Radiance .hdr file, or an uncompressed scanline OpenEXR file
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        let (width, height, data) = match extension.as_deref() {
+            Some("hdr") => load_hdr(path)?,
+            Some("exr") => load_exr(path)?,
+            _ => return Err(invalid_data("expected a .hdr or .exr file")),
+        };
+        Ok(Self::new(width, height, data))
+    }
+
+    // turns the panorama about the vertical axis
+    pub fn with_rotation(mut self, degrees: f64) -> Self {
+        self.rotation = degrees.to_radians();
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    // rotate about the y axis by angle
+    fn rotate(v: Vec3, angle: f64) -> Vec3 {
+        let (sin, cos) = angle.sin_cos();
+        Vec3::new(v.x() * cos + v.z() * sin, v.y(), -v.x() * sin + v.z() * cos)
+    }
+
+    // panorama coordinates of a unit direction in world space
+    fn to_uv(&self, direction: Vec3) -> (f64, f64) {
+        let d = Self::rotate(direction, -self.rotation);
+        let u = 0.5 + d.x().atan2(-d.z()) / (2. * PI);
+        let v = d.y().clamp(-1., 1.).acos() / PI;
+        (u, v)
+    }
+
+    // world space direction at panorama coordinates (u, v)
+    fn direction_at(&self, u: f64, v: f64) -> Vec3 {
+        let (sin_theta, cos_theta) = (PI * v).sin_cos();
+        let phi = 2. * PI * (u - 0.5);
+        Self::rotate(
+            Vec3::new(sin_theta * phi.sin(), cos_theta, -sin_theta * phi.cos()),
+            self.rotation,
+        )
+    }
+
+    // nearest pixel, to match the piecewise constant distribution
+    fn lookup(&self, u: f64, v: f64) -> Vec3 {
+        let column = ((u * self.width as f64) as usize).min(self.width - 1);
+        let row = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.intensity * self.data[row * self.width + column]
+    }
+
+    // converts a density over the panorama to one over directions
+    fn solid_angle_pdf(&self, uv_pdf: f64, v: f64) -> f64 {
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0. {
+            0.
+        } else {
+            uv_pdf / (2. * PI * PI * sin_theta)
+        }
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn value(&self, direction: Vec3) -> Vec3 {
+        let (u, v) = self.to_uv(direction.norm());
+        self.lookup(u, v)
+    }
+}
+
+impl Light for EnvironmentMap {
+    fn sample(&self, _p: Vec3) -> Option<LightSample> {
+        let ((u, v), uv_pdf) = self.distribution.sample(random_in_01(), random_in_01());
+        let pdf = self.solid_angle_pdf(uv_pdf, v);
+        if pdf <= 0. {
+            return None;
+        }
+        Some(LightSample {
+            direction: self.direction_at(u, v),
+            distance: f64::INFINITY,
+            radiance: self.lookup(u, v),
+            pdf,
+        })
+    }
+
+    fn pdf(&self, _p: Vec3, direction: Vec3) -> f64 {
+        let (u, v) = self.to_uv(direction.norm());
+        self.solid_angle_pdf(self.distribution.pdf(u, v), v)
+    }
+
+    fn emitted(&self, direction: Vec3) -> Vec3 {
+        self.value(direction)
+    }
+}
+
+fn load_hdr(path: &Path) -> io::Result<(usize, usize, Vec<Vec3>)> {
+    let decoder = HdrDecoder::new(BufReader::new(File::open(path)?)).map_err(invalid_data)?;
+    let metadata = decoder.metadata();
+    let pixels = decoder.read_image_hdr().map_err(invalid_data)?;
+    let data = pixels
+        .iter()
+        .map(|p| Vec3::new(p.0[0] as f64, p.0[1] as f64, p.0[2] as f64))
+        .collect();
+    Ok((metadata.width as usize, metadata.height as usize, data))
+}
+
+// IEEE 754 half precision to single
+fn half_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1. } else { 1. };
+    let exponent = ((h >> 10) & 0x1f) as i32;
+    let mantissa = (h & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0. => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1. + mantissa / 1024.) * 2f32.powi(exponent - 15),
+    }
+}
+
+// Just enough of OpenEXR for single part, scanline files without compression, which most
+// tools can write. R, G and B channels are read, or Y for greyscale images.
+fn load_exr(path: &Path) -> io::Result<(usize, usize, Vec<Vec3>)> {
+    let bytes = fs::read(path)?;
+    let truncated = || invalid_data("truncated OpenEXR file");
+    let read_u32 = |at: usize| -> io::Result<u32> {
+        bytes
+            .get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(truncated)
+    };
+    let read_string = |at: usize| -> io::Result<(String, usize)> {
+        let end = bytes[at.min(bytes.len())..]
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(truncated)?;
+        let text = String::from_utf8_lossy(&bytes[at..at + end]).to_string();
+        Ok((text, at + end + 1))
+    };
+
+    if bytes.len() < 8 || bytes[..4] != [0x76, 0x2f, 0x31, 0x01] {
+        return Err(invalid_data("not an OpenEXR file"));
+    }
+    if read_u32(4)? & 0x1a00 != 0 {
+        return Err(invalid_data(
+            "tiled, deep and multi-part OpenEXR files are not supported",
+        ));
+    }
+
+    // (name, pixel type) in file order, which is sorted by name. Types are 0 for u32, 1 for
+    // half and 2 for f32.
+    let mut channels: Vec<(String, u32)> = vec![];
+    let mut compression = 0;
+    let mut window = (0, 0, -1, -1);
+    let mut at = 8;
+    loop {
+        let (name, next) = read_string(at)?;
+        if name.is_empty() {
+            at = next;
+            break;
+        }
+        let (_kind, next) = read_string(next)?;
+        let size = read_u32(next)? as usize;
+        let value = next + 4;
+        if bytes.len() < value + size {
+            return Err(truncated());
+        }
+        match name.as_str() {
+            "channels" => {
+                let mut c = value;
+                loop {
+                    let (channel, next) = read_string(c)?;
+                    if channel.is_empty() {
+                        break;
+                    }
+                    let pixel_type = read_u32(next)?;
+                    if read_u32(next + 8)? != 1 || read_u32(next + 12)? != 1 {
+                        return Err(invalid_data(
+                            "subsampled OpenEXR channels are not supported",
+                        ));
+                    }
+                    channels.push((channel, pixel_type));
+                    c = next + 16;
+                }
+            }
+            "compression" => compression = *bytes.get(value).ok_or_else(truncated)?,
+            "dataWindow" => {
+                window = (
+                    read_u32(value)? as i32,
+                    read_u32(value + 4)? as i32,
+                    read_u32(value + 8)? as i32,
+                    read_u32(value + 12)? as i32,
+                )
+            }
+            _ => {}
+        }
+        at = value + size;
+    }
+    if compression != 0 {
+        return Err(invalid_data(
+            "compressed OpenEXR files are not supported; save without compression or as .hdr",
+        ));
+    }
+    let width = (window.2 - window.0 + 1).max(0) as usize;
+    let height = (window.3 - window.1 + 1).max(0) as usize;
+    if width == 0 || height == 0 {
+        return Err(invalid_data("empty OpenEXR image"));
+    }
+
+    let sample_size = |pixel_type: u32| if pixel_type == 1 { 2 } else { 4 };
+    let find = |name: &str| channels.iter().position(|(n, _)| n == name);
+    let rgb = match (find("R"), find("G"), find("B"), find("Y")) {
+        (Some(r), Some(g), Some(b), _) => [r, g, b],
+        (_, _, _, Some(y)) => [y, y, y],
+        _ => return Err(invalid_data("no RGB or Y channels in OpenEXR file")),
+    };
+    // where each channel's samples start within a scanline
+    let mut starts = vec![];
+    let mut line_size = 0;
+    for (_, pixel_type) in &channels {
+        starts.push(line_size);
+        line_size += sample_size(*pixel_type) * width;
+    }
+
+    let mut data = vec![Vec3::default(); width * height];
+    for line in 0..height {
+        let offset = bytes
+            .get(at + 8 * line..at + 8 * line + 8)
+            .map(|b| u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as usize)
+            .ok_or_else(truncated)?;
+        let y = read_u32(offset)? as i32 - window.1;
+        let pixels = offset + 8;
+        if y < 0 || y as usize >= height || bytes.len() < pixels + line_size {
+            return Err(truncated());
+        }
+        for x in 0..width {
+            let mut color = [0.; 3];
+            for (c, &channel) in rgb.iter().enumerate() {
+                let pixel_type = channels[channel].1;
+                let i = pixels + starts[channel] + x * sample_size(pixel_type);
+                color[c] = match pixel_type {
+                    0 => read_u32(i)? as f64,
+                    1 => half_to_f32(u16::from_le_bytes([bytes[i], bytes[i + 1]])) as f64,
+                    _ => f32::from_bits(read_u32(i)?) as f64,
+                };
+            }
+            data[y as usize * width + x] = Vec3::new(color[0], color[1], color[2]);
+        }
+    }
+    Ok((width, height, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_half_floats() {
+        assert_eq!(half_to_f32(0x3c00), 1.);
+        assert_eq!(half_to_f32(0xc000), -2.);
+        assert_eq!(half_to_f32(0x3555), 0.333_251_95);
+        assert_eq!(half_to_f32(0x7bff), 65504.);
+        // smallest normal and subnormal
+        assert_eq!(half_to_f32(0x0400), 2f32.powi(-14));
+        assert_eq!(half_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(half_to_f32(0x0000), 0.);
+        assert!(half_to_f32(0x8000) == 0. && half_to_f32(0x8000).is_sign_negative());
+        assert_eq!(half_to_f32(0x7c00), f32::INFINITY);
+        assert_eq!(half_to_f32(0xfc00), f32::NEG_INFINITY);
+        assert!(half_to_f32(0x7e00).is_nan());
+    }
+
+    // an uncompressed scanline file with half R, G and B channels, rows top first
+    fn exr(width: i32, rows: &[Vec<[u16; 3]>], compression: u8) -> Vec<u8> {
+        let mut bytes = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+        let attribute = |bytes: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]| {
+            for text in &[name, kind] {
+                bytes.extend(text.as_bytes());
+                bytes.push(0);
+            }
+            bytes.extend(&(value.len() as u32).to_le_bytes());
+            bytes.extend(value);
+        };
+        let mut channels = vec![];
+        // sorted by name, as files store them
+        for name in &["B", "G", "R"] {
+            channels.extend(name.as_bytes());
+            channels.extend(&[0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0]);
+        }
+        channels.push(0);
+        attribute(&mut bytes, "channels", "chlist", &channels);
+        attribute(&mut bytes, "compression", "compression", &[compression]);
+        let mut window = vec![];
+        for value in &[0, 0, width - 1, rows.len() as i32 - 1] {
+            window.extend(&value.to_le_bytes());
+        }
+        attribute(&mut bytes, "dataWindow", "box2i", &window);
+        bytes.push(0);
+
+        let line_size = 3 * 2 * width as usize;
+        let table_end = bytes.len() + 8 * rows.len();
+        for y in 0..rows.len() {
+            let offset = (table_end + y * (8 + line_size)) as u64;
+            bytes.extend(&offset.to_le_bytes());
+        }
+        for (y, row) in rows.iter().enumerate() {
+            bytes.extend(&(y as i32).to_le_bytes());
+            bytes.extend(&(line_size as i32).to_le_bytes());
+            for channel in &[2, 1, 0] {
+                for pixel in row {
+                    bytes.extend(&pixel[*channel].to_le_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    fn load(name: &str, bytes: &[u8]) -> io::Result<(usize, usize, Vec<Vec3>)> {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, bytes)?;
+        let result = load_exr(&path);
+        fs::remove_file(&path)?;
+        result
+    }
+
+    #[test]
+    fn loads_uncompressed_exr() {
+        let rows = vec![
+            vec![[0x3c00, 0x4000, 0x3800], [0, 0x3c00, 0]],
+            vec![[0x4400, 0x4200, 0x4000], [0x3c00, 0x3c00, 0x3c00]],
+        ];
+        let (width, height, data) = load("loads_uncompressed.exr", &exr(2, &rows, 0)).unwrap();
+        assert_eq!((width, height), (2, 2));
+        assert_eq!(
+            data,
+            vec![
+                Vec3::new(1., 2., 0.5),
+                Vec3::new(0., 1., 0.),
+                Vec3::new(4., 3., 2.),
+                Vec3::new(1., 1., 1.),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_compressed_and_truncated_exr() {
+        let rows = vec![vec![[0x3c00; 3]; 3]; 2];
+        assert!(load("rejects_compressed.exr", &exr(3, &rows, 3)).is_err());
+        let bytes = exr(3, &rows, 0);
+        for &length in &[4, 20, bytes.len() - 1] {
+            assert!(load("rejects_truncated.exr", &bytes[..length]).is_err());
+        }
+    }
+
+    #[test]
+    fn sampled_directions_match_the_pdf() {
+        let (width, height) = (8, 4);
+        let data = (0..width * height)
+            .map(|i| Vec3::new(1., 1., 1.) * (i % 5) as f64)
+            .collect();
+        let map = EnvironmentMap::new(width, height, data).with_rotation(30.);
+        let p = Vec3::default();
+        for _ in 0..10_000 {
+            let sample = map.sample(p).unwrap();
+            assert!((sample.direction.magnitude() - 1.).abs() < 1e-9);
+            // right at the poles acos() can't recover the angle accurately enough
+            if sample.direction.y().abs() > 1. - 1e-8 {
+                continue;
+            }
+            assert!((map.pdf(p, sample.direction) / sample.pdf - 1.).abs() < 1e-6);
+            assert_eq!(sample.radiance, map.emitted(sample.direction));
+        }
+    }
+
+    #[test]
+    fn mismatched_sizes_fall_back_to_black() {
+        for (width, height, pixels) in vec![(0, 4, 0), (4, 0, 0), (2, 2, 3)] {
+            let map = EnvironmentMap::new(width, height, vec![Vec3::new(1., 1., 1.); pixels]);
+            assert_eq!(map.value(Vec3::new(0., 1., 0.)), Vec3::default());
+        }
+    }
+}
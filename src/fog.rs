@@ -0,0 +1,235 @@
+use crate::{
+    phase::{Isotropic, PhaseFunction},
+    ray::Ray,
+    utils::random_in_01,
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+// Atmosphere filling the whole scene outside of objects, for haze and aerial perspective.
+// Extinction is the same for every color, and the density is either constant or falls off
+// exponentially with height, so distances can be sampled exactly. The fog fills a sphere
+// around the origin so that rays can still escape to the sky.
+#[derive(Clone, Debug)]
+pub struct Fog {
+    // extinction per unit distance at base_height
+    density: f64,
+    albedo: Vec3,
+    base_height: f64,
+    // density is multiplied by exp(-falloff * (y - base_height)); 0 for uniform fog
+    falloff: f64,
+    radius: f64,
+    phase: Arc<dyn PhaseFunction>,
+}
+
+impl Fog {
+    pub fn new(density: f64, albedo: Vec3, radius: f64) -> Self {
+        Self {
+            density,
+            albedo,
+            base_height: 0.,
+            falloff: 0.,
+            radius,
+            phase: Arc::new(Isotropic),
+        }
+    }
+
+    // Ground fog: thins out by a factor e for every 1 / falloff of height above base_height
+    pub fn with_height_falloff(mut self, base_height: f64, falloff: f64) -> Self {
+        self.base_height = base_height;
+        self.falloff = falloff.max(0.);
+        self
+    }
+
+    pub fn with_phase(mut self, phase: Arc<dyn PhaseFunction>) -> Self {
+        self.phase = phase;
+        self
+    }
+
+    pub fn albedo(&self) -> Vec3 {
+        self.albedo
+    }
+
+    pub fn phase(&self) -> &dyn PhaseFunction {
+        self.phase.as_ref()
+    }
+
+    pub fn density_at(&self, p: Vec3) -> f64 {
+        if p.magnitude() > self.radius {
+            0.
+        } else {
+            self.height_density(p)
+        }
+    }
+
+    // density ignoring the fog's extent, for points just on its boundary
+    fn height_density(&self, p: Vec3) -> f64 {
+        self.density * (-self.falloff * (p.y() - self.base_height)).exp()
+    }
+
+    // part of the ray from 0 to max_distance that is inside the fog, in distances along it
+    fn clip(&self, origin: Vec3, direction: Vec3, max_distance: f64) -> Option<(f64, f64)> {
+        let half_b = origin.dot(direction);
+        let c = origin.dot(origin) - self.radius * self.radius;
+        let discriminant = half_b * half_b - c;
+        if discriminant <= 0. {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        let start = (-half_b - root).max(0.);
+        let end = (-half_b + root).min(max_distance);
+        if end <= start {
+            None
+        } else {
+            Some((start, end))
+        }
+    }
+
+    // optical depth over distance from a point with the given density, along a unit
+    // direction, and the rate c at which the density changes with distance
+    fn optical_depth(density: f64, c: f64, distance: f64) -> f64 {
+        if c.abs() < 1e-9 {
+            density * distance
+        } else {
+            -density * (-c * distance).exp_m1() / c
+        }
+    }
+
+    // fraction of light getting through along r up to ray parameter t_max
+    pub fn transmittance(&self, r: &Ray, t_max: f64) -> f64 {
+        let speed = r.direction().magnitude();
+        let direction = r.direction() / speed;
+        match self.clip(r.origin(), direction, t_max * speed) {
+            Some((start, end)) => {
+                let density = self.height_density(r.origin() + start * direction);
+                let c = self.falloff * direction.y();
+                (-Self::optical_depth(density, c, end - start)).exp()
+            }
+            None => 1.,
+        }
+    }
+
+    // Ray parameter where a path along r is scattered or absorbed by the fog before t_max,
+    // if it is. Distances are sampled in proportion to transmittance, so a path that carries
+    // on needs no reweighting, and one that stops scatters with weight albedo.
+    pub fn sample_distance(&self, r: &Ray, t_max: f64) -> Option<f64> {
+        let speed = r.direction().magnitude();
+        let direction = r.direction() / speed;
+        let (start, end) = self.clip(r.origin(), direction, t_max * speed)?;
+        let density = self.height_density(r.origin() + start * direction);
+        if density <= 0. {
+            return None;
+        }
+        let c = self.falloff * direction.y();
+        // invert the optical depth for an exponentially distributed target
+        let target = -random_in_01().ln();
+        let distance = if c.abs() < 1e-9 {
+            target / density
+        } else {
+            let x = -target * c / density;
+            if x <= -1. {
+                // looking up, the fog thins out before the target is reached
+                return None;
+            }
+            -x.ln_1p() / c
+        };
+        if start + distance < end {
+            Some((start + distance) / speed)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fogs() -> Vec<Fog> {
+        let white = Vec3::new(1., 1., 1.);
+        vec![
+            Fog::new(0.3, white, 50.),
+            Fog::new(0.3, white, 50.).with_height_falloff(1., 0.7),
+        ]
+    }
+
+    // (start, non-unit direction, t_max): level, climbing, descending, reaching the edge
+    // of the fog, and starting outside it
+    fn rays() -> Vec<(Ray, f64)> {
+        vec![
+            (Ray::new(Vec3::default(), Vec3::new(1., 0., 0.), 0.), 5.),
+            (
+                Ray::new(Vec3::new(0., 2., 0.), Vec3::new(0., 0.4, 2.), 0.),
+                1e9,
+            ),
+            (
+                Ray::new(Vec3::new(0., 3., 1.), Vec3::new(0.5, -0.2, 0.), 0.),
+                20.,
+            ),
+            (
+                Ray::new(Vec3::new(-60., 3., 0.), Vec3::new(2., 0.05, 0.), 0.),
+                1e9,
+            ),
+        ]
+    }
+
+    // transmittance by integrating the density along r
+    fn numeric_transmittance(fog: &Fog, r: &Ray, t_max: f64) -> f64 {
+        let speed = r.direction().magnitude();
+        let length = (t_max * speed).min(200.);
+        let steps = 100_000;
+        let step = length / steps as f64;
+        let mut optical_depth = 0.;
+        for i in 0..steps {
+            let s = (i as f64 + 0.5) * step;
+            optical_depth += fog.density_at(r.origin() + s * (r.direction() / speed)) * step;
+        }
+        (-optical_depth).exp()
+    }
+
+    #[test]
+    fn uniform_fog_follows_beer_lambert() {
+        let fog = Fog::new(0.3, Vec3::new(1., 1., 1.), 50.);
+        let r = Ray::new(Vec3::default(), Vec3::new(0., 0., 2.), 0.);
+        // ray parameter 5 is a distance of 10
+        assert!((fog.transmittance(&r, 5.) - (-3_f64).exp()).abs() < 1e-12);
+        let outside = Ray::new(Vec3::new(0., 100., 0.), Vec3::new(1., 0., 0.), 0.);
+        assert_eq!(fog.transmittance(&outside, 1e9), 1.);
+        assert_eq!(fog.sample_distance(&outside, 1e9), None);
+    }
+
+    #[test]
+    fn transmittance_matches_the_density() {
+        for fog in fogs() {
+            for (r, t_max) in rays() {
+                let expected = numeric_transmittance(&fog, &r, t_max);
+                assert!((fog.transmittance(&r, t_max) - expected).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn sampled_distances_follow_the_transmittance() {
+        let samples = 100_000;
+        for fog in fogs() {
+            for (r, t_max) in rays() {
+                // a point partway along, to check the distribution as well as the total
+                let t_half = t_max.min(10.) / 2.;
+                let (mut escaped, mut before_half) = (0, 0);
+                for _ in 0..samples {
+                    match fog.sample_distance(&r, t_max) {
+                        None => escaped += 1,
+                        Some(t) => {
+                            assert!(t > 0. && t < t_max);
+                            before_half += (t < t_half) as usize;
+                        }
+                    }
+                }
+                let escaped = escaped as f64 / samples as f64;
+                let before_half = before_half as f64 / samples as f64;
+                assert!((escaped - fog.transmittance(&r, t_max)).abs() < 0.01);
+                assert!((before_half - (1. - fog.transmittance(&r, t_half))).abs() < 0.01);
+            }
+        }
+    }
+}
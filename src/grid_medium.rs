@@ -0,0 +1,760 @@
+use crate::{
+    aabb::AABB,
+    consts::white,
+    hittable::{HitRecord, Hittable},
+    material::Material,
+    perlin::Perlin,
+    phase::{HenyeyGreenstein, Isotropic, PhaseFunction},
+    ray::Ray,
+    spectrum::blackbody_rgb,
+    utils::random_in_01,
+    vec3::Vec3,
+};
+use std::{fs, io, path::Path, sync::Arc};
+
+// cells per axis of the coarse grid of density bounds used to step through thin regions
+const MAJORANT_RESOLUTION: usize = 16;
+// entries in the temperature to color table used for emission
+const EMISSION_TABLE_SIZE: usize = 64;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// A 3D grid of samples (densities or temperatures) spanning the unit cube, interpolated
+// trilinearly between voxel centers. Samples are stored with z varying fastest, i.e. sample
+// (x, y, z) is at index (x * ny + y) * nz + z.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DensityGrid {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    data: Vec<f64>,
+}
+
+impl DensityGrid {
+    pub fn new(nx: usize, ny: usize, nz: usize, data: Vec<f64>) -> Self {
+        if nx == 0 || ny == 0 || nz == 0 {
+            eprintln!(
+                "A {}x{}x{} grid has no samples, using a single empty voxel instead",
+                nx, ny, nz
+            );
+            return Self::new(1, 1, 1, vec![0.]);
+        }
+        if data.len() != nx * ny * nz {
+            eprintln!(
+                "A {}x{}x{} grid needs {} samples but got {}",
+                nx,
+                ny,
+                nz,
+                nx * ny * nz,
+                data.len()
+            );
+        }
+        Self { nx, ny, nz, data }
+    }
+
+    // headerless little-endian 32-bit floats, as written by most simulation tools
+    pub fn from_raw<P: AsRef<Path>>(path: P, nx: usize, ny: usize, nz: usize) -> io::Result<Self> {
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err(invalid_data(format!("empty {}x{}x{} grid", nx, ny, nz)));
+        }
+        let bytes = fs::read(path)?;
+        if bytes.len() != nx * ny * nz * 4 {
+            return Err(invalid_data(format!(
+                "expected {} bytes for a {}x{}x{} grid, found {}",
+                nx * ny * nz * 4,
+                nx,
+                ny,
+                nz,
+                bytes.len()
+            )));
+        }
+        let data = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect();
+        Ok(Self::new(nx, ny, nz, data))
+    }
+
+    // a 3D array of 32 or 64-bit floats saved with numpy.save()
+    pub fn from_npy<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        if bytes.len() < 10 || &bytes[..6] != b"\x93NUMPY" {
+            return Err(invalid_data("not a .npy file".to_string()));
+        }
+        let (header_length, header_start) = if bytes[6] == 1 {
+            (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10)
+        } else {
+            if bytes.len() < 12 {
+                return Err(invalid_data("truncated .npy header".to_string()));
+            }
+            (
+                u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize,
+                12,
+            )
+        };
+        let data_start = header_start + header_length;
+        if bytes.len() < data_start {
+            return Err(invalid_data("truncated .npy header".to_string()));
+        }
+        let header = String::from_utf8_lossy(&bytes[header_start..data_start]);
+
+        // the header is a Python dict literal, e.g.
+        // {'descr': '<f4', 'fortran_order': False, 'shape': (64, 64, 64), }
+        let value = |key: &str| {
+            header
+                .find(&format!("'{}':", key))
+                .map(|i| header[i + key.len() + 3..].trim_start().to_string())
+        };
+        let descr = value("descr")
+            .and_then(|v| v.split('\'').nth(1).map(|d| d.to_string()))
+            .ok_or_else(|| invalid_data("missing descr in .npy header".to_string()))?;
+        if value("fortran_order").is_some_and(|v| v.starts_with("True")) {
+            return Err(invalid_data(
+                "Fortran-ordered .npy files are not supported".to_string(),
+            ));
+        }
+        let shape: Vec<usize> = value("shape")
+            .and_then(|v| {
+                let end = v.find(')')?;
+                Some(
+                    v[1..end]
+                        .split(',')
+                        .map(|n| n.trim())
+                        .filter(|n| !n.is_empty())
+                        .filter_map(|n| n.parse().ok())
+                        .collect(),
+                )
+            })
+            .ok_or_else(|| invalid_data("missing shape in .npy header".to_string()))?;
+        if shape.len() != 3 {
+            return Err(invalid_data(format!(
+                "expected a 3D array, got shape {:?}",
+                shape
+            )));
+        }
+        if shape.contains(&0) {
+            return Err(invalid_data(format!("empty array of shape {:?}", shape)));
+        }
+
+        let count = shape[0] * shape[1] * shape[2];
+        let body = &bytes[data_start..];
+        let data: Vec<f64> = match descr.as_str() {
+            "<f4" if body.len() >= count * 4 => body
+                .chunks_exact(4)
+                .take(count)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+                .collect(),
+            "<f8" if body.len() >= count * 8 => body
+                .chunks_exact(8)
+                .take(count)
+                .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+                .collect(),
+            _ => {
+                return Err(invalid_data(format!(
+                    "unsupported or truncated .npy data of type {}",
+                    descr
+                )))
+            }
+        };
+        Ok(Self::new(shape[0], shape[1], shape[2], data))
+    }
+
+    // A puff of cloud: Perlin turbulence, fading out towards the edges of the cube
+    pub fn from_turbulence(resolution: usize, frequency: f64) -> Self {
+        let perlin = Perlin::new();
+        let mut data = Vec::with_capacity(resolution * resolution * resolution);
+        let n = resolution as f64;
+        for x in 0..resolution {
+            for y in 0..resolution {
+                for z in 0..resolution {
+                    let p = Vec3::new(
+                        (x as f64 + 0.5) / n,
+                        (y as f64 + 0.5) / n,
+                        (z as f64 + 0.5) / n,
+                    );
+                    let r = 2. * (p - Vec3::new(0.5, 0.5, 0.5)).magnitude();
+                    let falloff = (1. - r * r).max(0.);
+                    data.push(perlin.turbulence(frequency * p, 7) * falloff);
+                }
+            }
+        }
+        Self::new(resolution, resolution, resolution, data)
+    }
+
+    // e.g. to turn a normalized field into temperatures in kelvin
+    pub fn scaled(mut self, factor: f64) -> Self {
+        for value in self.data.iter_mut() {
+            *value *= factor;
+        }
+        self
+    }
+
+    fn at(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.data
+            .get((x * self.ny + y) * self.nz + z)
+            .cloned()
+            .unwrap_or(0.)
+            .max(0.)
+    }
+
+    // value at p in the unit cube
+    pub fn lookup(&self, p: Vec3) -> f64 {
+        let dims = [self.nx, self.ny, self.nz];
+        let mut lower = [0; 3];
+        let mut fraction = [0.; 3];
+        for a in 0..3 {
+            let x = (p[a] * dims[a] as f64 - 0.5)
+                .max(0.)
+                .min((dims[a] - 1) as f64);
+            lower[a] = (x as usize).min(dims[a].saturating_sub(2));
+            fraction[a] = (x - lower[a] as f64).min(1.);
+        }
+        let mut value = 0.;
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let mut weight = 1.;
+            let mut index = [0; 3];
+            for a in 0..3 {
+                index[a] = (lower[a] + offset[a]).min(dims[a] - 1);
+                weight *= if offset[a] == 1 {
+                    fraction[a]
+                } else {
+                    1. - fraction[a]
+                };
+            }
+            value += weight * self.at(index[0], index[1], index[2]);
+        }
+        value
+    }
+
+    pub fn max_value(&self) -> f64 {
+        self.data.iter().cloned().fold(0., f64::max)
+    }
+
+    // largest sample that lookup() can interpolate from inside the box [lo, hi] of the unit
+    // cube
+    fn max_in(&self, lo: Vec3, hi: Vec3) -> f64 {
+        let dims = [self.nx, self.ny, self.nz];
+        let mut first = [0; 3];
+        let mut last = [0; 3];
+        for a in 0..3 {
+            let n = dims[a] as f64;
+            first[a] = (lo[a] * n - 0.5).floor().max(0.) as usize;
+            last[a] = ((hi[a] * n - 0.5).ceil().max(0.) as usize).min(dims[a] - 1);
+        }
+        let mut max: f64 = 0.;
+        for x in first[0]..=last[0] {
+            for y in first[1]..=last[1] {
+                for z in first[2]..=last[2] {
+                    max = max.max(self.at(x, y, z));
+                }
+            }
+        }
+        max
+    }
+}
+
+// upper bounds on the density over a coarse grid of cells, so that tracking can take long
+// steps through the thin parts of the volume
+#[derive(Clone, Debug)]
+struct MajorantGrid {
+    data: Vec<f64>,
+}
+
+impl MajorantGrid {
+    fn new(density: &DensityGrid) -> Self {
+        let n = MAJORANT_RESOLUTION;
+        let size = 1. / n as f64;
+        let mut data = Vec::with_capacity(n * n * n);
+        for x in 0..n {
+            for y in 0..n {
+                for z in 0..n {
+                    let lo = Vec3::new(x as f64 * size, y as f64 * size, z as f64 * size);
+                    data.push(density.max_in(lo, lo + Vec3::new(size, size, size)));
+                }
+            }
+        }
+        Self { data }
+    }
+
+    fn at(&self, cell: [usize; 3]) -> f64 {
+        self.data[(cell[0] * MAJORANT_RESOLUTION + cell[1]) * MAJORANT_RESOLUTION + cell[2]]
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Emission {
+    temperature: DensityGrid,
+    max_temperature: f64,
+    // color and brightness for temperatures evenly spaced from 0 to max_temperature
+    table: Vec<Vec3>,
+}
+
+#[derive(Clone, Debug)]
+struct Volume {
+    bounds: AABB,
+    density: DensityGrid,
+    majorants: MajorantGrid,
+    // extinction coefficient per unit distance where the density is 1
+    sigma_t: f64,
+    albedo: Vec3,
+    phase: Arc<dyn PhaseFunction>,
+    emission: Option<Emission>,
+}
+
+impl Volume {
+    // position in the unit cube spanned by the grids
+    fn to_unit(&self, p: Vec3) -> Vec3 {
+        let min = self.bounds.min();
+        let extent = self.bounds.max() - min;
+        Vec3::new(
+            (p.x() - min.x()) / extent.x(),
+            (p.y() - min.y()) / extent.y(),
+            (p.z() - min.z()) / extent.z(),
+        )
+    }
+
+    fn extinction(&self, p: Vec3) -> f64 {
+        self.sigma_t * self.density.lookup(self.to_unit(p))
+    }
+
+    fn emission(&self, p: Vec3) -> Vec3 {
+        let emission = match &self.emission {
+            Some(emission) => emission,
+            None => return Vec3::default(),
+        };
+        if emission.max_temperature <= 0. {
+            return Vec3::default();
+        }
+        let temperature = emission.temperature.lookup(self.to_unit(p));
+        let x = (temperature / emission.max_temperature).min(1.) * (EMISSION_TABLE_SIZE - 1) as f64;
+        let i = (x as usize).min(EMISSION_TABLE_SIZE - 2);
+        let t = x - i as f64;
+        (1. - t) * emission.table[i] + t * emission.table[i + 1]
+    }
+
+    // Walks the majorant cells that r passes through between t_start and t_end (3D DDA),
+    // calling f with each cell's extent along the ray and its majorant density. Stops early
+    // if f returns false.
+    fn traverse<F: FnMut(f64, f64, f64) -> bool>(
+        &self,
+        r: &Ray,
+        t_start: f64,
+        t_end: f64,
+        mut f: F,
+    ) {
+        let n = MAJORANT_RESOLUTION as f64;
+        let origin = self.to_unit(r.origin());
+        let extent = self.bounds.max() - self.bounds.min();
+        let mut cell = [0; 3];
+        let mut step = [0i64; 3];
+        let mut t_next = [f64::INFINITY; 3];
+        let mut t_delta = [f64::INFINITY; 3];
+        for a in 0..3 {
+            let o = origin[a] * n;
+            let d = r.direction()[a] / extent[a] * n;
+            let c = (o + d * t_start).floor().max(0.).min(n - 1.);
+            cell[a] = c as usize;
+            if d > 0. {
+                step[a] = 1;
+                t_next[a] = (c + 1. - o) / d;
+                t_delta[a] = 1. / d;
+            } else if d < 0. {
+                step[a] = -1;
+                t_next[a] = (c - o) / d;
+                t_delta[a] = -1. / d;
+            }
+        }
+
+        let mut t = t_start;
+        loop {
+            let axis = if t_next[0] < t_next[1] && t_next[0] < t_next[2] {
+                0
+            } else if t_next[1] < t_next[2] {
+                1
+            } else {
+                2
+            };
+            let cell_end = t_next[axis].min(t_end);
+            if cell_end > t && !f(t, cell_end, self.majorants.at(cell)) {
+                return;
+            }
+            if t_next[axis] >= t_end {
+                return;
+            }
+            t = t_next[axis];
+            let next = cell[axis] as i64 + step[axis];
+            if next < 0 || next >= MAJORANT_RESOLUTION as i64 {
+                return;
+            }
+            cell[axis] = next as usize;
+            t_next[axis] += t_delta[axis];
+        }
+    }
+
+    // Delta tracking: the ray parameter of the first real collision, if any
+    fn delta_track(&self, r: &Ray, t_start: f64, t_end: f64) -> Option<f64> {
+        let speed = r.direction().magnitude();
+        let mut collision = None;
+        self.traverse(r, t_start, t_end, |start, end, majorant| {
+            // per unit of the ray parameter
+            let majorant = majorant * self.sigma_t * speed;
+            if majorant <= 0. {
+                return true;
+            }
+            let mut t = start;
+            loop {
+                t -= random_in_01().ln() / majorant;
+                if t >= end {
+                    return true;
+                }
+                // real collision, rather than a fictitious one against the majorant
+                if random_in_01() * majorant <= self.extinction(r.at(t)) * speed {
+                    collision = Some(t);
+                    return false;
+                }
+            }
+        });
+        collision
+    }
+}
+
+// Clouds, smoke and fire from a density grid filling a box, in the style of the book's
+// ConstantMedium: hit() finds where a ray collides with the medium, and the returned record's
+// material scatters the ray from there.
+#[derive(Clone, Debug)]
+pub struct GridMedium {
+    volume: Arc<Volume>,
+    phase: Arc<dyn Material>,
+}
+
+impl GridMedium {
+    // sigma_t is the extinction coefficient per unit distance where the density is 1
+    pub fn new(bounds: AABB, density: DensityGrid, sigma_t: f64, albedo: Vec3) -> Self {
+        let majorants = MajorantGrid::new(&density);
+        Self::from_volume(Volume {
+            bounds,
+            density,
+            majorants,
+            sigma_t,
+            albedo,
+            phase: Arc::new(Isotropic),
+            emission: None,
+        })
+    }
+
+    pub fn with_phase(self, phase: Arc<dyn PhaseFunction>) -> Self {
+        let mut volume = (*self.volume).clone();
+        volume.phase = phase;
+        Self::from_volume(volume)
+    }
+
+    // Henyey–Greenstein scattering, forwards (g > 0, e.g. 0.85 for clouds) or backwards
+    // (g < 0)
+    pub fn with_asymmetry(self, g: f64) -> Self {
+        self.with_phase(Arc::new(HenyeyGreenstein::new(g)))
+    }
+
+    // Fire and explosions: temperature in kelvin over the same box, glowing like a blackbody.
+    // The hottest point is given a luminance of intensity.
+    pub fn with_emission(self, temperature: DensityGrid, intensity: f64) -> Self {
+        let mut volume = (*self.volume).clone();
+        let max_temperature = temperature.max_value();
+        let table = (0..EMISSION_TABLE_SIZE)
+            .map(|i| {
+                let t = max_temperature * i as f64 / (EMISSION_TABLE_SIZE - 1) as f64;
+                intensity * blackbody_rgb(t, max_temperature)
+            })
+            .collect();
+        volume.emission = Some(Emission {
+            temperature,
+            max_temperature,
+            table,
+        });
+        Self::from_volume(volume)
+    }
+
+    fn from_volume(volume: Volume) -> Self {
+        let volume = Arc::new(volume);
+        Self {
+            phase: Arc::new(VolumeScattering {
+                volume: volume.clone(),
+            }),
+            volume,
+        }
+    }
+}
+
+impl Hittable for GridMedium {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (t0, t1) = self.volume.bounds.hit_interval(&r, t_min, t_max)?;
+        let t = self.volume.delta_track(&r, t0, t1)?;
+        // a medium has no surface; any normal will do
+        Some(HitRecord::new(
+            t,
+            r.at(t),
+            -r.direction().norm(),
+            r,
+            self.phase.clone(),
+        ))
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.volume.bounds)
+    }
+}
+
+// What happens at a collision inside a GridMedium: the light scatters with probability albedo
+// and is otherwise absorbed, while hot parts of the medium add their glow
+#[derive(Clone, Debug)]
+struct VolumeScattering {
+    volume: Arc<Volume>,
+}
+
+impl Material for VolumeScattering {
+    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3, bool)> {
+        let direction = self.volume.phase.sample(r_in.direction().norm());
+        Some((
+            Ray::new(hit.p, direction, r_in.time()),
+            self.volume.albedo,
+            false,
+        ))
+    }
+
+    fn eval(&self, r_in: Ray, _hit: &HitRecord, direction: Vec3) -> Vec3 {
+        let phase = self
+            .volume
+            .phase
+            .eval(r_in.direction().norm(), direction.norm());
+        self.volume.albedo * phase
+    }
+
+    fn scattering_pdf(&self, r_in: Ray, _hit: &HitRecord, direction: Vec3) -> f64 {
+        self.volume
+            .phase
+            .pdf(r_in.direction().norm(), direction.norm())
+    }
+
+    fn is_volumetric(&self) -> bool {
+        true
+    }
+
+    // emission is collected at collisions, weighted by the chance of absorption there
+    fn emitted(&self, _u: f64, _v: f64, p: Vec3) -> Vec3 {
+        (white() - self.volume.albedo) * self.volume.emission(p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a version 1 .npy file holding data with the given dtype and shape
+    fn npy(descr: &str, fortran_order: bool, shape: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': {}, 'shape': {}, }}",
+            descr,
+            if fortran_order { "True" } else { "False" },
+            shape
+        );
+        // numpy pads the header with spaces to a multiple of 64 bytes, ending in a newline
+        while (10 + header.len() + 1) % 64 != 0 {
+            header.push(' ');
+        }
+        header.push('\n');
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend(&(header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    fn f32_bytes(values: &[f64]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|&v| (v as f32).to_le_bytes().to_vec())
+            .collect()
+    }
+
+    fn load_npy(name: &str, bytes: &[u8]) -> io::Result<DensityGrid> {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, bytes)?;
+        let result = DensityGrid::from_npy(&path);
+        fs::remove_file(&path)?;
+        result
+    }
+
+    fn load_raw(name: &str, bytes: &[u8], dims: [usize; 3]) -> io::Result<DensityGrid> {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, bytes)?;
+        let result = DensityGrid::from_raw(&path, dims[0], dims[1], dims[2]);
+        fs::remove_file(&path)?;
+        result
+    }
+
+    fn values(count: usize) -> Vec<f64> {
+        (0..count).map(|i| i as f64 * 0.5).collect()
+    }
+
+    #[test]
+    fn loads_npy_files() {
+        let data = values(24);
+        let grid = load_npy(
+            "loads_npy_f4.npy",
+            &npy("<f4", false, "(2, 3, 4)", &f32_bytes(&data)),
+        )
+        .unwrap();
+        assert_eq!(grid, DensityGrid::new(2, 3, 4, data.clone()));
+
+        let f64_bytes: Vec<u8> = data.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+        let grid = load_npy(
+            "loads_npy_f8.npy",
+            &npy("<f8", false, "(4, 3, 2)", &f64_bytes),
+        );
+        assert_eq!(grid.unwrap(), DensityGrid::new(4, 3, 2, data));
+    }
+
+    #[test]
+    fn rejects_bad_npy_files() {
+        let data = f32_bytes(&values(24));
+        let good = npy("<f4", false, "(2, 3, 4)", &data);
+        let mut bad_magic = good.clone();
+        bad_magic[1] = b'X';
+        for (name, bytes) in &[
+            ("rejects_npy_magic.npy", bad_magic),
+            ("rejects_npy_header.npy", good[..20].to_vec()),
+            ("rejects_npy_data.npy", good[..good.len() - 1].to_vec()),
+            ("rejects_npy_2d.npy", npy("<f4", false, "(6, 4)", &data)),
+            (
+                "rejects_npy_fortran.npy",
+                npy("<f4", true, "(2, 3, 4)", &data),
+            ),
+            (
+                "rejects_npy_dtype.npy",
+                npy("<i4", false, "(2, 3, 4)", &data),
+            ),
+            (
+                "rejects_npy_shape.npy",
+                npy("<f4", false, "(2, 3, 5)", &data),
+            ),
+            ("rejects_npy_empty.npy", npy("<f4", false, "(2, 0, 4)", &[])),
+        ] {
+            assert!(load_npy(name, bytes).is_err(), "{} loaded", name);
+        }
+    }
+
+    #[test]
+    fn loads_raw_files_of_the_right_length() {
+        let data = values(24);
+        let bytes = f32_bytes(&data);
+        let grid = load_raw("loads_raw.raw", &bytes, [3, 4, 2]).unwrap();
+        assert_eq!(grid, DensityGrid::new(3, 4, 2, data));
+        assert!(load_raw("rejects_raw_short.raw", &bytes[..92], [3, 4, 2]).is_err());
+        assert!(load_raw("rejects_raw_dims.raw", &bytes, [3, 4, 3]).is_err());
+        assert!(load_raw("rejects_raw_empty.raw", &[], [0, 4, 2]).is_err());
+    }
+
+    #[test]
+    fn empty_grids_fall_back_to_one_empty_voxel() {
+        let grid = DensityGrid::new(3, 0, 2, Vec::new());
+        assert_eq!(grid, DensityGrid::new(1, 1, 1, vec![0.]));
+        assert_eq!(grid.lookup(Vec3::new(0.5, 0.5, 0.5)), 0.);
+        assert_eq!(grid.max_value(), 0.);
+    }
+
+    #[test]
+    fn lookup_interpolates_between_voxel_centers() {
+        let (nx, ny, nz) = (3, 4, 5);
+        let f = |p: Vec3| p.x() + 2. * p.y() + 4. * p.z();
+        let center = |x: usize, y: usize, z: usize| {
+            Vec3::new(
+                (x as f64 + 0.5) / nx as f64,
+                (y as f64 + 0.5) / ny as f64,
+                (z as f64 + 0.5) / nz as f64,
+            )
+        };
+        let mut data = vec![];
+        for x in 0..nx {
+            for y in 0..ny {
+                for z in 0..nz {
+                    data.push(f(center(x, y, z)));
+                }
+            }
+        }
+        let grid = DensityGrid::new(nx, ny, nz, data);
+        for x in 0..nx {
+            for y in 0..ny {
+                for z in 0..nz {
+                    let p = center(x, y, z);
+                    assert!((grid.lookup(p) - f(p)).abs() < 1e-12);
+                }
+            }
+        }
+        // trilinear interpolation reproduces a linear function between the centers
+        for &p in &[Vec3::new(0.5, 0.5, 0.5), Vec3::new(0.3, 0.7, 0.45)] {
+            assert!((grid.lookup(p) - f(p)).abs() < 1e-12);
+        }
+        // and holds the outermost samples out to the corners of the cube
+        assert!((grid.lookup(Vec3::new(0., 0., 0.)) - f(center(0, 0, 0))).abs() < 1e-12);
+        assert!((grid.lookup(Vec3::new(1., 1., 1.)) - f(center(2, 3, 4))).abs() < 1e-12);
+        assert!((grid.lookup(Vec3::new(0., 1., 0.)) - f(center(0, 3, 0))).abs() < 1e-12);
+    }
+
+    #[test]
+    fn majorants_bound_the_density_in_their_cells() {
+        let n = 11;
+        let data = (0..n * n * n).map(|_| random_in_01().powi(4)).collect();
+        let density = DensityGrid::new(n, n, n, data);
+        let majorants = MajorantGrid::new(&density);
+        let size = 1. / MAJORANT_RESOLUTION as f64;
+        for x in 0..MAJORANT_RESOLUTION {
+            for y in 0..MAJORANT_RESOLUTION {
+                for z in 0..MAJORANT_RESOLUTION {
+                    let lo = Vec3::new(x as f64, y as f64, z as f64) * size;
+                    let bound = majorants.at([x, y, z]);
+                    for _ in 0..20 {
+                        let offset =
+                            Vec3::new(random_in_01(), random_in_01(), random_in_01()) * size;
+                        assert!(density.lookup(lo + offset) <= bound + 1e-12);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn delta_tracking_matches_the_mean_free_path() {
+        let n = 4;
+        let sigma_t = 2.;
+        let medium = GridMedium::new(
+            AABB::new(Vec3::new(0., 0., 0.), Vec3::new(10., 10., 10.)),
+            DensityGrid::new(n, n, n, vec![1.; n * n * n]),
+            sigma_t,
+            Vec3::new(0.8, 0.8, 0.8),
+        );
+        // the distance to a collision is exponential with mean 1 / sigma_t, counted in units of
+        // the ray parameter
+        for &speed in &[1., 2.] {
+            let r = Ray::new(Vec3::new(0., 5., 5.), Vec3::new(speed, 0., 0.), 0.);
+            let samples = 20_000;
+            let mut total = 0.;
+            for _ in 0..samples {
+                total += medium.volume.delta_track(&r, 0., 10. / speed).unwrap();
+            }
+            let mean = total / samples as f64;
+            let expected = 1. / (sigma_t * speed);
+            assert!((mean - expected).abs() < 0.03 * expected, "{}", mean);
+        }
+        // nothing collides in an empty grid
+        let empty = GridMedium::new(
+            AABB::new(Vec3::new(0., 0., 0.), Vec3::new(1., 1., 1.)),
+            DensityGrid::new(n, n, n, vec![0.; n * n * n]),
+            sigma_t,
+            Vec3::new(0.8, 0.8, 0.8),
+        );
+        let r = Ray::new(Vec3::new(-1., 0.5, 0.5), Vec3::new(1., 0., 0.), 0.);
+        assert!(empty.hit(r, 0.001, f64::INFINITY).is_none());
+    }
+}
@@ -7,9 +7,16 @@ use std::{fmt::Debug, sync::Arc};
 pub struct HitRecord {
     pub t: f64,
     pub p: Vec3,
+    // shading normal; normal and bump maps may tilt it away from geometric_normal
     pub normal: Vec3,
+    // true surface normal, on the same side as normal
+    pub geometric_normal: Vec3,
     pub u: f64,
     pub v: f64,
+    // partial derivatives of p with respect to u and v; zero if the surface has no
+    // parameterization
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub front_face: bool,
     pub material: Arc<dyn Material>,
 }
@@ -26,13 +33,38 @@ impl HitRecord {
             t,
             p,
             normal,
-            // TODO: use real values here
+            geometric_normal: normal,
             u: 0.,
             v: 0.,
+            dpdu: Vec3::default(),
+            dpdv: Vec3::default(),
             front_face,
             material,
         }
     }
+
+    pub fn with_uv(mut self, u: f64, v: f64, dpdu: Vec3, dpdv: Vec3) -> Self {
+        self.u = u;
+        self.v = v;
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self
+    }
+
+    // Replaces the shading normal, keeping it on the ray's side of the surface. n should
+    // point out of the object, like the outward_normal passed to new().
+    pub fn set_shading_normal(&mut self, n: Vec3) {
+        self.normal = if self.front_face { n } else { -n };
+    }
+
+    // the shading normal pointing out of the object, regardless of which side was hit
+    pub fn outward_normal(&self) -> Vec3 {
+        if self.front_face {
+            self.normal
+        } else {
+            -self.normal
+        }
+    }
 }
 
 // TODO: don't know why, but putting a dyn Hittable in an Arc requires that Hittable implements Send
@@ -40,3 +72,87 @@ pub trait Hittable: Debug + DynClone + Sync + Send {
     fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB>;
 }
+
+// Closest hit on object along r that accept() keeps. Each rejected hit is skipped and the
+// object searched again beyond it, for wrappers that make parts of an object invisible.
+pub fn hit_accepted<F: FnMut(&HitRecord) -> bool>(
+    object: &dyn Hittable,
+    r: Ray,
+    t_min: f64,
+    t_max: f64,
+    mut accept: F,
+) -> Option<HitRecord> {
+    let mut t_min = t_min;
+    loop {
+        let rec = object.hit(r, t_min, t_max)?;
+        if accept(&rec) {
+            return Some(rec);
+        }
+        if rec.t <= t_min {
+            // no progress; give up rather than loop forever
+            return None;
+        }
+        t_min = rec.t;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diffuse::Lambertian, sphere::Sphere, texture::SolidColor};
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.5, 0.5, 0.5,
+        ))))
+    }
+
+    // a surface that is always hit right at t_min
+    #[derive(Clone, Debug)]
+    struct Stuck;
+
+    impl Hittable for Stuck {
+        fn hit(&self, r: Ray, t_min: f64, _t_max: f64) -> Option<HitRecord> {
+            Some(HitRecord::new(
+                t_min,
+                r.at(t_min),
+                Vec3::new(0., 0., 1.),
+                r,
+                material(),
+            ))
+        }
+
+        fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+            None
+        }
+    }
+
+    #[test]
+    fn rejected_hits_are_skipped_in_order() {
+        let sphere = Sphere::new(Vec3::default(), 1., material());
+        let r = Ray::new(Vec3::new(0., 0., -5.), Vec3::new(0., 0., 1.), 0.);
+        let mut seen = vec![];
+        let rec = hit_accepted(&sphere, r, 0.001, f64::INFINITY, |rec| {
+            seen.push(rec.t);
+            !rec.front_face
+        });
+        assert!((rec.unwrap().t - 6.).abs() < 1e-9);
+        assert_eq!(seen.len(), 2);
+        assert!((seen[0] - 4.).abs() < 1e-9);
+
+        let mut seen = 0;
+        let rec = hit_accepted(&sphere, r, 0.001, f64::INFINITY, |_| {
+            seen += 1;
+            false
+        });
+        assert!(rec.is_none());
+        assert_eq!(seen, 2);
+    }
+
+    #[test]
+    fn gives_up_without_progress() {
+        let r = Ray::new(Vec3::default(), Vec3::new(1., 0., 0.), 0.);
+        assert!(hit_accepted(&Stuck, r, 0.001, f64::INFINITY, |_| false).is_none());
+        assert!(hit_accepted(&Stuck, r, 0.001, f64::INFINITY, |_| true).is_some());
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{
+    aabb::AABB,
+    hittable::{HitRecord, Hittable},
+    material::Material,
+    ray::Ray,
+    transform::Transform,
+};
+use std::sync::Arc;
+
+// A transformed reference to shared geometry. Typically `object` is the bottom-level BVH
+// of a mesh (see TriangleMesh::build_bvh), and a BvhNode built over many Instances forms
+// the top-level acceleration structure. Memory therefore scales with the number of unique
+// meshes rather than the number of copies placed in the scene.
+#[derive(Clone, Debug)]
+pub struct Instance {
+    object: Arc<dyn Hittable>,
+    // object space to world space
+    transform: Transform,
+    // replaces the material of whatever the ray hits in `object`
+    material: Option<Arc<dyn Material>>,
+    bounds: Option<AABB>,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hittable>, transform: Transform, time_0: f64, time_1: f64) -> Self {
+        let bounds = object
+            .bounding_box(time_0, time_1)
+            .map(|b| transform.apply_box(b));
+        Self {
+            object,
+            transform,
+            material: None,
+            bounds,
+        }
+    }
+    pub fn with_material(mut self, material: Arc<dyn Material>) -> Self {
+        self.material = Some(material);
+        self
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let object_ray = self.transform.inverse().apply_ray(r);
+        let mut rec = self.object.hit(object_ray, t_min, t_max)?;
+
+        // the normal was already flipped against the object-space ray, and the sign of
+        // its dot product with the ray direction survives the transform
+        rec.p = r.at(rec.t);
+        rec.normal = self.transform.apply_normal(rec.normal).norm();
+        rec.geometric_normal = self.transform.apply_normal(rec.geometric_normal).norm();
+        rec.dpdu = self.transform.apply_vector(rec.dpdu);
+        rec.dpdv = self.transform.apply_vector(rec.dpdv);
+        if let Some(material) = &self.material {
+            rec.material = material.clone();
+        }
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diffuse::Lambertian, mesh::TriangleMesh, texture::SolidColor, vec3::Vec3};
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.5, 0.5, 0.5,
+        ))))
+    }
+
+    // a tetrahedron with uvs, under transform
+    fn tetrahedron(transform: &Transform) -> Arc<TriangleMesh> {
+        let vertices = vec![
+            Vec3::new(0., 0., 0.),
+            Vec3::new(1., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            Vec3::new(0., 0., 1.),
+        ];
+        Arc::new(
+            TriangleMesh::new(
+                vertices.iter().map(|&v| transform.apply_point(v)).collect(),
+                vec![[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]],
+                material(),
+            )
+            .with_uvs(vec![(0., 0.), (1., 0.), (0., 1.), (0.5, 0.5)]),
+        )
+    }
+
+    // none of them mirror, so the winding and with it front_face is kept
+    fn transforms() -> Vec<Transform> {
+        vec![
+            Transform::translation(Vec3::new(1., -2., 3.)),
+            Transform::scaling(Vec3::new(2., 0.5, 3.))
+                .then(Transform::rotation_y(-75.))
+                .then(Transform::translation(Vec3::new(0., 1., 0.))),
+            Transform::from_matrix([[1., 0.7, 0., 2.], [0., 1., -0.4, 0.], [0.3, 0., 1., -1.]]),
+        ]
+    }
+
+    #[test]
+    fn instance_matches_a_transformed_copy() {
+        let object = TriangleMesh::build_bvh(&tetrahedron(&Transform::identity()), 0., 1.);
+        let mut hits = 0;
+        for transform in transforms() {
+            let instance = Instance::new(object.clone(), transform.clone(), 0., 1.);
+            let copy = TriangleMesh::build_bvh(&tetrahedron(&transform), 0., 1.);
+            let center = transform.apply_point(Vec3::new(0.25, 0.25, 0.25));
+            for i in 0..20 {
+                for j in 0..20 {
+                    let theta = std::f64::consts::PI * (i as f64 + 0.5) / 20.;
+                    let phi = 2. * std::f64::consts::PI * j as f64 / 20.;
+                    let from = Vec3::new(
+                        theta.sin() * phi.cos(),
+                        theta.cos(),
+                        theta.sin() * phi.sin(),
+                    );
+                    let aim = Vec3::new(0.1 * phi.sin(), 0.2 * theta.cos(), -0.1);
+                    let r = Ray::new(center + 5. * from, aim - 2.5 * from, 0.);
+                    let expected = copy.hit(r, 0.001, f64::INFINITY);
+                    let actual = instance.hit(r, 0.001, f64::INFINITY);
+                    assert_eq!(actual.is_some(), expected.is_some());
+                    if let (Some(actual), Some(expected)) = (actual, expected) {
+                        hits += 1;
+                        assert!((actual.t - expected.t).abs() < 1e-9);
+                        assert!((actual.p - expected.p).magnitude() < 1e-9);
+                        assert!((actual.normal - expected.normal).magnitude() < 1e-9);
+                        let geometric = actual.geometric_normal - expected.geometric_normal;
+                        assert!(geometric.magnitude() < 1e-9);
+                        assert!((actual.dpdu - expected.dpdu).magnitude() < 1e-9);
+                        assert!((actual.dpdv - expected.dpdv).magnitude() < 1e-9);
+                        assert_eq!(actual.front_face, expected.front_face);
+                        assert!((actual.u - expected.u).abs() < 1e-9);
+                        assert!((actual.v - expected.v).abs() < 1e-9);
+                    }
+                }
+            }
+        }
+        assert!(hits > 300);
+    }
+
+    #[test]
+    fn bounds_contain_the_transformed_object() {
+        for transform in transforms() {
+            let object = TriangleMesh::build_bvh(&tetrahedron(&Transform::identity()), 0., 1.);
+            let bounds = Instance::new(object, transform.clone(), 0., 1.)
+                .bounding_box(0., 1.)
+                .unwrap();
+            let copy = TriangleMesh::build_bvh(&tetrahedron(&transform), 0., 1.);
+            let inner = copy.bounding_box(0., 1.).unwrap();
+            for a in 0..3 {
+                assert!(bounds.min()[a] <= inner.min()[a] + 1e-4);
+                assert!(bounds.max()[a] >= inner.max()[a] - 1e-4);
+            }
+        }
+    }
+}
@@ -1,18 +1,47 @@
 pub mod aabb;
+pub mod alpha;
+pub mod background;
 pub mod bvh_node;
 pub mod camera;
 pub mod canvas;
+pub mod cloth;
+pub mod coated;
+pub mod conductor;
 pub mod consts;
 pub mod dielectric;
 pub mod diffuse;
+pub mod diffuse_light;
+pub mod distribution;
+pub mod environment;
+pub mod fog;
+pub mod grid_medium;
 pub mod hittable;
 pub mod hittable_list;
+pub mod instance;
+pub mod light;
 pub mod material;
+pub mod medium;
+pub mod mesh;
 pub mod metal;
+pub mod microfacet;
+pub mod mix_material;
 pub mod moving_sphere;
+pub mod normal_map;
+pub mod onb;
 pub mod perlin;
+pub mod phase;
+pub mod principled;
 pub mod ray;
+pub mod ray_packet;
+pub mod rough_dielectric;
+pub mod sky;
+pub mod spectrum;
 pub mod sphere;
+pub mod subsurface;
 pub mod texture;
+pub mod thin_film;
+pub mod transform;
+pub mod two_sided;
 pub mod utils;
 pub mod vec3;
+pub mod wide_bvh;
@@ -0,0 +1,423 @@
+use crate::{onb::Onb, utils::random_in_01, vec3::Vec3};
+use dyn_clone::DynClone;
+use std::{f64::consts::PI, fmt::Debug};
+
+// Light sources the integrator samples directly (next event estimation), rather than waiting
+// for paths to find them. This is what gives small, bright lights like the sun clean shadows.
+pub trait Light: Debug + DynClone + Sync + Send {
+    // Picks a direction from p towards the light, or None if it can't light p
+    fn sample(&self, p: Vec3) -> Option<LightSample>;
+
+    // density with which sample() picks direction from p; 0 for delta lights
+    fn pdf(&self, p: Vec3, direction: Vec3) -> f64;
+
+    // radiance seen along direction by paths that leave the scene, for lights at infinity
+    fn emitted(&self, _direction: Vec3) -> Vec3 {
+        Vec3::default()
+    }
+
+    // True for lights with no extent, such as points, which paths can never hit. For these
+    // radiance is the light arriving from the single direction, and pdf is 1.
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightSample {
+    // unit vector from the lit point towards the light
+    pub direction: Vec3,
+    // how far along direction the light is; infinite for lights at infinity
+    pub distance: f64,
+    pub radiance: Vec3,
+    // density in solid angle with which direction was picked
+    pub pdf: f64,
+}
+
+// A distant disk, such as the sun, covering the cone of directions within angular_radius of
+// direction
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct DistantDisk {
+    direction: Vec3,
+    cos_max: f64,
+    radiance: Vec3,
+}
+
+impl DistantDisk {
+    pub fn new(direction: Vec3, angular_radius: f64, radiance: Vec3) -> Self {
+        Self {
+            direction: direction.norm(),
+            cos_max: angular_radius.cos(),
+            radiance,
+        }
+    }
+
+    fn solid_angle(&self) -> f64 {
+        2. * PI * (1. - self.cos_max)
+    }
+}
+
+impl Light for DistantDisk {
+    fn sample(&self, _p: Vec3) -> Option<LightSample> {
+        // uniformly within the cone
+        let cos_theta = 1. - random_in_01() * (1. - self.cos_max);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * random_in_01();
+        let direction = Onb::build_from_w(self.direction).local(Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+        Some(LightSample {
+            direction,
+            distance: f64::INFINITY,
+            radiance: self.radiance,
+            pdf: 1. / self.solid_angle(),
+        })
+    }
+
+    fn pdf(&self, _p: Vec3, direction: Vec3) -> f64 {
+        if direction.norm().dot(self.direction) >= self.cos_max {
+            1. / self.solid_angle()
+        } else {
+            0.
+        }
+    }
+
+    fn emitted(&self, direction: Vec3) -> Vec3 {
+        if direction.norm().dot(self.direction) >= self.cos_max {
+            self.radiance
+        } else {
+            Vec3::default()
+        }
+    }
+}
+
+// Light from a single point, falling off with the square of the distance. Intensity is the
+// radiant intensity, per steradian, in every direction.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct PointLight {
+    position: Vec3,
+    intensity: Vec3,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Vec3) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: Vec3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance = to_light.magnitude();
+        if distance <= 0. {
+            return None;
+        }
+        Some(LightSample {
+            direction: to_light / distance,
+            distance,
+            radiance: self.intensity / (distance * distance),
+            pdf: 1.,
+        })
+    }
+
+    fn pdf(&self, _p: Vec3, _direction: Vec3) -> f64 {
+        0.
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+// A point light shining into a cone around direction. Full intensity within falloff_start
+// degrees of the axis, fading smoothly to nothing at cone_angle degrees.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct SpotLight {
+    position: Vec3,
+    direction: Vec3,
+    intensity: Vec3,
+    cos_falloff_start: f64,
+    cos_cone: f64,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        cone_angle: f64,
+        falloff_start: f64,
+    ) -> Self {
+        Self {
+            position,
+            direction: direction.norm(),
+            intensity,
+            cos_falloff_start: falloff_start.min(cone_angle).to_radians().cos(),
+            cos_cone: cone_angle.to_radians().cos(),
+        }
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta >= self.cos_falloff_start {
+            1.
+        } else if cos_theta <= self.cos_cone {
+            0.
+        } else {
+            // smoothstep
+            let t = (cos_theta - self.cos_cone) / (self.cos_falloff_start - self.cos_cone);
+            t * t * (3. - 2. * t)
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: Vec3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance = to_light.magnitude();
+        if distance <= 0. {
+            return None;
+        }
+        let direction = to_light / distance;
+        let falloff = self.falloff(-direction.dot(self.direction));
+        if falloff <= 0. {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: falloff * self.intensity / (distance * distance),
+            pdf: 1.,
+        })
+    }
+
+    fn pdf(&self, _p: Vec3, _direction: Vec3) -> f64 {
+        0.
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+// Parallel light from far away, arriving from direction. Irradiance is measured on a surface
+// facing the light. Giving it an angular diameter turns it into a small disk in the sky
+// (the sun is about 0.53 degrees), which softens shadows.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct DirectionalLight {
+    direction: Vec3,
+    irradiance: Vec3,
+    disk: Option<DistantDisk>,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Vec3) -> Self {
+        Self {
+            direction: direction.norm(),
+            irradiance,
+            disk: None,
+        }
+    }
+
+    pub fn with_angular_diameter(mut self, degrees: f64) -> Self {
+        self.disk = if degrees > 0. {
+            let radius = 0.5 * degrees.to_radians();
+            let solid_angle = 2. * PI * (1. - radius.cos());
+            Some(DistantDisk::new(
+                self.direction,
+                radius,
+                self.irradiance / solid_angle,
+            ))
+        } else {
+            None
+        };
+        self
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, p: Vec3) -> Option<LightSample> {
+        match &self.disk {
+            Some(disk) => disk.sample(p),
+            None => Some(LightSample {
+                direction: self.direction,
+                distance: f64::INFINITY,
+                radiance: self.irradiance,
+                pdf: 1.,
+            }),
+        }
+    }
+
+    fn pdf(&self, p: Vec3, direction: Vec3) -> f64 {
+        self.disk.map_or(0., |disk| disk.pdf(p, direction))
+    }
+
+    fn emitted(&self, direction: Vec3) -> Vec3 {
+        self.disk
+            .map_or(Vec3::default(), |disk| disk.emitted(direction))
+    }
+
+    fn is_delta(&self) -> bool {
+        self.disk.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sky::PreethamSky;
+
+    // Checks that sample() picks directions with the density pdf() reports: every sample's pdf
+    // agrees with pdf(), pdf() integrates to one, and the samples land in a region as often as
+    // the integral of the pdf over it says
+    fn assert_samples_match_pdf(light: &dyn Light, axis: Vec3) {
+        let p = Vec3::new(0.3, -2., 1.);
+        let onb = Onb::build_from_w(axis);
+        // midpoint rule in (theta, phi) around the axis, counting the region theta < 0.05 apart
+        let (n_theta, n_phi) = (20_000, 64);
+        let theta_max = 0.4;
+        let (mut total, mut inner) = (0., 0.);
+        for i in 0..n_theta {
+            let theta = theta_max * (i as f64 + 0.5) / n_theta as f64;
+            let (sin_theta, cos_theta) = theta.sin_cos();
+            for j in 0..n_phi {
+                let phi = 2. * PI * (j as f64 + 0.5) / n_phi as f64;
+                let d = onb.local(Vec3::new(
+                    sin_theta * phi.cos(),
+                    sin_theta * phi.sin(),
+                    cos_theta,
+                ));
+                let mass = light.pdf(p, d) * sin_theta;
+                total += mass;
+                if theta < 0.05 {
+                    inner += mass;
+                }
+            }
+        }
+        let cell = theta_max * 2. * PI / (n_theta * n_phi) as f64;
+        let (total, inner) = (total * cell, inner * cell);
+        assert!((total - 1.).abs() < 1e-3, "pdf integrates to {}", total);
+
+        let samples = 100_000;
+        let mut inner_count = 0;
+        for _ in 0..samples {
+            let sample = light.sample(p).unwrap();
+            assert!((sample.direction.magnitude() - 1.).abs() < 1e-9);
+            assert!((sample.pdf - light.pdf(p, sample.direction)).abs() < 1e-9 * sample.pdf);
+            assert_eq!(sample.radiance, light.emitted(sample.direction));
+            if sample.direction.dot(axis) > 0.05f64.cos() {
+                inner_count += 1;
+            }
+        }
+        let fraction = inner_count as f64 / samples as f64;
+        assert!((fraction - inner).abs() < 0.01, "{} != {}", fraction, inner);
+    }
+
+    #[test]
+    fn disk_samples_match_the_pdf() {
+        let axis = Vec3::new(1., 2., -0.5).norm();
+        let disk = DistantDisk::new(axis, 0.3, Vec3::new(2., 1., 0.5));
+        assert_samples_match_pdf(&disk, axis);
+        // nothing outside the cone
+        let outside = Onb::build_from_w(axis).local(Vec3::new(0.31f64.sin(), 0., 0.31f64.cos()));
+        assert_eq!(disk.pdf(Vec3::default(), outside), 0.);
+        assert_eq!(disk.emitted(outside), Vec3::default());
+        assert!(!disk.is_delta());
+    }
+
+    #[test]
+    fn sun_is_sampled_within_its_disk() {
+        let sky = PreethamSky::new(30., 40., 3.);
+        let sun = sky.sun();
+        let p = Vec3::default();
+        for _ in 0..1000 {
+            let sample = sun.sample(p).unwrap();
+            assert!(sample.direction.dot(sky.sun_direction()) >= sun.cos_max - 1e-12);
+            assert!((sample.pdf - sun.pdf(p, sample.direction)).abs() < 1e-9 * sample.pdf);
+            assert_eq!(sample.distance, f64::INFINITY);
+        }
+        assert!(sun.pdf(p, -sky.sun_direction()) == 0.);
+        // the sun sets behind the horizon
+        let below = PreethamSky::new(-5., 40., 3.).sun();
+        assert_eq!(below.sample(p).unwrap().radiance, Vec3::default());
+    }
+
+    #[test]
+    fn point_light_falls_off_with_distance() {
+        let light = PointLight::new(Vec3::new(1., 4., 2.), Vec3::new(8., 4., 2.));
+        let p = Vec3::new(1., 0., -1.);
+        let sample = light.sample(p).unwrap();
+        assert!((sample.direction - Vec3::new(0., 0.8, 0.6)).magnitude() < 1e-12);
+        assert!((sample.distance - 5.).abs() < 1e-12);
+        assert!((sample.radiance - Vec3::new(8., 4., 2.) / 25.).magnitude() < 1e-12);
+        // a delta light is picked with certainty, and never by a scattered direction
+        assert_eq!(sample.pdf, 1.);
+        assert_eq!(light.pdf(p, sample.direction), 0.);
+        assert!(light.is_delta());
+        assert!(light.sample(Vec3::new(1., 4., 2.)).is_none());
+    }
+
+    #[test]
+    fn spot_light_fades_between_the_cones() {
+        let position = Vec3::new(0., 10., 0.);
+        let intensity = Vec3::new(100., 100., 100.);
+        let light = SpotLight::new(position, Vec3::new(0., -1., 0.), intensity, 30., 20.);
+        // points on the ground at a given angle from the spot's axis
+        let at = |degrees: f64| Vec3::new(10. * degrees.to_radians().tan(), 0., 0.);
+        let radiance = |degrees: f64| {
+            let p = at(degrees);
+            light.sample(p).map_or(0., |sample| {
+                let distance = (position - p).magnitude();
+                assert!((sample.distance - distance).abs() < 1e-9);
+                assert_eq!(sample.pdf, 1.);
+                assert_eq!(light.pdf(p, sample.direction), 0.);
+                sample.radiance.x() * distance * distance / intensity.x()
+            })
+        };
+        for &degrees in &[0., 10., 19.9] {
+            assert!((radiance(degrees) - 1.).abs() < 1e-9);
+        }
+        let mut previous = 1.;
+        for &degrees in &[21., 24., 27., 29.9] {
+            let falloff = radiance(degrees);
+            assert!(falloff > 0. && falloff < previous);
+            previous = falloff;
+        }
+        assert!(light.sample(at(30.1)).is_none());
+        assert!(light.sample(Vec3::new(0., 20., 0.)).is_none());
+        assert!(light.is_delta());
+    }
+
+    #[test]
+    fn directional_light_is_a_delta_or_a_disk() {
+        let direction = Vec3::new(-1., 3., 0.5).norm();
+        let irradiance = Vec3::new(3., 2., 1.);
+        let p = Vec3::new(4., 0., -2.);
+        let light = DirectionalLight::new(direction, irradiance);
+        let sample = light.sample(p).unwrap();
+        assert!((sample.direction - direction).magnitude() < 1e-12);
+        assert_eq!(sample.distance, f64::INFINITY);
+        assert_eq!(sample.radiance, irradiance);
+        assert_eq!(sample.pdf, 1.);
+        assert_eq!(light.pdf(p, direction), 0.);
+        assert_eq!(light.emitted(direction), Vec3::default());
+        assert!(light.is_delta());
+
+        let disk = light.with_angular_diameter(10.);
+        assert!(!disk.is_delta());
+        assert_samples_match_pdf(&disk, direction);
+        // a surface facing the light still receives the irradiance
+        let samples = 10_000;
+        let mut received = Vec3::default();
+        for _ in 0..samples {
+            let sample = disk.sample(p).unwrap();
+            received += sample.radiance * (sample.direction.dot(direction) / sample.pdf);
+        }
+        let received = received / samples as f64;
+        assert!((received - irradiance).magnitude() < 0.01 * irradiance.magnitude());
+    }
+}
@@ -1,8 +1,115 @@
-use crate::{hittable::HitRecord, ray::Ray, vec3::Vec3};
+use crate::{
+    hittable::HitRecord, medium::HomogeneousMedium, ray::Ray, spectrum::rgb_to_illuminant,
+    vec3::Vec3,
+};
 use dyn_clone::DynClone;
 use std::fmt::Debug;
 
 pub trait Material: Debug + DynClone + Sync + Send {
-    // returns (scattered ray, attenuation)
-    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3)>;
+    // Returns (scattered ray, attenuation, is_specular). is_specular is true when the direction
+    // came from a perfectly sharp lobe that eval and scattering_pdf leave out.
+    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3, bool)>;
+
+    // BSDF times the cosine term for light arriving from direction and leaving along -r_in.
+    // Purely specular materials can't be evaluated and return black.
+    fn eval(&self, _r_in: Ray, _hit: &HitRecord, _direction: Vec3) -> Vec3 {
+        Vec3::default()
+    }
+
+    // density with which scatter() picks direction; 0 for purely specular materials
+    fn scattering_pdf(&self, _r_in: Ray, _hit: &HitRecord, _direction: Vec3) -> f64 {
+        0.
+    }
+
+    // Medium enclosed by surfaces of this material. The integrator switches to it when a
+    // scattered ray passes through a front face, and back out when it leaves through a back face.
+    fn interior_medium(&self) -> Option<HomogeneousMedium> {
+        None
+    }
+
+    // True for collisions inside a participating medium rather than with a surface. Their
+    // normal means nothing, and paths scattering there don't enter or leave anything.
+    fn is_volumetric(&self) -> bool {
+        false
+    }
+
+    // light given off by the surface, in linear RGB
+    fn emitted(&self, _u: f64, _v: f64, _p: Vec3) -> Vec3 {
+        Vec3::default()
+    }
+
+    // emission at a single wavelength (in nanometres), for spectral rendering
+    fn emitted_spectral(&self, u: f64, v: f64, p: Vec3, lambda: f64) -> f64 {
+        rgb_to_illuminant(self.emitted(u, v, p), lambda)
+    }
+
+    // true if scatter() looks at the ray's wavelength, in which case spectral rendering can
+    // only follow that one wavelength afterwards
+    fn is_wavelength_dependent(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{f64::consts::PI, sync::Arc};
+
+    // Checks that eval and scattering_pdf describe what scatter() does, for light arriving
+    // along incoming at a surface whose outward normal is +z and tangent +x (light from below
+    // hits the back face): the pdf has to add up to the chance of a non-specular sample, and
+    // those samples have to average out to the integral of eval. Both are compared on a
+    // function that varies with direction.
+    pub(crate) fn assert_consistent(material: Arc<dyn Material>, incoming: Vec3) {
+        let r_in = Ray::new(-incoming, incoming, 0.);
+        let hit = HitRecord::new(1., Vec3::default(), Vec3::new(0., 0., 1.), r_in, material)
+            .with_uv(0.5, 0.5, Vec3::new(1., 0., 0.), Vec3::new(0., 1., 0.));
+        let weight = |d: Vec3| 1. + 0.5 * d.x() - 0.3 * d.y() + d.z() * d.z();
+
+        // midpoint rule over the sphere in (theta, phi), which resolves lobes around the
+        // normal (such as refraction at normal incidence) better than steps in cos theta
+        let (n_theta, n_phi) = (1000, 200);
+        let mut integral = Vec3::default();
+        let mut total_pdf = 0.;
+        for i in 0..n_theta {
+            let theta = PI * (i as f64 + 0.5) / n_theta as f64;
+            let (sin_theta, cos_theta) = theta.sin_cos();
+            for j in 0..n_phi {
+                let phi = 2. * PI * (j as f64 + 0.5) / n_phi as f64;
+                let d = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                integral += hit.material.eval(r_in, &hit, d) * (weight(d) * sin_theta);
+                total_pdf += hit.material.scattering_pdf(r_in, &hit, d) * sin_theta;
+            }
+        }
+        let cell = 2. * PI * PI / (n_theta * n_phi) as f64;
+        let integral = integral * cell;
+        let total_pdf = total_pdf * cell;
+
+        let samples = 100_000;
+        let mut estimate = Vec3::default();
+        let mut non_specular = 0;
+        for _ in 0..samples {
+            if let Some((scattered, attenuation, false)) = hit.material.scatter(r_in, &hit) {
+                estimate += attenuation * weight(scattered.direction().norm());
+                non_specular += 1;
+            }
+        }
+        let estimate = estimate / samples as f64;
+        let fraction = non_specular as f64 / samples as f64;
+
+        assert!(
+            (total_pdf - fraction).abs() < 0.01,
+            "pdf integrates to {} but {} of the samples are non-specular",
+            total_pdf,
+            fraction
+        );
+        for axis in 0..3 {
+            assert!(
+                (estimate[axis] - integral[axis]).abs() < 0.02 * integral[axis].max(0.1),
+                "scatter averages {:?} but eval integrates to {:?}",
+                estimate,
+                integral
+            );
+        }
+    }
 }
@@ -0,0 +1,118 @@
+use crate::{
+    phase::{Isotropic, PhaseFunction},
+    utils::random_in_01,
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+// Uniform participating medium filling the inside of a closed object, e.g. tinted glass, or
+// wax and skin when it also scatters. Coefficients are per unit distance, per color channel.
+#[derive(Clone, Debug)]
+pub struct HomogeneousMedium {
+    absorption: Vec3,
+    scattering: Vec3,
+    phase: Arc<dyn PhaseFunction>,
+}
+
+impl HomogeneousMedium {
+    pub fn new(absorption: Vec3) -> Self {
+        Self {
+            absorption,
+            scattering: Vec3::default(),
+            phase: Arc::new(Isotropic),
+        }
+    }
+
+    // absorption such that white light keeps `color` after travelling `distance`
+    pub fn from_transmittance(color: Vec3, distance: f64) -> Self {
+        let channel = |c: f64| -c.clamp(1e-6, 1.).ln() / distance;
+        Self::new(Vec3::new(
+            channel(color.x()),
+            channel(color.y()),
+            channel(color.z()),
+        ))
+    }
+
+    // A scattering medium described the way artists think about it: the fraction of light
+    // surviving each scattering event, and the average distance between events
+    pub fn from_albedo(albedo: Vec3, mean_free_path: Vec3) -> Self {
+        let channel = |albedo: f64, mean_free_path: f64| {
+            let extinction = 1. / mean_free_path.max(1e-6);
+            let scattering = albedo.clamp(0., 1.) * extinction;
+            (extinction - scattering, scattering)
+        };
+        let (ra, rs) = channel(albedo.x(), mean_free_path.x());
+        let (ga, gs) = channel(albedo.y(), mean_free_path.y());
+        let (ba, bs) = channel(albedo.z(), mean_free_path.z());
+        Self::new(Vec3::new(ra, ga, ba)).with_scattering(Vec3::new(rs, gs, bs))
+    }
+
+    pub fn with_scattering(mut self, scattering: Vec3) -> Self {
+        self.scattering = scattering;
+        self
+    }
+    // isotropic unless set
+    pub fn with_phase(mut self, phase: Arc<dyn PhaseFunction>) -> Self {
+        self.phase = phase;
+        self
+    }
+
+    pub fn absorption(&self) -> Vec3 {
+        self.absorption
+    }
+    pub fn scattering(&self) -> Vec3 {
+        self.scattering
+    }
+    pub fn phase(&self) -> &dyn PhaseFunction {
+        self.phase.as_ref()
+    }
+    pub fn scatters(&self) -> bool {
+        self.scattering.x() > 0. || self.scattering.y() > 0. || self.scattering.z() > 0.
+    }
+
+    // Beer–Lambert law
+    pub fn transmittance(&self, distance: f64) -> Vec3 {
+        let extinction = self.absorption + self.scattering;
+        Vec3::new(
+            (-extinction.x() * distance).exp(),
+            (-extinction.y() * distance).exp(),
+            (-extinction.z() * distance).exp(),
+        )
+    }
+
+    // Picks where a path travelling max_distance through the medium scatters: Some(distance)
+    // to scatter there, or None if it reaches the end. Returns the path weight along with it.
+    // The distance is sampled for one randomly chosen color channel and weighted against all
+    // three, so strongly colored media don't produce fireflies.
+    pub fn sample_distance(&self, max_distance: f64) -> (Option<f64>, Vec3) {
+        if !self.scatters() {
+            return (None, self.transmittance(max_distance));
+        }
+        let extinction = self.absorption + self.scattering;
+        let channel = ((random_in_01() * 3.) as usize).min(2);
+        let distance = if extinction[channel] > 0. {
+            -random_in_01().ln() / extinction[channel]
+        } else {
+            f64::INFINITY
+        };
+
+        let scattered = distance < max_distance;
+        let distance = distance.min(max_distance);
+        let transmittance = self.transmittance(distance);
+        // pdf of the event averaged over the channel choice
+        let density = if scattered {
+            extinction * transmittance
+        } else {
+            transmittance
+        };
+        let pdf = (density.x() + density.y() + density.z()) / 3.;
+        if pdf <= 0. {
+            return (None, Vec3::default());
+        }
+        if scattered {
+            (Some(distance), self.scattering * transmittance / pdf)
+        } else {
+            (None, transmittance / pdf)
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use crate::{
+    aabb::AABB,
+    alpha::Alpha,
+    bvh_node::BvhNode,
+    hittable::{HitRecord, Hittable},
+    hittable_list::HittableList,
+    material::Material,
+    ray::Ray,
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+// Triangles below this determinant are considered parallel to the ray
+const PARALLEL_EPSILON: f64 = 1e-12;
+
+#[derive(Clone, Debug)]
+pub struct TriangleMesh {
+    vertices: Vec<Vec3>,
+    // one (u, v) pair per vertex; barycentric coordinates are used when absent
+    uvs: Option<Vec<(f64, f64)>>,
+    indices: Vec<[usize; 3]>,
+    material: Arc<dyn Material>,
+    // cutout mask looked up with the triangle's (u, v)
+    alpha: Option<Alpha>,
+}
+
+impl TriangleMesh {
+    pub fn new(vertices: Vec<Vec3>, indices: Vec<[usize; 3]>, material: Arc<dyn Material>) -> Self {
+        Self {
+            vertices,
+            uvs: None,
+            indices,
+            material,
+            alpha: None,
+        }
+    }
+    pub fn with_uvs(mut self, uvs: Vec<(f64, f64)>) -> Self {
+        if uvs.len() != self.vertices.len() {
+            eprintln!(
+                "Mesh has {} vertices but {} uvs",
+                self.vertices.len(),
+                uvs.len()
+            );
+        }
+        self.uvs = Some(uvs);
+        self
+    }
+    // Leaves, fences and other cutouts: rays go through where the alpha says so
+    pub fn with_alpha(mut self, alpha: Alpha) -> Self {
+        self.alpha = Some(alpha);
+        self
+    }
+    pub fn num_triangles(&self) -> usize {
+        self.indices.len()
+    }
+
+    // One Triangle per face, each sharing this mesh's vertex data
+    pub fn triangles(mesh: &Arc<TriangleMesh>) -> HittableList {
+        let mut list = HittableList::new();
+        for index in 0..mesh.num_triangles() {
+            list.add(Arc::new(Triangle {
+                mesh: mesh.clone(),
+                index,
+            }));
+        }
+        list
+    }
+
+    // Bottom-level acceleration structure for this mesh; build once and share it between
+    // every Instance of the mesh.
+    pub fn build_bvh(mesh: &Arc<TriangleMesh>, time_0: f64, time_1: f64) -> Arc<BvhNode> {
+        Arc::new(BvhNode::new_from_hittable(
+            &TriangleMesh::triangles(mesh),
+            time_0,
+            time_1,
+        ))
+    }
+
+    fn corners(&self, index: usize) -> (Vec3, Vec3, Vec3) {
+        let [a, b, c] = self.indices[index];
+        (self.vertices[a], self.vertices[b], self.vertices[c])
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Triangle {
+    mesh: Arc<TriangleMesh>,
+    index: usize,
+}
+
+impl Hittable for Triangle {
+    // Möller–Trumbore intersection
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (p0, p1, p2) = self.mesh.corners(self.index);
+        let edge_1 = p1 - p0;
+        let edge_2 = p2 - p0;
+        let p_vec = r.direction().cross(edge_2);
+        let det = edge_1.dot(p_vec);
+        if det.abs() < PARALLEL_EPSILON {
+            return None;
+        }
+        let inv_det = 1. / det;
+
+        let t_vec = r.origin() - p0;
+        let b1 = t_vec.dot(p_vec) * inv_det;
+        if !(0. ..=1.).contains(&b1) {
+            return None;
+        }
+        let q_vec = t_vec.cross(edge_1);
+        let b2 = r.direction().dot(q_vec) * inv_det;
+        if b2 < 0. || b1 + b2 > 1. {
+            return None;
+        }
+        let t = edge_2.dot(q_vec) * inv_det;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+
+        let rec = HitRecord::new(
+            t,
+            r.at(t),
+            edge_1.cross(edge_2).norm(),
+            r,
+            self.mesh.material.clone(),
+        );
+        let b0 = 1. - b1 - b2;
+        let rec = match &self.mesh.uvs {
+            Some(uvs) => {
+                let [a, b, c] = self.mesh.indices[self.index];
+                let u = b0 * uvs[a].0 + b1 * uvs[b].0 + b2 * uvs[c].0;
+                let v = b0 * uvs[a].1 + b1 * uvs[b].1 + b2 * uvs[c].1;
+                // solve p - p2 = (u - u2) dpdu + (v - v2) dpdv at the other two corners
+                let (du_02, dv_02) = (uvs[a].0 - uvs[c].0, uvs[a].1 - uvs[c].1);
+                let (du_12, dv_12) = (uvs[b].0 - uvs[c].0, uvs[b].1 - uvs[c].1);
+                let det = du_02 * dv_12 - dv_02 * du_12;
+                if det.abs() < PARALLEL_EPSILON {
+                    // degenerate uvs; fall back to the barycentric parameterization
+                    rec.with_uv(u, v, edge_1, edge_2)
+                } else {
+                    let (dp_02, dp_12) = (p0 - p2, p1 - p2);
+                    let dpdu = (dv_12 * dp_02 - dv_02 * dp_12) / det;
+                    let dpdv = (du_02 * dp_12 - du_12 * dp_02) / det;
+                    rec.with_uv(u, v, dpdu, dpdv)
+                }
+            }
+            None => rec.with_uv(b1, b2, edge_1, edge_2),
+        };
+        if let Some(alpha) = &self.mesh.alpha {
+            if !alpha.is_opaque(rec.u, rec.v, rec.p) {
+                return None;
+            }
+        }
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        let (p0, p1, p2) = self.mesh.corners(self.index);
+        // pad so that axis-aligned triangles don't produce a zero-width box
+        let padding = Vec3::new(1e-4, 1e-4, 1e-4);
+        let bounds = AABB::new(p0, p0)
+            .combine(AABB::new(p1, p1))
+            .combine(AABB::new(p2, p2));
+        Some(AABB::new(bounds.min() - padding, bounds.max() + padding))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diffuse::Lambertian, texture::SolidColor};
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.5, 0.5, 0.5,
+        ))))
+    }
+
+    fn triangle() -> Triangle {
+        let mesh = TriangleMesh::new(
+            vec![
+                Vec3::new(0., 0., 0.),
+                Vec3::new(2., 0., 0.),
+                Vec3::new(0., 2., 0.),
+            ],
+            vec![[0, 1, 2]],
+            material(),
+        )
+        .with_uvs(vec![(0.2, 0.1), (1., 0.3), (0.4, 0.9)]);
+        Triangle {
+            mesh: Arc::new(mesh),
+            index: 0,
+        }
+    }
+
+    #[test]
+    fn hit_interpolates_the_vertices() {
+        let r = Ray::new(Vec3::new(0.5, 0.5, 3.), Vec3::new(0., 0., -2.), 0.);
+        let rec = triangle().hit(r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 1.5).abs() < 1e-12);
+        assert!((rec.p - Vec3::new(0.5, 0.5, 0.)).magnitude() < 1e-12);
+        assert!((rec.normal - Vec3::new(0., 0., 1.)).magnitude() < 1e-12);
+        assert!(rec.front_face);
+        // barycentric (0.5, 0.25, 0.25)
+        assert!((rec.u - 0.45).abs() < 1e-12);
+        assert!((rec.v - 0.35).abs() < 1e-12);
+        assert_eq!(rec.geometric_normal, rec.normal);
+        // moving along the tangents by the uv differences reaches the other corners
+        let corners = [
+            (Vec3::new(2., 0., 0.), (1., 0.3)),
+            (Vec3::new(0., 2., 0.), (0.4, 0.9)),
+        ];
+        for &(corner, (u, v)) in &corners {
+            let step = (u - 0.2) * rec.dpdu + (v - 0.1) * rec.dpdv;
+            assert!((step - corner).magnitude() < 1e-12);
+        }
+
+        let from_below = Ray::new(Vec3::new(0.5, 0.5, -1.), Vec3::new(0., 0., 1.), 0.);
+        let rec = triangle().hit(from_below, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.normal - Vec3::new(0., 0., -1.)).magnitude() < 1e-12);
+        assert!(!rec.front_face);
+    }
+
+    #[test]
+    fn misses_outside_the_edges_and_interval() {
+        let down = Vec3::new(0., 0., -1.);
+        for &(x, y) in &[(-0.1, 0.5), (0.5, -0.1), (1.1, 1.), (3., 3.)] {
+            let r = Ray::new(Vec3::new(x, y, 1.), down, 0.);
+            assert!(triangle().hit(r, 0.001, f64::INFINITY).is_none());
+        }
+        let r = Ray::new(Vec3::new(0.5, 0.5, 1.), down, 0.);
+        assert!(triangle().hit(r, 0.001, 0.9).is_none());
+        assert!(triangle().hit(r, 1.1, f64::INFINITY).is_none());
+        let parallel = Ray::new(Vec3::new(-1., 0.5, 0.), Vec3::new(1., 0., 0.), 0.);
+        assert!(triangle().hit(parallel, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn bvh_matches_the_triangle_list() {
+        // a bumpy 8x8 grid of quads
+        let n = 8;
+        let mut vertices = vec![];
+        for j in 0..=n {
+            for i in 0..=n {
+                let height = ((i * 7 + j * 3) % 5) as f64 * 0.1;
+                vertices.push(Vec3::new(i as f64, height, j as f64));
+            }
+        }
+        let mut indices = vec![];
+        for j in 0..n {
+            for i in 0..n {
+                let corner = j * (n + 1) + i;
+                indices.push([corner, corner + 1, corner + n + 1]);
+                indices.push([corner + 1, corner + n + 2, corner + n + 1]);
+            }
+        }
+        let mesh = Arc::new(TriangleMesh::new(vertices, indices, material()));
+        let list = TriangleMesh::triangles(&mesh);
+        let bvh = TriangleMesh::build_bvh(&mesh, 0., 1.);
+        for i in 0..40 {
+            for j in 0..40 {
+                let target = Vec3::new(i as f64 * 0.21 - 0.1, 0., j as f64 * 0.2 + 0.05);
+                let origin = Vec3::new(4., 5., 4.);
+                let r = Ray::new(origin, target - origin, 0.);
+                let expected = list.hit(r, 0.001, f64::INFINITY).map(|h| h.t);
+                assert_eq!(bvh.hit(r, 0.001, f64::INFINITY).map(|h| h.t), expected);
+            }
+        }
+    }
+}
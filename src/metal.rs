@@ -19,7 +19,7 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3, bool)> {
         let reflected = reflect(r_in.direction().norm(), hit.normal);
         let scattered = Ray::new(
             hit.p,
@@ -27,7 +27,7 @@ impl Material for Metal {
             r_in.time(),
         );
         if scattered.direction().dot(hit.normal) > 0. {
-            Some((scattered, self.albedo))
+            Some((scattered, self.albedo, true))
         } else {
             None
         }
@@ -0,0 +1,231 @@
+use crate::vec3::Vec3;
+use std::f64::consts::PI;
+
+// Anisotropic GGX (Trowbridge-Reitz) microfacet distribution. All directions are in the
+// local shading frame (see Onb), with the macro surface normal along +z and both directions
+// pointing away from the surface.
+
+// below this alpha the surface is treated as a perfect mirror
+pub const MIN_ALPHA: f64 = 1e-4;
+
+// perceptual roughness in [0, 1] to the distribution's alpha
+pub fn roughness_to_alpha(roughness: f64) -> f64 {
+    roughness * roughness
+}
+
+// distribution of microfacet normals m
+pub fn ggx_d(m: Vec3, alpha_x: f64, alpha_y: f64) -> f64 {
+    if m.z() <= 0. {
+        return 0.;
+    }
+    let e = (m.x() / alpha_x).powi(2) + (m.y() / alpha_y).powi(2) + m.z().powi(2);
+    1. / (PI * alpha_x * alpha_y * e * e)
+}
+
+fn smith_lambda(w: Vec3, alpha_x: f64, alpha_y: f64) -> f64 {
+    let tan_2 = ((alpha_x * w.x()).powi(2) + (alpha_y * w.y()).powi(2)) / w.z().powi(2);
+    0.5 * (-1. + (1. + tan_2).sqrt())
+}
+
+// Smith masking for one direction
+pub fn smith_g1(w: Vec3, alpha_x: f64, alpha_y: f64) -> f64 {
+    1. / (1. + smith_lambda(w, alpha_x, alpha_y))
+}
+
+// height-correlated Smith masking-shadowing
+pub fn smith_g2(wo: Vec3, wi: Vec3, alpha_x: f64, alpha_y: f64) -> f64 {
+    1. / (1. + smith_lambda(wo, alpha_x, alpha_y) + smith_lambda(wi, alpha_x, alpha_y))
+}
+
+// Samples a microfacet normal from the distribution of normals visible from wo
+// (Heitz 2018, "Sampling the GGX Distribution of Visible Normals"); u1 and u2 are in [0, 1).
+pub fn sample_visible_normal(wo: Vec3, alpha_x: f64, alpha_y: f64, u1: f64, u2: f64) -> Vec3 {
+    // stretch the view direction so the distribution becomes a hemisphere
+    let vh = Vec3::new(alpha_x * wo.x(), alpha_y * wo.y(), wo.z()).norm();
+    let len_sq = vh.x() * vh.x() + vh.y() * vh.y();
+    let t1_axis = if len_sq > 0. {
+        Vec3::new(-vh.y(), vh.x(), 0.) / len_sq.sqrt()
+    } else {
+        Vec3::new(1., 0., 0.)
+    };
+    let t2_axis = vh.cross(t1_axis);
+
+    // uniformly sample the projected area of the hemisphere
+    let r = u1.sqrt();
+    let phi = 2. * PI * u2;
+    let t1 = r * phi.cos();
+    let t2 = r * phi.sin();
+    let s = 0.5 * (1. + vh.z());
+    let t2 = (1. - s) * (1. - t1 * t1).sqrt() + s * t2;
+    let nh = t1 * t1_axis + t2 * t2_axis + (1. - t1 * t1 - t2 * t2).max(0.).sqrt() * vh;
+
+    // and unstretch
+    Vec3::new(alpha_x * nh.x(), alpha_y * nh.y(), nh.z().max(0.)).norm()
+}
+
+// density of sample_visible_normal choosing m
+pub fn visible_normal_pdf(wo: Vec3, m: Vec3, alpha_x: f64, alpha_y: f64) -> f64 {
+    if wo.z() <= 0. {
+        return 0.;
+    }
+    smith_g1(wo, alpha_x, alpha_y) * wo.dot(m).max(0.) * ggx_d(m, alpha_x, alpha_y) / wo.z()
+}
+
+// Exact unpolarized Fresnel reflectance of a conductor with complex refractive index
+// eta + i*k, evaluated per color channel
+pub fn fresnel_conductor(cos_theta: f64, eta: Vec3, k: Vec3) -> Vec3 {
+    let cos_2 = cos_theta.min(1.).powi(2);
+    let sin_2 = 1. - cos_2;
+    let channel = |eta: f64, k: f64| {
+        let t0 = eta * eta - k * k - sin_2;
+        let a2_plus_b2 = (t0 * t0 + 4. * eta * eta * k * k).sqrt();
+        let t1 = a2_plus_b2 + cos_2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.).sqrt();
+        let t2 = 2. * cos_theta * a;
+        let r_s = (t1 - t2) / (t1 + t2);
+        let t3 = cos_2 * a2_plus_b2 + sin_2 * sin_2;
+        let t4 = t2 * sin_2;
+        let r_p = r_s * (t3 - t4) / (t3 + t4);
+        0.5 * (r_p + r_s)
+    };
+    Vec3::new(
+        channel(eta.x(), k.x()),
+        channel(eta.y(), k.y()),
+        channel(eta.z(), k.z()),
+    )
+}
+
+// Exact unpolarized Fresnel reflectance of a dielectric interface; eta is the refractive
+// index on the far side divided by the index on the side of the incoming light
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let cos_theta_i = cos_theta_i.clamp(-1., 1.).abs();
+    let sin_2_theta_t = (1. - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin_2_theta_t >= 1. {
+        // total internal reflection
+        return 1.;
+    }
+    let cos_theta_t = (1. - sin_2_theta_t).sqrt();
+    let r_s = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    let r_p = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    0.5 * (r_s * r_s + r_p * r_p)
+}
+
+// (1 - cos)^5, the angular falloff in Schlick's Fresnel approximation
+pub fn schlick_weight(cos_theta: f64) -> f64 {
+    (1. - cos_theta.clamp(0., 1.)).powi(5)
+}
+
+// Schlick's approximation of Fresnel reflectance, given the reflectance at normal incidence
+pub fn fresnel_schlick(f0: Vec3, cos_theta: f64) -> Vec3 {
+    let w = schlick_weight(cos_theta);
+    f0 * (1. - w) + Vec3::new(w, w, w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // integral of f over the hemisphere around +z, by the midpoint rule in (cos theta, phi)
+    fn integrate_hemisphere<F: Fn(Vec3) -> f64>(f: F) -> f64 {
+        let (n_cos, n_phi) = (1000, 400);
+        let mut sum = 0.;
+        for i in 0..n_cos {
+            let cos_theta = (i as f64 + 0.5) / n_cos as f64;
+            let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+            for j in 0..n_phi {
+                let phi = 2. * PI * (j as f64 + 0.5) / n_phi as f64;
+                sum += f(Vec3::new(
+                    sin_theta * phi.cos(),
+                    sin_theta * phi.sin(),
+                    cos_theta,
+                ));
+            }
+        }
+        sum * 2. * PI / (n_cos * n_phi) as f64
+    }
+
+    const ALPHAS: [(f64, f64); 3] = [(0.5, 0.5), (0.3, 0.3), (0.2, 0.6)];
+
+    fn outgoing() -> Vec<Vec3> {
+        vec![
+            Vec3::new(0., 0., 1.),
+            Vec3::new(0.5, 0.2, 0.8).norm(),
+            Vec3::new(-0.9, 0.3, 0.2).norm(),
+        ]
+    }
+
+    #[test]
+    fn projected_normals_cover_the_surface_once() {
+        for &(alpha_x, alpha_y) in &ALPHAS {
+            let area = integrate_hemisphere(|m| ggx_d(m, alpha_x, alpha_y) * m.z());
+            assert!((area - 1.).abs() < 1e-3, "{}", area);
+        }
+    }
+
+    #[test]
+    fn visible_normal_pdf_integrates_to_one() {
+        for &(alpha_x, alpha_y) in &ALPHAS {
+            for wo in outgoing() {
+                let total = integrate_hemisphere(|m| visible_normal_pdf(wo, m, alpha_x, alpha_y));
+                assert!((total - 1.).abs() < 1e-2, "{}", total);
+            }
+        }
+    }
+
+    #[test]
+    fn visible_normals_are_sampled_with_their_pdf() {
+        let n = 400;
+        for &(alpha_x, alpha_y) in &ALPHAS {
+            for wo in outgoing() {
+                // compare the mean sampled normal with the one the pdf predicts
+                let mut mean = Vec3::default();
+                for i in 0..n {
+                    for j in 0..n {
+                        let (u1, u2) = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                        mean += sample_visible_normal(wo, alpha_x, alpha_y, u1, u2);
+                    }
+                }
+                let mean = mean / (n * n) as f64;
+                for axis in 0..3 {
+                    let expected = integrate_hemisphere(|m| {
+                        m[axis] * visible_normal_pdf(wo, m, alpha_x, alpha_y)
+                    });
+                    assert!(
+                        (mean[axis] - expected).abs() < 1e-2,
+                        "{:?} {}",
+                        mean,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn masking_is_complete_at_normal_incidence() {
+        let up = Vec3::new(0., 0., 1.);
+        assert!((smith_g1(up, 0.3, 0.7) - 1.).abs() < 1e-12);
+        for wo in outgoing() {
+            let wi = Vec3::new(-wo.x(), wo.y(), wo.z());
+            assert!(smith_g2(wo, wi, 0.3, 0.3) <= smith_g1(wo, 0.3, 0.3));
+        }
+    }
+
+    #[test]
+    fn fresnel_reflectance() {
+        for &eta in &[1.33_f64, 1.5, 2.4] {
+            let normal = ((eta - 1.) / (eta + 1.)).powi(2);
+            assert!((fresnel_dielectric(1., eta) - normal).abs() < 1e-12);
+            assert!((fresnel_dielectric(1., 1. / eta) - normal).abs() < 1e-12);
+            // from the dense side, past the critical angle
+            assert_eq!(fresnel_dielectric(0.1, 1. / eta), 1.);
+            // a conductor with no absorption is a dielectric
+            for &cos_theta in &[1., 0.7, 0.3, 0.05] {
+                let conductor =
+                    fresnel_conductor(cos_theta, Vec3::new(eta, eta, eta), Vec3::default());
+                assert!((conductor.x() - fresnel_dielectric(cos_theta, eta)).abs() < 1e-9);
+            }
+        }
+        assert!((fresnel_dielectric(0., 1.5) - 1.).abs() < 1e-12);
+    }
+}
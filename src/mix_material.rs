@@ -0,0 +1,100 @@
+use crate::{
+    hittable::HitRecord, material::Material, medium::HomogeneousMedium, ray::Ray, texture::Texture,
+    utils::random_in_01, vec3::Vec3,
+};
+use std::sync::Arc;
+
+// Blends two materials with a mask texture (its first channel): 0 gives `first`, 1 gives
+// `second`. Each hit picks one of them at random with the mask as probability, so e.g. a
+// NoiseTexture can scatter rust patches over a Conductor.
+#[derive(Clone, Debug)]
+pub struct MixMaterial {
+    first: Arc<dyn Material>,
+    second: Arc<dyn Material>,
+    mask: Arc<dyn Texture>,
+}
+
+impl MixMaterial {
+    pub fn new(
+        first: Arc<dyn Material>,
+        second: Arc<dyn Material>,
+        mask: Arc<dyn Texture>,
+    ) -> Self {
+        Self {
+            first,
+            second,
+            mask,
+        }
+    }
+
+    // how much of `second` there is at the hit point
+    fn weight(&self, u: f64, v: f64, p: Vec3) -> f64 {
+        self.mask.value(u, v, p).x().clamp(0., 1.)
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3, bool)> {
+        // the probability of picking a material cancels its weight in the mix
+        if random_in_01() <= self.weight(hit.u, hit.v, hit.p) {
+            self.second.scatter(r_in, hit)
+        } else {
+            self.first.scatter(r_in, hit)
+        }
+    }
+
+    fn eval(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        let w = self.weight(hit.u, hit.v, hit.p);
+        (1. - w) * self.first.eval(r_in, hit, direction)
+            + w * self.second.eval(r_in, hit, direction)
+    }
+
+    fn scattering_pdf(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        let w = self.weight(hit.u, hit.v, hit.p);
+        (1. - w) * self.first.scattering_pdf(r_in, hit, direction)
+            + w * self.second.scattering_pdf(r_in, hit, direction)
+    }
+
+    // a medium can't vary over the surface, so the first material that has one wins
+    fn interior_medium(&self) -> Option<HomogeneousMedium> {
+        self.first
+            .interior_medium()
+            .or_else(|| self.second.interior_medium())
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
+        let w = self.weight(u, v, p);
+        (1. - w) * self.first.emitted(u, v, p) + w * self.second.emitted(u, v, p)
+    }
+
+    fn emitted_spectral(&self, u: f64, v: f64, p: Vec3, lambda: f64) -> f64 {
+        let w = self.weight(u, v, p);
+        (1. - w) * self.first.emitted_spectral(u, v, p, lambda)
+            + w * self.second.emitted_spectral(u, v, p, lambda)
+    }
+
+    fn is_wavelength_dependent(&self) -> bool {
+        self.first.is_wavelength_dependent() || self.second.is_wavelength_dependent()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        diffuse::Lambertian, material::tests::assert_consistent, metal::Metal, texture::SolidColor,
+    };
+
+    #[test]
+    fn eval_matches_scatter_with_a_specular_half() {
+        let grey = |value| Arc::new(SolidColor::new_from_rgb(value, value, value));
+        let mix = MixMaterial::new(
+            Arc::new(Lambertian::new(grey(0.7))),
+            Arc::new(Metal::new(Vec3::new(0.9, 0.9, 0.9), 0.)),
+            grey(0.3),
+        );
+        for &d in &[Vec3::new(0., 0., -1.), Vec3::new(0.6, 0., -0.8)] {
+            assert_consistent(Arc::new(mix.clone()), d);
+        }
+    }
+}
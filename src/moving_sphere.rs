@@ -3,6 +3,7 @@ use crate::{
     hittable::{HitRecord, Hittable},
     material::Material,
     ray::Ray,
+    sphere::sphere_hit_record,
     vec3::Vec3,
 };
 use std::sync::Arc;
@@ -56,10 +57,11 @@ impl Hittable for MovingSphere {
                 let root_1 = (-half_b - disc_sqrt) / a;
                 if root_1 > t_min && root_1 < t_max {
                     let p = r.at(root_1);
-                    return Some(HitRecord::new(
+                    return Some(sphere_hit_record(
                         root_1,
                         p,
-                        (p - center) / self.radius,
+                        center,
+                        self.radius,
                         r,
                         self.material.clone(),
                     ));
@@ -68,10 +70,11 @@ impl Hittable for MovingSphere {
             let root_2 = (-half_b + disc_sqrt) / a;
             if root_2 > t_min && root_2 < t_max {
                 let p = r.at(root_2);
-                Some(HitRecord::new(
+                Some(sphere_hit_record(
                     root_2,
                     p,
-                    (p - center) / self.radius,
+                    center,
+                    self.radius,
                     r,
                     self.material.clone(),
                 ))
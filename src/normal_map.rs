@@ -0,0 +1,217 @@
+use crate::{
+    hittable::HitRecord, material::Material, medium::HomogeneousMedium, onb::Onb, ray::Ray,
+    texture::Texture, vec3::Vec3,
+};
+use std::sync::Arc;
+
+// world-space distance over which BumpMap takes finite differences of the height
+const BUMP_STEP: f64 = 1e-4;
+
+// Surface tangents at the hit, falling back to an arbitrary frame around the normal for
+// surfaces without a parameterization (or at its singularities, like a sphere's poles)
+fn tangents(hit: &HitRecord, n: Vec3) -> (Vec3, Vec3) {
+    if hit.dpdu.length_squared() > 1e-16 && hit.dpdv.length_squared() > 1e-16 {
+        (hit.dpdu, hit.dpdv)
+    } else {
+        let onb = Onb::build_from_w(n);
+        (onb.u(), onb.v())
+    }
+}
+
+// Perturbs the shading normal of another material with a tangent-space normal map, usually
+// an ImageTexture where (r, g, b) = (n + 1) / 2 and blue points away from the surface. The
+// geometric normal is left alone.
+#[derive(Clone, Debug)]
+pub struct NormalMap {
+    material: Arc<dyn Material>,
+    map: Arc<dyn Texture>,
+}
+
+impl NormalMap {
+    pub fn new(material: Arc<dyn Material>, map: Arc<dyn Texture>) -> Self {
+        Self { material, map }
+    }
+
+    fn shade(&self, hit: &HitRecord) -> HitRecord {
+        let n = hit.outward_normal();
+        let (dpdu, dpdv) = tangents(hit, n);
+        // Gram-Schmidt, with the bitangent flipped for mirrored uvs
+        let tangent = (dpdu - n.dot(dpdu) * n).norm();
+        let mut bitangent = n.cross(tangent);
+        if bitangent.dot(dpdv) < 0. {
+            bitangent = -bitangent;
+        }
+
+        let c = self.map.value(hit.u, hit.v, hit.p);
+        let local = Vec3::new(2. * c.x() - 1., 2. * c.y() - 1., 2. * c.z() - 1.);
+        let mut shaded = hit.clone();
+        shaded.set_shading_normal(
+            (local.x() * tangent + local.y() * bitangent + local.z() * n).norm(),
+        );
+        shaded
+    }
+}
+
+// Bumps the shading normal of another material as if the surface were displaced along its
+// normal by scale times a height texture (its first channel), e.g. a NoiseTexture. The
+// geometric normal is left alone.
+#[derive(Clone, Debug)]
+pub struct BumpMap {
+    material: Arc<dyn Material>,
+    height: Arc<dyn Texture>,
+    scale: f64,
+}
+
+impl BumpMap {
+    pub fn new(material: Arc<dyn Material>, height: Arc<dyn Texture>, scale: f64) -> Self {
+        Self {
+            material,
+            height,
+            scale,
+        }
+    }
+
+    fn displacement(&self, u: f64, v: f64, p: Vec3) -> f64 {
+        self.scale * self.height.value(u, v, p).x()
+    }
+
+    fn shade(&self, hit: &HitRecord) -> HitRecord {
+        let n = hit.outward_normal();
+        let (dpdu, dpdv) = tangents(hit, n);
+        let du = BUMP_STEP / dpdu.magnitude();
+        let dv = BUMP_STEP / dpdv.magnitude();
+        let d = self.displacement(hit.u, hit.v, hit.p);
+        let d_u = self.displacement(hit.u + du, hit.v, hit.p + du * dpdu);
+        let d_v = self.displacement(hit.u, hit.v + dv, hit.p + dv * dpdv);
+
+        // tangents of the displaced surface p + d * n, ignoring the change of n itself
+        let bumped_dpdu = dpdu + ((d_u - d) / du) * n;
+        let bumped_dpdv = dpdv + ((d_v - d) / dv) * n;
+        let mut bumped = bumped_dpdu.cross(bumped_dpdv).norm();
+        if bumped.dot(n) < 0. {
+            bumped = -bumped;
+        }
+        let mut shaded = hit.clone();
+        shaded.set_shading_normal(bumped);
+        shaded
+    }
+}
+
+// A direction on one side of the shading normal's surface but the other side of the true
+// surface would reflect light through the object or transmit it back out, so the wrappers
+// drop it
+fn same_side(hit: &HitRecord, shaded: &HitRecord, direction: Vec3) -> bool {
+    direction.dot(shaded.normal) * direction.dot(hit.geometric_normal) > 0.
+}
+
+// Material for a wrapper with a `material` field and a `shade` method: the wrapped material
+// sees a copy of the hit record with the new normal, and everything else is passed through
+macro_rules! shading_normal_material {
+    ($wrapper:ty) => {
+        impl Material for $wrapper {
+            fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3, bool)> {
+                let shaded = self.shade(hit);
+                self.material
+                    .scatter(r_in, &shaded)
+                    .filter(|(scattered, _, _)| same_side(hit, &shaded, scattered.direction()))
+            }
+
+            fn eval(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+                let shaded = self.shade(hit);
+                if !same_side(hit, &shaded, direction) {
+                    return Vec3::default();
+                }
+                self.material.eval(r_in, &shaded, direction)
+            }
+
+            fn scattering_pdf(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+                let shaded = self.shade(hit);
+                if !same_side(hit, &shaded, direction) {
+                    return 0.;
+                }
+                self.material.scattering_pdf(r_in, &shaded, direction)
+            }
+
+            fn interior_medium(&self) -> Option<HomogeneousMedium> {
+                self.material.interior_medium()
+            }
+
+            fn emitted(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
+                self.material.emitted(u, v, p)
+            }
+
+            fn emitted_spectral(&self, u: f64, v: f64, p: Vec3, lambda: f64) -> f64 {
+                self.material.emitted_spectral(u, v, p, lambda)
+            }
+
+            fn is_wavelength_dependent(&self) -> bool {
+                self.material.is_wavelength_dependent()
+            }
+        }
+    };
+}
+
+shading_normal_material!(NormalMap);
+shading_normal_material!(BumpMap);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dielectric::Dielectric, diffuse::Lambertian, material::tests::assert_consistent,
+        metal::Metal, texture::SolidColor,
+    };
+
+    // a constant map tilting the normal towards +x
+    fn tilted(material: Arc<dyn Material>) -> Arc<dyn Material> {
+        let tilt = Vec3::new(0.6, 0., 0.8);
+        let color = 0.5 * (tilt + Vec3::new(1., 1., 1.));
+        Arc::new(NormalMap::new(material, Arc::new(SolidColor::new(color))))
+    }
+
+    // like material::tests::assert_consistent, the surface is z = 0 with tangent +x
+    fn hit_from(incoming: Vec3, material: Arc<dyn Material>) -> (Ray, HitRecord) {
+        let r_in = Ray::new(-incoming, incoming, 0.);
+        let hit = HitRecord::new(1., Vec3::default(), Vec3::new(0., 0., 1.), r_in, material)
+            .with_uv(0.5, 0.5, Vec3::new(1., 0., 0.), Vec3::new(0., 1., 0.));
+        (r_in, hit)
+    }
+
+    #[test]
+    fn reflections_stay_above_the_surface() {
+        let material = tilted(Arc::new(Metal::new(Vec3::new(0.9, 0.9, 0.9), 0.)));
+        // mirrored about the tilted normal, this direction would go into the surface
+        let (r_in, hit) = hit_from(Vec3::new(0.6, 0., -0.8), material.clone());
+        assert!(material.scatter(r_in, &hit).is_none());
+
+        let fuzzy = tilted(Arc::new(Metal::new(Vec3::new(0.9, 0.9, 0.9), 0.5)));
+        for i in 0..100 {
+            let angle = 0.06 * i as f64;
+            let (r_in, hit) = hit_from(Vec3::new(angle.cos(), angle.sin(), -0.5), fuzzy.clone());
+            if let Some((scattered, _, _)) = fuzzy.scatter(r_in, &hit) {
+                assert!(scattered.direction().z() > 0.);
+            }
+        }
+    }
+
+    #[test]
+    fn tilted_lambertian_is_consistent() {
+        let lambertian = Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(
+            0.8, 0.6, 0.4,
+        ))));
+        assert_consistent(tilted(lambertian), Vec3::new(0.3, 0.1, -1.).norm());
+    }
+
+    #[test]
+    fn glass_still_transmits() {
+        let material = tilted(Arc::new(Dielectric::new(1.5)));
+        let (r_in, hit) = hit_from(Vec3::new(0., 0., -1.), material.clone());
+        let mut transmitted = 0;
+        for _ in 0..1000 {
+            if let Some((scattered, _, _)) = material.scatter(r_in, &hit) {
+                transmitted += (scattered.direction().z() < 0.) as usize;
+            }
+        }
+        assert!(transmitted > 500);
+    }
+}
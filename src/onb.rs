@@ -0,0 +1,52 @@
+use crate::vec3::Vec3;
+
+// Orthonormal basis, used to move directions into a local shading frame where the surface
+// normal is the z axis
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct Onb {
+    axis: [Vec3; 3],
+}
+
+impl Onb {
+    pub fn build_from_w(n: Vec3) -> Self {
+        let w = n.norm();
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0., 1., 0.)
+        } else {
+            Vec3::new(1., 0., 0.)
+        };
+        let v = w.cross(a).norm();
+        let u = w.cross(v);
+        Self { axis: [u, v, w] }
+    }
+    // A frame around n whose u axis follows the tangent as closely as possible, so that
+    // directions in it are tied to the surface. Falls back to build_from_w when the tangent
+    // is missing or parallel to n.
+    pub fn build_from_w_and_u(n: Vec3, tangent: Vec3) -> Self {
+        let w = n.norm();
+        let u = tangent - tangent.dot(w) * w;
+        if u.length_squared() <= 1e-12 * tangent.length_squared() {
+            return Self::build_from_w(n);
+        }
+        let u = u.norm();
+        let v = w.cross(u);
+        Self { axis: [u, v, w] }
+    }
+    pub fn u(&self) -> Vec3 {
+        self.axis[0]
+    }
+    pub fn v(&self) -> Vec3 {
+        self.axis[1]
+    }
+    pub fn w(&self) -> Vec3 {
+        self.axis[2]
+    }
+    // local coordinates to world space
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        a.x() * self.u() + a.y() * self.v() + a.z() * self.w()
+    }
+    // world space to local coordinates
+    pub fn to_local(&self, a: Vec3) -> Vec3 {
+        Vec3::new(a.dot(self.u()), a.dot(self.v()), a.dot(self.w()))
+    }
+}
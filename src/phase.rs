@@ -0,0 +1,224 @@
+use crate::{onb::Onb, utils::random_in_01, vec3::Vec3};
+use dyn_clone::DynClone;
+use std::{f64::consts::PI, fmt::Debug};
+
+// How a medium redistributes light when it scatters. Directions are unit vectors: `incoming`
+// is the direction the light was travelling and `outgoing` the direction it leaves in, so
+// forward scattering has incoming.dot(outgoing) near 1.
+pub trait PhaseFunction: Debug + DynClone + Sync + Send {
+    // fraction of the scattered light going into outgoing, per steradian; integrates to 1
+    fn eval(&self, incoming: Vec3, outgoing: Vec3) -> f64;
+
+    // new direction for light travelling along incoming
+    fn sample(&self, incoming: Vec3) -> Vec3;
+
+    // density with which sample() picks outgoing, for weighting against light sampling. All
+    // the phase functions here are sampled exactly, so it is the phase function itself.
+    fn pdf(&self, incoming: Vec3, outgoing: Vec3) -> f64 {
+        self.eval(incoming, outgoing)
+    }
+}
+
+// a direction at angle acos(cos_theta) to axis, uniformly around it
+fn around(axis: Vec3, cos_theta: f64) -> Vec3 {
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * PI * random_in_01();
+    Onb::build_from_w(axis).local(Vec3::new(
+        sin_theta * phi.cos(),
+        sin_theta * phi.sin(),
+        cos_theta,
+    ))
+}
+
+// Scatters equally in all directions
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct Isotropic;
+
+impl PhaseFunction for Isotropic {
+    fn eval(&self, _incoming: Vec3, _outgoing: Vec3) -> f64 {
+        1. / (4. * PI)
+    }
+
+    fn sample(&self, incoming: Vec3) -> Vec3 {
+        around(incoming, 1. - 2. * random_in_01())
+    }
+}
+
+// Henyey–Greenstein: a one-parameter fit to scattering by larger particles. g is the average
+// cosine of the scattering angle; g > 0 scatters forwards (clouds, around 0.85), g < 0
+// backwards, and g = 0 is isotropic.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct HenyeyGreenstein {
+    g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        // at exactly ±1 it becomes a delta distribution
+        Self {
+            g: g.clamp(-0.999, 0.999),
+        }
+    }
+
+    pub fn g(&self) -> f64 {
+        self.g
+    }
+
+    fn eval_cos(&self, cos_theta: f64) -> f64 {
+        let g = self.g;
+        let denominator = 1. + g * g - 2. * g * cos_theta;
+        (1. - g * g) / (4. * PI * denominator * denominator.sqrt())
+    }
+
+    // inverts the cumulative distribution of the scattering angle
+    fn sample_cos(&self, u: f64) -> f64 {
+        let g = self.g;
+        if g.abs() < 1e-3 {
+            return 1. - 2. * u;
+        }
+        let s = (1. - g * g) / (1. - g + 2. * g * u);
+        ((1. + g * g - s * s) / (2. * g)).clamp(-1., 1.)
+    }
+}
+
+impl PhaseFunction for HenyeyGreenstein {
+    fn eval(&self, incoming: Vec3, outgoing: Vec3) -> f64 {
+        self.eval_cos(incoming.dot(outgoing))
+    }
+
+    fn sample(&self, incoming: Vec3) -> Vec3 {
+        around(incoming, self.sample_cos(random_in_01()))
+    }
+}
+
+// Blend of a forward and a backward Henyey–Greenstein lobe, for media such as smoke, skin
+// and dusty air that show both a strong forward peak and some back scattering
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct DoubleHenyeyGreenstein {
+    forward: HenyeyGreenstein,
+    backward: HenyeyGreenstein,
+    // fraction of the light going into the forward lobe
+    weight: f64,
+}
+
+impl DoubleHenyeyGreenstein {
+    pub fn new(g_forward: f64, g_backward: f64, weight: f64) -> Self {
+        Self {
+            forward: HenyeyGreenstein::new(g_forward),
+            backward: HenyeyGreenstein::new(g_backward),
+            weight: weight.clamp(0., 1.),
+        }
+    }
+}
+
+impl PhaseFunction for DoubleHenyeyGreenstein {
+    fn eval(&self, incoming: Vec3, outgoing: Vec3) -> f64 {
+        self.weight * self.forward.eval(incoming, outgoing)
+            + (1. - self.weight) * self.backward.eval(incoming, outgoing)
+    }
+
+    fn sample(&self, incoming: Vec3) -> Vec3 {
+        if random_in_01() <= self.weight {
+            self.forward.sample(incoming)
+        } else {
+            self.backward.sample(incoming)
+        }
+    }
+}
+
+// Scattering by particles much smaller than the wavelength, like the molecules of the air.
+// (The blue of the sky comes from its 1 / wavelength^4 strength, which belongs in the
+// medium's coefficients.)
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct Rayleigh;
+
+impl PhaseFunction for Rayleigh {
+    fn eval(&self, incoming: Vec3, outgoing: Vec3) -> f64 {
+        let cos_theta = incoming.dot(outgoing);
+        3. / (16. * PI) * (1. + cos_theta * cos_theta)
+    }
+
+    fn sample(&self, incoming: Vec3) -> Vec3 {
+        // the cumulative distribution (cos^3 + 3 cos + 4) / 8 = u is a depressed cubic,
+        // solved with Cardano's formula
+        let z = 4. * random_in_01() - 2.;
+        let root = (z * z + 1.).sqrt();
+        let cos_theta = ((z + root).cbrt() + (z - root).cbrt()).clamp(-1., 1.);
+        around(incoming, cos_theta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phase_functions() -> Vec<Box<dyn PhaseFunction>> {
+        vec![
+            Box::new(Isotropic),
+            Box::new(HenyeyGreenstein::new(0.85)),
+            Box::new(HenyeyGreenstein::new(-0.4)),
+            Box::new(HenyeyGreenstein::new(0.0005)),
+            Box::new(DoubleHenyeyGreenstein::new(0.8, -0.3, 0.7)),
+            Box::new(Rayleigh),
+        ]
+    }
+
+    // integral of f(cos theta) * p over the sphere, for a phase function p that only depends
+    // on the scattering angle
+    fn integrate<F: Fn(f64) -> f64>(phase: &dyn PhaseFunction, f: F) -> f64 {
+        let incoming = Vec3::new(0., 0., 1.);
+        let steps = 100_000;
+        let mut sum = 0.;
+        for i in 0..steps {
+            let cos_theta = -1. + 2. * (i as f64 + 0.5) / steps as f64;
+            let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+            let outgoing = Vec3::new(sin_theta, 0., cos_theta);
+            sum += f(cos_theta) * phase.eval(incoming, outgoing);
+        }
+        sum * 4. * PI / steps as f64
+    }
+
+    #[test]
+    fn phase_functions_integrate_to_one() {
+        for phase in phase_functions() {
+            let total = integrate(phase.as_ref(), |_| 1.);
+            assert!((total - 1.).abs() < 1e-3, "{:?}: {}", phase, total);
+        }
+    }
+
+    #[test]
+    fn henyey_greenstein_mean_cosine_is_g() {
+        for &g in &[-0.5, 0., 0.3, 0.9] {
+            let mean = integrate(&HenyeyGreenstein::new(g), |cos_theta| cos_theta);
+            assert!((mean - g).abs() < 1e-3, "{} != {}", mean, g);
+        }
+    }
+
+    #[test]
+    fn samples_follow_eval() {
+        let incoming = Vec3::new(0.3, -0.5, 0.8).norm();
+        let samples = 200_000;
+        for phase in phase_functions() {
+            let (mut mean, mut mean_square) = (0., 0.);
+            for _ in 0..samples {
+                let outgoing = phase.sample(incoming);
+                assert!((outgoing.length_squared() - 1.).abs() < 1e-9);
+                let cos_theta = incoming.dot(outgoing);
+                mean += cos_theta;
+                mean_square += cos_theta * cos_theta;
+            }
+            let expected = integrate(phase.as_ref(), |cos_theta| cos_theta);
+            let expected_square = integrate(phase.as_ref(), |cos_theta| cos_theta * cos_theta);
+            assert!(
+                (mean / samples as f64 - expected).abs() < 0.01,
+                "{:?}",
+                phase
+            );
+            assert!(
+                (mean_square / samples as f64 - expected_square).abs() < 0.01,
+                "{:?}",
+                phase
+            );
+        }
+    }
+}
@@ -0,0 +1,474 @@
+use crate::{
+    hittable::HitRecord,
+    material::Material,
+    microfacet::{
+        fresnel_dielectric, fresnel_schlick, ggx_d, roughness_to_alpha, sample_visible_normal,
+        schlick_weight, smith_g1, smith_g2, visible_normal_pdf, MIN_ALPHA,
+    },
+    onb::Onb,
+    ray::Ray,
+    texture::{SolidColor, Texture},
+    utils::random_in_01,
+    vec3::Vec3,
+};
+use std::{f64::consts::PI, sync::Arc};
+
+// Disney's "principled" BSDF (Burley 2012 and 2015): one material whose artist-friendly
+// parameters cover plastics, metals, glass, cloth sheen and lacquer. Every parameter is a
+// texture; scalar parameters read the texture's first channel and should be in [0, 1].
+#[derive(Clone, Debug)]
+pub struct Principled {
+    base_color: Arc<dyn Texture>,
+    metallic: Arc<dyn Texture>,
+    roughness: Arc<dyn Texture>,
+    // strength of the dielectric highlight; 0.5 is a refractive index of 1.5
+    specular: Arc<dyn Texture>,
+    specular_tint: Arc<dyn Texture>,
+    sheen: Arc<dyn Texture>,
+    sheen_tint: Arc<dyn Texture>,
+    clearcoat: Arc<dyn Texture>,
+    clearcoat_gloss: Arc<dyn Texture>,
+    transmission: Arc<dyn Texture>,
+    // blends the diffuse lobe towards Burley's flattened subsurface approximation
+    subsurface: Arc<dyn Texture>,
+    // used by the transmission lobe
+    refractive_index: f64,
+}
+
+fn constant(value: f64) -> Arc<dyn Texture> {
+    Arc::new(SolidColor::new_from_rgb(value, value, value))
+}
+
+impl Principled {
+    pub fn new(base_color: Arc<dyn Texture>) -> Self {
+        Self {
+            base_color,
+            metallic: constant(0.),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.),
+            sheen: constant(0.),
+            sheen_tint: constant(0.5),
+            clearcoat: constant(0.),
+            clearcoat_gloss: constant(1.),
+            transmission: constant(0.),
+            subsurface: constant(0.),
+            refractive_index: 1.5,
+        }
+    }
+    pub fn with_metallic(mut self, metallic: Arc<dyn Texture>) -> Self {
+        self.metallic = metallic;
+        self
+    }
+    pub fn with_roughness(mut self, roughness: Arc<dyn Texture>) -> Self {
+        self.roughness = roughness;
+        self
+    }
+    pub fn with_specular(mut self, specular: Arc<dyn Texture>, tint: Arc<dyn Texture>) -> Self {
+        self.specular = specular;
+        self.specular_tint = tint;
+        self
+    }
+    pub fn with_sheen(mut self, sheen: Arc<dyn Texture>, tint: Arc<dyn Texture>) -> Self {
+        self.sheen = sheen;
+        self.sheen_tint = tint;
+        self
+    }
+    pub fn with_clearcoat(mut self, clearcoat: Arc<dyn Texture>, gloss: Arc<dyn Texture>) -> Self {
+        self.clearcoat = clearcoat;
+        self.clearcoat_gloss = gloss;
+        self
+    }
+    pub fn with_transmission(
+        mut self,
+        transmission: Arc<dyn Texture>,
+        refractive_index: f64,
+    ) -> Self {
+        self.transmission = transmission;
+        self.refractive_index = refractive_index;
+        self
+    }
+    pub fn with_subsurface(mut self, subsurface: Arc<dyn Texture>) -> Self {
+        self.subsurface = subsurface;
+        self
+    }
+
+    // looks up all the textures at the hit point
+    fn parameters(&self, hit: &HitRecord) -> Parameters {
+        let scalar =
+            |texture: &Arc<dyn Texture>| texture.value(hit.u, hit.v, hit.p).x().clamp(0., 1.);
+        let base_color = self.base_color.value(hit.u, hit.v, hit.p);
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness);
+        let transmission = scalar(&self.transmission);
+
+        // hue and saturation of the base color, without its brightness
+        let tint = if luminance(base_color) > 0. {
+            base_color / luminance(base_color)
+        } else {
+            Vec3::new(1., 1., 1.)
+        };
+        let white = Vec3::new(1., 1., 1.);
+        let specular_color = lerp(
+            0.08 * scalar(&self.specular) * lerp(white, tint, scalar(&self.specular_tint)),
+            base_color,
+            metallic,
+        );
+        let sheen_color = scalar(&self.sheen) * lerp(white, tint, scalar(&self.sheen_tint));
+
+        Parameters {
+            base_color,
+            roughness,
+            alpha: roughness_to_alpha(roughness).max(MIN_ALPHA),
+            specular_color,
+            sheen_color,
+            clearcoat: scalar(&self.clearcoat),
+            clearcoat_alpha: 0.1 + (0.001 - 0.1) * scalar(&self.clearcoat_gloss),
+            diffuse_weight: (1. - metallic) * (1. - transmission),
+            transmission_weight: (1. - metallic) * transmission,
+            subsurface: scalar(&self.subsurface),
+            eta: if hit.front_face {
+                self.refractive_index
+            } else {
+                1. / self.refractive_index
+            },
+        }
+    }
+}
+
+// Disney's approximate luminance
+fn luminance(color: Vec3) -> f64 {
+    0.3 * color.x() + 0.6 * color.y() + 0.1 * color.z()
+}
+
+fn lerp(a: Vec3, b: Vec3, t: f64) -> Vec3 {
+    (1. - t) * a + t * b
+}
+
+// Generalized Trowbridge-Reitz with gamma = 1, used for the clearcoat's long-tailed highlight
+fn gtr1(m: Vec3, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    let t = 1. + (a2 - 1.) * m.z() * m.z();
+    (a2 - 1.) / (PI * a2.ln() * t)
+}
+
+fn sample_gtr1(alpha: f64, u1: f64, u2: f64) -> Vec3 {
+    let a2 = alpha * alpha;
+    let cos_theta = ((1. - a2.powf(1. - u1)) / (1. - a2)).max(0.).sqrt();
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * PI * u2;
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+fn sample_cosine_hemisphere(u1: f64, u2: f64) -> Vec3 {
+    let phi = 2. * PI * u1;
+    let r = u2.sqrt();
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1. - u2).max(0.).sqrt())
+}
+
+// refracts wo through the microfacet m; None on total internal reflection
+fn refract(wo: Vec3, m: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = wo.dot(m);
+    let sin_2_t = (1. - cos_i * cos_i) / (eta * eta);
+    if sin_2_t >= 1. {
+        return None;
+    }
+    let cos_t = (1. - sin_2_t).sqrt();
+    Some(-wo / eta + (cos_i / eta - cos_t) * m)
+}
+
+const CLEARCOAT_ALPHA: f64 = 0.25;
+
+#[derive(Clone, Copy, Debug)]
+struct Parameters {
+    base_color: Vec3,
+    roughness: f64,
+    alpha: f64,
+    // reflectance at normal incidence of the specular lobe
+    specular_color: Vec3,
+    sheen_color: Vec3,
+    clearcoat: f64,
+    clearcoat_alpha: f64,
+    diffuse_weight: f64,
+    transmission_weight: f64,
+    subsurface: f64,
+    eta: f64,
+}
+
+enum Lobe {
+    Diffuse,
+    Specular,
+    Clearcoat,
+    Transmission,
+}
+
+impl Parameters {
+    // Chance of sampling each lobe, roughly in proportion to how much light it reflects
+    // from wo: [diffuse, specular, clearcoat, transmission]
+    fn lobe_probabilities(&self, wo: Vec3) -> [f64; 4] {
+        // Rough microfacets can still refract light that the macro surface would totally
+        // internally reflect, so keep some chance of sampling transmission
+        let dielectric_fresnel = fresnel_dielectric(wo.z(), self.eta).min(0.9);
+        let specular = (1. - self.transmission_weight)
+            * luminance(fresnel_schlick(self.specular_color, wo.z()))
+            + self.transmission_weight * dielectric_fresnel;
+        let weights = [
+            self.diffuse_weight * luminance(self.base_color).max(0.01),
+            specular,
+            0.25 * self.clearcoat * fresnel_schlick(Vec3::new(0.04, 0.04, 0.04), wo.z()).x(),
+            self.transmission_weight * (1. - dielectric_fresnel),
+        ];
+        let total: f64 = weights.iter().sum();
+        if total <= 0. {
+            return [0.; 4];
+        }
+        [
+            weights[0] / total,
+            weights[1] / total,
+            weights[2] / total,
+            weights[3] / total,
+        ]
+    }
+
+    // (f * cos, pdf) for light arriving from wi and leaving along wo
+    fn eval_and_pdf(&self, wo: Vec3, wi: Vec3) -> (Vec3, f64) {
+        if wo.z() <= 0. || wi.z() == 0. {
+            return (Vec3::default(), 0.);
+        }
+        let f_cos = if wi.z() < 0. {
+            self.eval_transmission(wo, wi)
+        } else {
+            self.eval_reflection(wo, wi)
+        };
+        (f_cos, self.pdf(wo, wi))
+    }
+
+    // Density with which scatter() picks wi. Reflection off a tilted microfacet can end up
+    // below the surface and refraction above it, so every lobe counts on both sides.
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        let p = self.lobe_probabilities(wo);
+        let alpha = self.alpha;
+        let mut pdf = 0.;
+        if wi.z() > 0. {
+            pdf += p[0] * wi.z() / PI;
+        }
+        let h = (wo + wi).norm();
+        let o_dot_h = wo.dot(h);
+        if o_dot_h > 0. && h.z() > 0. {
+            let reflection_pdf = visible_normal_pdf(wo, h, alpha, alpha) / (4. * o_dot_h);
+            pdf += p[1] * reflection_pdf
+                + p[2] * gtr1(h, self.clearcoat_alpha) * h.z() / (4. * o_dot_h);
+            // the transmission lobe reflects where light can't get out
+            if refract(wo, h, self.eta).is_none() {
+                pdf += p[3] * reflection_pdf;
+            }
+        }
+        if let Some((m, jacobian)) = self.refraction_half_vector(wo, wi) {
+            pdf += p[3] * visible_normal_pdf(wo, m, alpha, alpha) * jacobian;
+        }
+        pdf
+    }
+
+    // The microfacet normal that refracts wo into wi, and the Jacobian of the refraction
+    // mapping from microfacet normal to direction, or None if no microfacet does
+    fn refraction_half_vector(&self, wo: Vec3, wi: Vec3) -> Option<(Vec3, f64)> {
+        if self.transmission_weight <= 0. {
+            return None;
+        }
+        let eta = self.eta;
+        // generalized half vector for refraction, as in RoughDielectric
+        let mut m = -(wo + eta * wi).norm();
+        if m.z() < 0. {
+            m = -m;
+        }
+        let o_dot_m = wo.dot(m);
+        let i_dot_m = wi.dot(m);
+        if o_dot_m <= 0. || i_dot_m >= 0. {
+            return None;
+        }
+        let jacobian = eta * eta * -i_dot_m / (o_dot_m + eta * i_dot_m).powi(2);
+        Some((m, jacobian))
+    }
+
+    fn eval_transmission(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        let (m, jacobian) = match self.refraction_half_vector(wo, wi) {
+            Some(refraction) => refraction,
+            None => return Vec3::default(),
+        };
+        let alpha = self.alpha;
+        let o_dot_m = wo.dot(m);
+        let transmitted = 1. - fresnel_dielectric(o_dot_m, self.eta);
+        let f_cos = transmitted
+            * ggx_d(m, alpha, alpha)
+            * smith_g2(wo, wi, alpha, alpha)
+            * o_dot_m
+            * jacobian
+            / wo.z();
+        // the square root tints by the base color once over the two crossings of a
+        // closed object
+        let tint = Vec3::new(
+            self.base_color.x().max(0.).sqrt(),
+            self.base_color.y().max(0.).sqrt(),
+            self.base_color.z().max(0.).sqrt(),
+        );
+        tint * (self.transmission_weight * f_cos)
+    }
+
+    fn eval_reflection(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        let alpha = self.alpha;
+        let h = (wo + wi).norm();
+        let cos_d = wi.dot(h);
+        let f_o = schlick_weight(wo.z());
+        let f_i = schlick_weight(wi.z());
+
+        // diffuse with Burley's retro-reflection, blended towards the subsurface
+        // approximation
+        let fd90 = 0.5 + 2. * self.roughness * cos_d * cos_d;
+        let fd = (1. + (fd90 - 1.) * f_i) * (1. + (fd90 - 1.) * f_o);
+        let fss90 = self.roughness * cos_d * cos_d;
+        let fss = (1. + (fss90 - 1.) * f_i) * (1. + (fss90 - 1.) * f_o);
+        let ss = 1.25 * (fss * (1. / (wi.z() + wo.z()) - 0.5) + 0.5);
+        let diffuse = self.base_color
+            * (self.diffuse_weight * ((1. - self.subsurface) * fd + self.subsurface * ss) / PI);
+        let sheen = self.sheen_color * (self.diffuse_weight * schlick_weight(cos_d));
+
+        // specular reflection: Schlick for the opaque part, exact Fresnel where the surface
+        // also transmits, so that reflection and transmission add up
+        let d = ggx_d(h, alpha, alpha);
+        let fresnel = (1. - self.transmission_weight) * fresnel_schlick(self.specular_color, cos_d)
+            + self.transmission_weight
+                * fresnel_dielectric(wo.dot(h), self.eta)
+                * Vec3::new(1., 1., 1.);
+        let specular = fresnel * (d * smith_g2(wo, wi, alpha, alpha) / (4. * wo.z() * wi.z()));
+
+        let clearcoat = 0.25
+            * self.clearcoat
+            * gtr1(h, self.clearcoat_alpha)
+            * fresnel_schlick(Vec3::new(0.04, 0.04, 0.04), cos_d).x()
+            * smith_g1(wo, CLEARCOAT_ALPHA, CLEARCOAT_ALPHA)
+            * smith_g1(wi, CLEARCOAT_ALPHA, CLEARCOAT_ALPHA)
+            / (4. * wo.z() * wi.z());
+
+        (diffuse + sheen + specular + Vec3::new(clearcoat, clearcoat, clearcoat)) * wi.z()
+    }
+}
+
+impl Principled {
+    fn local_frame(&self, r_in: Ray, hit: &HitRecord) -> (Onb, Vec3) {
+        let onb = Onb::build_from_w(hit.normal);
+        let wo = onb.to_local(-r_in.direction().norm());
+        (onb, wo)
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3, bool)> {
+        let (onb, wo) = self.local_frame(r_in, hit);
+        if wo.z() <= 0. {
+            return None;
+        }
+        let params = self.parameters(hit);
+        let p = params.lobe_probabilities(wo);
+
+        let u = random_in_01();
+        let lobe = if u < p[0] {
+            Lobe::Diffuse
+        } else if u < p[0] + p[1] {
+            Lobe::Specular
+        } else if u < p[0] + p[1] + p[2] {
+            Lobe::Clearcoat
+        } else {
+            Lobe::Transmission
+        };
+        let (u1, u2) = (random_in_01(), random_in_01());
+        let wi = match lobe {
+            Lobe::Diffuse => sample_cosine_hemisphere(u1, u2),
+            Lobe::Specular => {
+                let m = sample_visible_normal(wo, params.alpha, params.alpha, u1, u2);
+                -wo + 2. * wo.dot(m) * m
+            }
+            Lobe::Clearcoat => {
+                let m = sample_gtr1(params.clearcoat_alpha, u1, u2);
+                -wo + 2. * wo.dot(m) * m
+            }
+            Lobe::Transmission => {
+                let m = sample_visible_normal(wo, params.alpha, params.alpha, u1, u2);
+                // total internal reflection
+                refract(wo, m, params.eta).unwrap_or_else(|| -wo + 2. * wo.dot(m) * m)
+            }
+        };
+
+        // weight by the pdf of the whole mixture, so every lobe gets its share of the
+        // contribution whichever one was sampled
+        let (f_cos, pdf) = params.eval_and_pdf(wo, wi);
+        if pdf <= 0. {
+            return None;
+        }
+        Some((
+            Ray::new(hit.p, onb.local(wi), r_in.time()),
+            f_cos / pdf,
+            false,
+        ))
+    }
+
+    fn eval(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        let (onb, wo) = self.local_frame(r_in, hit);
+        let params = self.parameters(hit);
+        params.eval_and_pdf(wo, onb.to_local(direction.norm())).0
+    }
+
+    fn scattering_pdf(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        let (onb, wo) = self.local_frame(r_in, hit);
+        let params = self.parameters(hit);
+        params.eval_and_pdf(wo, onb.to_local(direction.norm())).1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::tests::assert_consistent;
+
+    fn incoming() -> [Vec3; 3] {
+        [
+            Vec3::new(0., 0., -1.),
+            Vec3::new(0.5, 0.1, -0.8).norm(),
+            Vec3::new(-0.9, 0.2, -0.3).norm(),
+        ]
+    }
+
+    #[test]
+    fn eval_matches_scatter() {
+        let base = || constant(0.8);
+        let materials = [
+            Principled::new(base()).with_roughness(constant(0.5)),
+            Principled::new(base())
+                .with_metallic(constant(1.))
+                .with_roughness(constant(0.4)),
+            Principled::new(base())
+                .with_roughness(constant(0.6))
+                .with_sheen(constant(1.), constant(0.5))
+                .with_clearcoat(constant(1.), constant(0.5))
+                .with_subsurface(constant(0.5)),
+            Principled::new(base())
+                .with_roughness(constant(0.5))
+                .with_transmission(constant(0.8), 1.5),
+        ];
+        for material in materials.iter() {
+            for &d in &incoming() {
+                assert_consistent(Arc::new(material.clone()), d);
+            }
+        }
+    }
+
+    #[test]
+    fn eval_matches_scatter_inside_glass() {
+        // from inside, steep directions are totally internally reflected
+        let glass = Principled::new(constant(1.))
+            .with_roughness(constant(0.4))
+            .with_transmission(constant(1.), 1.5);
+        for &d in &incoming() {
+            assert_consistent(Arc::new(glass.clone()), -d);
+        }
+    }
+}
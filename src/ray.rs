@@ -5,6 +5,8 @@ pub struct Ray {
     origin: Vec3,
     direction: Vec3,
     time: f64,
+    // in nanometres; set once the path has committed to a single wavelength
+    wavelength: Option<f64>,
 }
 
 impl Ray {
@@ -13,8 +15,13 @@ impl Ray {
             origin,
             direction,
             time,
+            wavelength: None,
         }
     }
+    pub fn with_wavelength(mut self, wavelength: f64) -> Self {
+        self.wavelength = Some(wavelength);
+        self
+    }
     pub fn origin(&self) -> Vec3 {
         self.origin
     }
@@ -24,6 +31,9 @@ impl Ray {
     pub fn time(&self) -> f64 {
         self.time
     }
+    pub fn wavelength(&self) -> Option<f64> {
+        self.wavelength
+    }
     pub fn at(&self, t: f64) -> Vec3 {
         self.origin + self.direction * t
     }
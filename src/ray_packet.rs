@@ -0,0 +1,30 @@
+use crate::ray::Ray;
+
+pub const PACKET_SIZE: usize = 8;
+
+// A bundle of coherent rays (e.g. primary rays for neighbouring pixels, or shadow rays
+// towards the same light) that walk a BvhNode together. Bit i of `active` says whether
+// rays[i] takes part; inactive slots are ignored by traversal.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct RayPacket {
+    pub rays: [Ray; PACKET_SIZE],
+    pub active: u8,
+}
+
+impl RayPacket {
+    // the first rays.len() slots are filled and activated
+    pub fn new(rays: &[Ray]) -> Self {
+        if rays.len() > PACKET_SIZE {
+            eprintln!("{} rays do not fit in a packet", rays.len());
+        }
+        let mut packet = Self::default();
+        for (i, r) in rays.iter().take(PACKET_SIZE).enumerate() {
+            packet.rays[i] = *r;
+            packet.active |= 1 << i;
+        }
+        packet
+    }
+    pub fn is_active(&self, i: usize) -> bool {
+        self.active & (1 << i) != 0
+    }
+}
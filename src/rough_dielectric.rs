@@ -0,0 +1,167 @@
+use crate::{
+    hittable::HitRecord,
+    material::Material,
+    microfacet::{
+        fresnel_dielectric, ggx_d, roughness_to_alpha, sample_visible_normal, smith_g1, smith_g2,
+        visible_normal_pdf, MIN_ALPHA,
+    },
+    onb::Onb,
+    ray::Ray,
+    utils::random_in_01,
+    vec3::Vec3,
+};
+
+// Frosted glass: a GGX microfacet interface that both reflects and transmits, following
+// Walter et al. 2007, "Microfacet Models for Refraction through Rough Surfaces". Reflection
+// and refraction are chosen with the exact Fresnel term of the sampled microfacet.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct RoughDielectric {
+    refractive_index: f64,
+    alpha: f64,
+}
+
+impl RoughDielectric {
+    pub fn new(refractive_index: f64, roughness: f64) -> Self {
+        Self {
+            refractive_index,
+            alpha: roughness_to_alpha(roughness).max(MIN_ALPHA),
+        }
+    }
+
+    fn is_smooth(&self) -> bool {
+        self.alpha <= MIN_ALPHA
+    }
+
+    // (shading frame, outgoing direction in that frame, relative index across the surface)
+    fn local_frame(&self, r_in: Ray, hit: &HitRecord) -> (Onb, Vec3, f64) {
+        let onb = Onb::build_from_w(hit.normal);
+        let wo = onb.to_local(-r_in.direction().norm());
+        let eta = if hit.front_face {
+            self.refractive_index
+        } else {
+            1. / self.refractive_index
+        };
+        (onb, wo, eta)
+    }
+
+    // (f * cos, pdf) for scattering from wo into wi
+    fn eval_and_pdf(&self, wo: Vec3, wi: Vec3, eta: f64) -> (f64, f64) {
+        if self.is_smooth() || wo.z() <= 0. || wi.z() == 0. {
+            return (0., 0.);
+        }
+        let alpha = self.alpha;
+        if wi.z() > 0. {
+            let m = (wo + wi).norm();
+            let fresnel = fresnel_dielectric(wo.dot(m), eta);
+            let d = ggx_d(m, alpha, alpha);
+            let f_cos = fresnel * d * smith_g2(wo, wi, alpha, alpha) / (4. * wo.z());
+            let pdf = fresnel * smith_g1(wo, alpha, alpha) * d / (4. * wo.z());
+            (f_cos, pdf)
+        } else {
+            // generalized half vector for refraction, oriented towards wo
+            let mut m = -(wo + eta * wi).norm();
+            if m.z() < 0. {
+                m = -m;
+            }
+            let o_dot_m = wo.dot(m);
+            let i_dot_m = wi.dot(m);
+            if o_dot_m <= 0. || i_dot_m >= 0. {
+                return (0., 0.);
+            }
+            let transmitted = 1. - fresnel_dielectric(o_dot_m, eta);
+            let d = ggx_d(m, alpha, alpha);
+            // Jacobian of the refraction mapping from microfacet normal to direction
+            let jacobian = eta * eta * -i_dot_m / (o_dot_m + eta * i_dot_m).powi(2);
+            let visible_pdf = visible_normal_pdf(wo, m, alpha, alpha);
+            let pdf = transmitted * visible_pdf * jacobian;
+            let f_cos =
+                transmitted * smith_g2(wo, wi, alpha, alpha) * o_dot_m * d * jacobian / wo.z();
+            (f_cos, pdf)
+        }
+    }
+}
+
+// Refracts the outgoing direction wo through the microfacet with normal m; None on total
+// internal reflection
+fn refract(wo: Vec3, m: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = wo.dot(m);
+    let sin_2_t = (1. - cos_i * cos_i) / (eta * eta);
+    if sin_2_t >= 1. {
+        return None;
+    }
+    let cos_t = (1. - sin_2_t).sqrt();
+    Some(-wo / eta + (cos_i / eta - cos_t) * m)
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3, bool)> {
+        let (onb, wo, eta) = self.local_frame(r_in, hit);
+        if wo.z() <= 0. {
+            return None;
+        }
+        let m = if self.is_smooth() {
+            Vec3::new(0., 0., 1.)
+        } else {
+            sample_visible_normal(wo, self.alpha, self.alpha, random_in_01(), random_in_01())
+        };
+
+        // Fresnel weights cancel against the probability of picking each branch
+        let fresnel = fresnel_dielectric(wo.dot(m), eta);
+        let wi = if random_in_01() <= fresnel {
+            let reflected = -wo + 2. * wo.dot(m) * m;
+            if reflected.z() <= 0. {
+                return None;
+            }
+            reflected
+        } else {
+            match refract(wo, m, eta) {
+                Some(refracted) if refracted.z() < 0. => refracted,
+                _ => return None,
+            }
+        };
+
+        let attenuation = if self.is_smooth() {
+            1.
+        } else {
+            smith_g2(wo, wi, self.alpha, self.alpha) / smith_g1(wo, self.alpha, self.alpha)
+        };
+        Some((
+            Ray::new(hit.p, onb.local(wi), r_in.time()),
+            Vec3::new(attenuation, attenuation, attenuation),
+            self.is_smooth(),
+        ))
+    }
+
+    fn eval(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        let (onb, wo, eta) = self.local_frame(r_in, hit);
+        let (f_cos, _) = self.eval_and_pdf(wo, onb.to_local(direction.norm()), eta);
+        Vec3::new(f_cos, f_cos, f_cos)
+    }
+
+    fn scattering_pdf(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        let (onb, wo, eta) = self.local_frame(r_in, hit);
+        self.eval_and_pdf(wo, onb.to_local(direction.norm()), eta).1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::tests::assert_consistent;
+    use std::sync::Arc;
+
+    #[test]
+    fn eval_matches_scatter() {
+        let incoming = [
+            Vec3::new(0., 0., -1.),
+            Vec3::new(0.6, 0.2, -0.7).norm(),
+            // from inside the glass, including past the critical angle
+            Vec3::new(0.3, -0.1, 0.9).norm(),
+            Vec3::new(-0.8, 0.2, 0.4).norm(),
+        ];
+        for &d in &incoming {
+            assert_consistent(Arc::new(RoughDielectric::new(1.5, 0.3)), d);
+            assert_consistent(Arc::new(RoughDielectric::new(1.33, 0.7)), d);
+        }
+    }
+}
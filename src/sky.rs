@@ -0,0 +1,175 @@
+use crate::{
+    background::Background,
+    light::DistantDisk,
+    spectrum::{xyz_to_linear_srgb, Illuminant},
+    vec3::Vec3,
+};
+use std::f64::consts::PI;
+
+// as seen from the earth
+const SUN_ANGULAR_RADIUS: f64 = 0.00465;
+const SUN_TEMPERATURE: f64 = 5778.;
+// luminance of the sun above the atmosphere, in the sky model's kcd/m^2
+const SUN_LUMINANCE: f64 = 1.6e6;
+
+// Perez et al.'s five parameter model of how sky brightness (or chromaticity) varies with
+// the zenith angle theta of the view and its angle gamma to the sun
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+struct Perez {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+}
+
+impl Perez {
+    fn value(&self, cos_theta: f64, gamma: f64) -> f64 {
+        (1. + self.a * (self.b / cos_theta).exp())
+            * (1. + self.c * (self.d * gamma).exp() + self.e * gamma.cos().powi(2))
+    }
+}
+
+// Daylight from the analytic model of Preetham, Shirley and Smits 1999, "A Practical
+// Analytic Model for Daylight". Turbidity runs from about 2 for a clear day to 10 for a hazy
+// one. The sky is scaled so a white diffuse surface facing an overhead sun, above the
+// atmosphere, has radiance intensity; sun() is the matching light.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct PreethamSky {
+    sun_direction: Vec3,
+    // sun's zenith angle
+    theta_s: f64,
+    turbidity: f64,
+    // CIE xyY at the zenith, divided by the Perez functions there
+    zenith: Vec3,
+    perez_y: Perez,
+    perez_x_chroma: Perez,
+    perez_y_chroma: Perez,
+    scale: f64,
+}
+
+impl PreethamSky {
+    // Elevation of the sun above the horizon and its azimuth around the y axis (from +z
+    // towards +x), both in degrees
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64) -> Self {
+        let elevation = elevation.to_radians();
+        let azimuth = azimuth.to_radians();
+        let sun_direction = Vec3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            elevation.cos() * azimuth.cos(),
+        );
+        let theta_s = PI / 2. - elevation;
+        let t = turbidity;
+
+        let perez_y = Perez {
+            a: 0.1787 * t - 1.4630,
+            b: -0.3554 * t + 0.4275,
+            c: -0.0227 * t + 5.3251,
+            d: 0.1206 * t - 2.5771,
+            e: -0.0670 * t + 0.3703,
+        };
+        let perez_x_chroma = Perez {
+            a: -0.0193 * t - 0.2592,
+            b: -0.0665 * t + 0.0008,
+            c: -0.0004 * t + 0.2125,
+            d: -0.0641 * t - 0.8989,
+            e: -0.0033 * t + 0.0452,
+        };
+        let perez_y_chroma = Perez {
+            a: -0.0167 * t - 0.2608,
+            b: -0.0950 * t + 0.0092,
+            c: -0.0079 * t + 0.2102,
+            d: -0.0441 * t - 1.6537,
+            e: -0.0109 * t + 0.0529,
+        };
+
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic = |c3: f64, c2: f64, c1: f64, c0: f64| {
+            c3 * theta_s.powi(3) + c2 * theta_s.powi(2) + c1 * theta_s + c0
+        };
+        let zenith_x = t * t * cubic(0.00166, -0.00375, 0.00209, 0.)
+            + t * cubic(-0.02903, 0.06377, -0.03202, 0.00394)
+            + cubic(0.11693, -0.21196, 0.06052, 0.25886);
+        let zenith_y = t * t * cubic(0.00275, -0.00610, 0.00317, 0.)
+            + t * cubic(-0.04214, 0.08970, -0.04153, 0.00516)
+            + cubic(0.15346, -0.26756, 0.06670, 0.26688);
+        let cos_theta_s = theta_s.cos();
+
+        let sun_solid_angle = 2. * PI * (1. - SUN_ANGULAR_RADIUS.cos());
+        Self {
+            sun_direction,
+            theta_s,
+            turbidity,
+            zenith: Vec3::new(
+                zenith_x / perez_x_chroma.value(1., theta_s),
+                zenith_y / perez_y_chroma.value(1., theta_s),
+                zenith_luminance.max(0.) / perez_y.value(1., theta_s),
+            ),
+            perez_y,
+            perez_x_chroma,
+            perez_y_chroma,
+            // the model breaks down once the sun has set
+            scale: if cos_theta_s > 0. {
+                PI / (SUN_LUMINANCE * sun_solid_angle)
+            } else {
+                0.
+            },
+        }
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.scale *= intensity;
+        self
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun_direction
+    }
+
+    // The sun's disk as a light. Its color comes from light lost on the way through the
+    // atmosphere, to molecules (Rayleigh) and haze (Ångström's aerosol formula).
+    pub fn sun(&self) -> DistantDisk {
+        let cos_theta_s = self.theta_s.cos();
+        let radiance = if cos_theta_s <= 0. {
+            Vec3::default()
+        } else {
+            // relative air mass (Kasten and Young)
+            let theta_degrees = self.theta_s.to_degrees();
+            let air_mass = 1. / (cos_theta_s + 0.15 * (93.885 - theta_degrees).powf(-1.253));
+            let beta = 0.04608 * self.turbidity - 0.04586;
+            let transmittance = |lambda_um: f64| {
+                let rayleigh = 0.008735 * lambda_um.powf(-4.08);
+                let aerosol = beta * lambda_um.powf(-1.3);
+                (-(rayleigh + aerosol) * air_mass).exp()
+            };
+            let color = Illuminant::blackbody(SUN_TEMPERATURE).to_rgb();
+            SUN_LUMINANCE
+                * self.scale
+                * Vec3::new(
+                    color.x() * transmittance(0.65),
+                    color.y() * transmittance(0.55),
+                    color.z() * transmittance(0.45),
+                )
+        };
+        DistantDisk::new(self.sun_direction, SUN_ANGULAR_RADIUS, radiance)
+    }
+}
+
+impl Background for PreethamSky {
+    fn value(&self, direction: Vec3) -> Vec3 {
+        // below the horizon, repeat the sky just above it
+        let cos_theta = direction.y().max(0.01);
+        let gamma = direction.dot(self.sun_direction).clamp(-1., 1.).acos();
+        let x = self.zenith.x() * self.perez_x_chroma.value(cos_theta, gamma);
+        let y = self.zenith.y() * self.perez_y_chroma.value(cos_theta, gamma);
+        let luminance = self.zenith.z() * self.perez_y.value(cos_theta, gamma);
+        if y <= 0. {
+            return Vec3::default();
+        }
+        let xyz = Vec3::new(x / y * luminance, luminance, (1. - x - y) / y * luminance);
+        let rgb = self.scale * xyz_to_linear_srgb(xyz);
+        Vec3::new(rgb.x().max(0.), rgb.y().max(0.), rgb.z().max(0.))
+    }
+}
@@ -0,0 +1,483 @@
+use crate::{
+    hittable::HitRecord,
+    material::Material,
+    utils::{random_in_01, random_in_range},
+    vec3::Vec3,
+};
+use std::ops::{Add, AddAssign, Mul};
+
+// Visible range sampled by spectral effects, in nanometres
+pub const LAMBDA_MIN: f64 = 380.;
+pub const LAMBDA_MAX: f64 = 780.;
+
+// Integrals over [LAMBDA_MIN, LAMBDA_MAX] of the red, green and blue curves produced by
+// wavelength_to_rgb (computed numerically), used to keep white light white
+const RGB_INTEGRALS: [f64; 3] = [176.1773222, 115.3855698, 109.3184554];
+// integral of the fitted CIE y curve over the same range
+const CIE_Y_INTEGRAL: f64 = 106.9197346;
+// scales the D65 table so that it has a luminance (Y) of 1
+const D65_NORMALIZATION: f64 = 0.0101161283;
+
+fn piecewise_gaussian(x: f64, mu: f64, sigma_below: f64, sigma_above: f64) -> f64 {
+    let sigma = if x < mu { sigma_below } else { sigma_above };
+    (-0.5 * ((x - mu) / sigma).powi(2)).exp()
+}
+
+// CIE 1931 2° color matching functions, using the multi-lobe fit from Wyman, Sloan and
+// Shirley 2013, "Simple Analytic Approximations to the CIE XYZ Color Matching Functions"
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    let x = 1.056 * piecewise_gaussian(lambda, 599.8, 37.9, 31.0)
+        + 0.362 * piecewise_gaussian(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * piecewise_gaussian(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * piecewise_gaussian(lambda, 568.8, 46.9, 40.5)
+        + 0.286 * piecewise_gaussian(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * piecewise_gaussian(lambda, 437.0, 11.8, 36.0)
+        + 0.681 * piecewise_gaussian(lambda, 459.0, 26.0, 13.8);
+    Vec3::new(x, y, z)
+}
+
+pub fn xyz_to_linear_srgb(xyz: Vec3) -> Vec3 {
+    Vec3::new(
+        3.2404542 * xyz.x() - 1.5371385 * xyz.y() - 0.4985314 * xyz.z(),
+        -0.9692660 * xyz.x() + 1.8760108 * xyz.y() + 0.0415560 * xyz.z(),
+        0.0556434 * xyz.x() - 0.2040259 * xyz.y() + 1.0572252 * xyz.z(),
+    )
+}
+
+// Linear sRGB color of a single wavelength, with out-of-gamut negative values clipped
+pub fn wavelength_to_rgb(lambda: f64) -> Vec3 {
+    let rgb = xyz_to_linear_srgb(cie_xyz(lambda));
+    Vec3::new(rgb.x().max(0.), rgb.y().max(0.), rgb.z().max(0.))
+}
+
+pub fn sample_wavelength() -> f64 {
+    random_in_range(LAMBDA_MIN, LAMBDA_MAX)
+}
+
+// Weight that turns a path carrying only the wavelength lambda, chosen by
+// sample_wavelength(), back into an RGB contribution. Averaged over all wavelengths it is
+// (1, 1, 1), so paths through non-dispersive materials keep their color on average.
+pub fn wavelength_to_rgb_weight(lambda: f64) -> Vec3 {
+    let rgb = wavelength_to_rgb(lambda);
+    let range = LAMBDA_MAX - LAMBDA_MIN;
+    Vec3::new(
+        rgb.x() * range / RGB_INTEGRALS[0],
+        rgb.y() * range / RGB_INTEGRALS[1],
+        rgb.z() * range / RGB_INTEGRALS[2],
+    )
+}
+
+// CIE standard illuminant D65 from 380nm to 780nm in 10nm steps
+const D65: [f64; 41] = [
+    49.9755, 54.6482, 82.7549, 91.486, 93.4318, 86.6823, 104.865, 117.008, 117.812, 114.861,
+    115.923, 108.811, 109.354, 107.802, 104.79, 107.689, 104.405, 104.046, 100.0, 96.3342, 95.788,
+    88.6856, 90.0062, 89.5991, 87.6987, 83.2886, 83.6992, 80.0268, 80.2146, 82.2778, 78.2842,
+    69.7213, 71.6091, 74.349, 61.604, 69.8856, 75.087, 63.5927, 46.4182, 66.8054, 63.3828,
+];
+
+// Basis spectra from Smits 1999, "An RGB to Spectrum Conversion for Reflectances", in ten
+// equal bins from 380nm to 720nm
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+const SMITS_MIN: f64 = 380.;
+const SMITS_MAX: f64 = 720.;
+
+// Linear interpolation in a table of samples spaced evenly from start to end
+fn lookup(table: &[f64], start: f64, end: f64, lambda: f64) -> f64 {
+    let last = table.len() - 1;
+    let x = (lambda - start) / (end - start) * last as f64;
+    if x <= 0. {
+        return table[0];
+    }
+    let i = (x as usize).min(last - 1);
+    let t = (x - i as f64).min(1.);
+    (1. - t) * table[i] + t * table[i + 1]
+}
+
+fn smits(table: &[f64; 10], lambda: f64) -> f64 {
+    // the table stores bins; sample at the bin centers
+    let half_bin = 0.5 * (SMITS_MAX - SMITS_MIN) / 10.;
+    lookup(table, SMITS_MIN + half_bin, SMITS_MAX - half_bin, lambda)
+}
+
+// D65 scaled to unit luminance, so that it looks like RGB (1, 1, 1)
+pub fn d65(lambda: f64) -> f64 {
+    D65_NORMALIZATION * lookup(&D65, LAMBDA_MIN, LAMBDA_MAX, lambda)
+}
+
+// Smooth reflectance spectrum with (roughly) the given linear sRGB color
+pub fn rgb_to_reflectance(rgb: Vec3, lambda: f64) -> f64 {
+    let (r, g, b) = (rgb.x().max(0.), rgb.y().max(0.), rgb.z().max(0.));
+    let basis = |table: &[f64; 10]| smits(table, lambda);
+    if r <= g && r <= b {
+        r * basis(&SMITS_WHITE)
+            + if g <= b {
+                (g - r) * basis(&SMITS_CYAN) + (b - g) * basis(&SMITS_BLUE)
+            } else {
+                (b - r) * basis(&SMITS_CYAN) + (g - b) * basis(&SMITS_GREEN)
+            }
+    } else if g <= r && g <= b {
+        g * basis(&SMITS_WHITE)
+            + if r <= b {
+                (r - g) * basis(&SMITS_MAGENTA) + (b - r) * basis(&SMITS_BLUE)
+            } else {
+                (b - g) * basis(&SMITS_MAGENTA) + (r - b) * basis(&SMITS_RED)
+            }
+    } else {
+        b * basis(&SMITS_WHITE)
+            + if r <= g {
+                (r - b) * basis(&SMITS_YELLOW) + (g - r) * basis(&SMITS_GREEN)
+            } else {
+                (g - b) * basis(&SMITS_YELLOW) + (r - g) * basis(&SMITS_RED)
+            }
+    }
+}
+
+// Emission spectrum for an RGB light: the reflectance shape lit by the sRGB white point
+pub fn rgb_to_illuminant(rgb: Vec3, lambda: f64) -> f64 {
+    rgb_to_reflectance(rgb, lambda) * d65(lambda)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum IlluminantKind {
+    Blackbody { temperature: f64 },
+    D65,
+    // incandescent tungsten
+    A,
+}
+
+// A light source spectrum, normalized to unit luminance and then multiplied by scale
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Illuminant {
+    kind: IlluminantKind,
+    scale: f64,
+}
+
+// Planck's law, with lambda in nanometres
+fn planck(lambda: f64, temperature: f64) -> f64 {
+    const C: f64 = 299792458.;
+    const H: f64 = 6.62606957e-34;
+    const K_B: f64 = 1.3806488e-23;
+    let l = lambda * 1e-9;
+    2. * H * C * C / (l.powi(5) * ((H * C / (l * K_B * temperature)).exp() - 1.))
+}
+
+impl Illuminant {
+    fn new(kind: IlluminantKind) -> Self {
+        let unnormalized = Self { kind, scale: 1. };
+        let luminance = unnormalized.to_xyz().y();
+        Self {
+            kind,
+            scale: 1. / luminance,
+        }
+    }
+    // temperature in kelvin
+    pub fn blackbody(temperature: f64) -> Self {
+        Self::new(IlluminantKind::Blackbody { temperature })
+    }
+    pub fn d65() -> Self {
+        Self::new(IlluminantKind::D65)
+    }
+    pub fn a() -> Self {
+        Self::new(IlluminantKind::A)
+    }
+    pub fn scaled(self, factor: f64) -> Self {
+        Self {
+            kind: self.kind,
+            scale: self.scale * factor,
+        }
+    }
+
+    pub fn value(&self, lambda: f64) -> f64 {
+        let unscaled = match self.kind {
+            IlluminantKind::Blackbody { temperature } => planck(lambda, temperature),
+            IlluminantKind::D65 => d65(lambda),
+            IlluminantKind::A => {
+                // closed form from the CIE definition
+                let c2: f64 = 1.435e7;
+                100. * (560. / lambda).powi(5) * ((c2 / (2848. * 560.)).exp() - 1.)
+                    / ((c2 / (2848. * lambda)).exp() - 1.)
+            }
+        };
+        self.scale * unscaled
+    }
+
+    fn to_xyz(self) -> Vec3 {
+        const STEPS: usize = 400;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / STEPS as f64;
+        let mut xyz = Vec3::default();
+        for i in 0..STEPS {
+            let lambda = LAMBDA_MIN + (i as f64 + 0.5) * step;
+            xyz += cie_xyz(lambda) * self.value(lambda);
+        }
+        xyz * (step / CIE_Y_INTEGRAL)
+    }
+
+    // linear sRGB color of the light, for RGB rendering
+    pub fn to_rgb(&self) -> Vec3 {
+        xyz_to_linear_srgb(self.to_xyz())
+    }
+}
+
+// Linear sRGB of a blackbody at temperature (kelvin), relative to one at
+// reference_temperature which has unit luminance. Unlike Illuminant::blackbody, hotter
+// bodies come out much brighter as well as bluer.
+pub fn blackbody_rgb(temperature: f64, reference_temperature: f64) -> Vec3 {
+    let radiance = |temperature| Illuminant {
+        kind: IlluminantKind::Blackbody { temperature },
+        scale: 1.,
+    };
+    let reference = radiance(reference_temperature).to_xyz().y();
+    if temperature <= 0. || reference <= 0. {
+        return Vec3::default();
+    }
+    radiance(temperature).to_rgb() / reference
+}
+
+pub const NUM_WAVELENGTHS: usize = 4;
+
+// Hero wavelength sampling (Wilkie et al. 2014): one uniformly chosen wavelength plus others
+// spaced evenly around the visible range, all traced along the same path.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct SampledWavelengths {
+    lambda: [f64; NUM_WAVELENGTHS],
+    pdf: [f64; NUM_WAVELENGTHS],
+}
+
+impl SampledWavelengths {
+    pub fn sample() -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = random_in_01() * range;
+        let mut lambda = [0.; NUM_WAVELENGTHS];
+        for (i, l) in lambda.iter_mut().enumerate() {
+            let offset = (hero + i as f64 * range / NUM_WAVELENGTHS as f64) % range;
+            *l = LAMBDA_MIN + offset;
+        }
+        Self {
+            lambda,
+            pdf: [1. / range; NUM_WAVELENGTHS],
+        }
+    }
+
+    // the wavelength that wavelength-dependent materials see
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+    pub fn lambda(&self) -> [f64; NUM_WAVELENGTHS] {
+        self.lambda
+    }
+
+    // Called when a path hits something whose behaviour depends on wavelength (e.g. a
+    // dispersive Dielectric): from then on only the hero wavelength is valid.
+    pub fn terminate_secondary(&mut self) {
+        if self.secondary_terminated() {
+            return;
+        }
+        for pdf in self.pdf.iter_mut().skip(1) {
+            *pdf = 0.;
+        }
+        self.pdf[0] /= NUM_WAVELENGTHS as f64;
+    }
+    pub fn secondary_terminated(&self) -> bool {
+        self.pdf[1..].iter().all(|&pdf| pdf == 0.)
+    }
+
+    // Monte Carlo estimate of the CIE XYZ color of a radiance sampled at these wavelengths
+    pub fn to_xyz(&self, radiance: SampledSpectrum) -> Vec3 {
+        let mut xyz = Vec3::default();
+        for i in 0..NUM_WAVELENGTHS {
+            if self.pdf[i] != 0. {
+                xyz += cie_xyz(self.lambda[i]) * (radiance.values[i] / self.pdf[i]);
+            }
+        }
+        xyz / (NUM_WAVELENGTHS as f64 * CIE_Y_INTEGRAL)
+    }
+}
+
+// Radiance at each of a path's SampledWavelengths
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct SampledSpectrum {
+    values: [f64; NUM_WAVELENGTHS],
+}
+
+impl SampledSpectrum {
+    pub fn new(values: [f64; NUM_WAVELENGTHS]) -> Self {
+        Self { values }
+    }
+    pub fn from_fn<F: Fn(f64) -> f64>(wavelengths: &SampledWavelengths, f: F) -> Self {
+        let mut values = [0.; NUM_WAVELENGTHS];
+        for (value, &lambda) in values.iter_mut().zip(wavelengths.lambda.iter()) {
+            *value = f(lambda);
+        }
+        Self { values }
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn add(self, other: SampledSpectrum) -> SampledSpectrum {
+        let mut values = self.values;
+        for (value, o) in values.iter_mut().zip(other.values.iter()) {
+            *value += o;
+        }
+        Self { values }
+    }
+}
+
+impl AddAssign for SampledSpectrum {
+    fn add_assign(&mut self, other: SampledSpectrum) {
+        *self = *self + other;
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn mul(self, other: SampledSpectrum) -> SampledSpectrum {
+        let mut values = self.values;
+        for (value, o) in values.iter_mut().zip(other.values.iter()) {
+            *value *= o;
+        }
+        Self { values }
+    }
+}
+
+// Radiance carried along a path, so that one integrator serves both RGB and spectral
+// rendering. Materials and textures always work in RGB; these conversions lift their
+// values into whichever representation the path uses.
+pub trait Radiance: Copy + Default + Add<Output = Self> + Mul<Output = Self> + AddAssign {
+    fn from_reflectance(rgb: Vec3, wavelengths: &SampledWavelengths) -> Self;
+    fn from_illuminant(rgb: Vec3, wavelengths: &SampledWavelengths) -> Self;
+    fn emitted(hit: &HitRecord, wavelengths: &SampledWavelengths) -> Self;
+}
+
+// plain RGB rendering ignores the wavelengths
+impl Radiance for Vec3 {
+    fn from_reflectance(rgb: Vec3, _wavelengths: &SampledWavelengths) -> Self {
+        rgb
+    }
+    fn from_illuminant(rgb: Vec3, _wavelengths: &SampledWavelengths) -> Self {
+        rgb
+    }
+    fn emitted(hit: &HitRecord, _wavelengths: &SampledWavelengths) -> Self {
+        hit.material.emitted(hit.u, hit.v, hit.p)
+    }
+}
+
+impl Radiance for SampledSpectrum {
+    fn from_reflectance(rgb: Vec3, wavelengths: &SampledWavelengths) -> Self {
+        SampledSpectrum::from_fn(wavelengths, |lambda| rgb_to_reflectance(rgb, lambda))
+    }
+    fn from_illuminant(rgb: Vec3, wavelengths: &SampledWavelengths) -> Self {
+        SampledSpectrum::from_fn(wavelengths, |lambda| rgb_to_illuminant(rgb, lambda))
+    }
+    fn emitted(hit: &HitRecord, wavelengths: &SampledWavelengths) -> Self {
+        let material: &dyn Material = hit.material.as_ref();
+        SampledSpectrum::from_fn(wavelengths, |lambda| {
+            material.emitted_spectral(hit.u, hit.v, hit.p, lambda)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // average of f over the visible range, by the midpoint rule
+    fn average<F: Fn(f64) -> Vec3>(f: F) -> Vec3 {
+        const STEPS: usize = 4000;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / STEPS as f64;
+        let mut sum = Vec3::default();
+        for i in 0..STEPS {
+            sum += f(LAMBDA_MIN + (i as f64 + 0.5) * step);
+        }
+        sum / STEPS as f64
+    }
+
+    // linear sRGB seen from a spectrum, by integrating it against the color matching functions
+    fn spectrum_to_rgb<F: Fn(f64) -> f64>(spectrum: F) -> Vec3 {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let xyz = average(|lambda| cie_xyz(lambda) * spectrum(lambda)) * (range / CIE_Y_INTEGRAL);
+        xyz_to_linear_srgb(xyz)
+    }
+
+    fn assert_close(a: Vec3, b: Vec3, tolerance: f64) {
+        assert!((a - b).magnitude() < tolerance, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn smits_spectra_round_trip_to_rgb() {
+        let colors = [
+            Vec3::new(1., 1., 1.),
+            Vec3::new(0.5, 0.5, 0.5),
+            Vec3::new(0.8, 0.2, 0.1),
+            Vec3::new(0.1, 0.7, 0.3),
+            Vec3::new(0.2, 0.3, 0.9),
+            Vec3::new(0.9, 0.8, 0.1),
+        ];
+        for &rgb in &colors {
+            let seen = spectrum_to_rgb(|lambda| rgb_to_illuminant(rgb, lambda));
+            assert_close(seen, rgb, 0.05);
+        }
+    }
+
+    #[test]
+    fn smits_white_is_flat() {
+        for i in 0..=40 {
+            let lambda = LAMBDA_MIN + 10. * i as f64;
+            let white = rgb_to_reflectance(Vec3::new(1., 1., 1.), lambda);
+            assert!((white - 1.).abs() < 0.02, "{} at {}nm", white, lambda);
+            assert_eq!(rgb_to_reflectance(Vec3::default(), lambda), 0.);
+        }
+    }
+
+    #[test]
+    fn illuminants_have_unit_luminance() {
+        assert_close(Illuminant::d65().to_rgb(), Vec3::new(1., 1., 1.), 0.01);
+        for &illuminant in &[Illuminant::a(), Illuminant::blackbody(3000.)] {
+            assert!((illuminant.to_xyz().y() - 1.).abs() < 1e-9);
+            let rgb = illuminant.to_rgb();
+            // warm light
+            assert!(rgb.x() > rgb.y() && rgb.y() > rgb.z());
+        }
+        assert!((Illuminant::d65().scaled(3.).to_xyz().y() - 3.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sampled_wavelengths_estimate_xyz() {
+        let expected = Illuminant::d65().to_xyz();
+        let samples = 20_000;
+        let mut xyz = Vec3::default();
+        for _ in 0..samples {
+            let wavelengths = SampledWavelengths::sample();
+            xyz += wavelengths.to_xyz(SampledSpectrum::from_fn(&wavelengths, d65));
+        }
+        assert_close(xyz / samples as f64, expected, 0.02);
+    }
+
+    #[test]
+    fn rgb_weights_average_to_white() {
+        let mean = average(wavelength_to_rgb_weight);
+        for axis in 0..3 {
+            assert!((mean[axis] - 1.).abs() < 1e-4, "{:?}", mean);
+        }
+    }
+}
@@ -5,7 +5,7 @@ use crate::{
     ray::Ray,
     vec3::Vec3,
 };
-use std::sync::Arc;
+use std::{f64::consts::PI, sync::Arc};
 #[derive(Clone, Debug)]
 pub struct Sphere {
     center: Vec3,
@@ -23,6 +23,39 @@ impl Sphere {
     }
 }
 
+// (u, v) in [0, 1] for a point on the unit sphere, with u going around the y axis starting
+// from -x, and v from the south pole to the north pole
+pub fn sphere_uv(p: Vec3) -> (f64, f64) {
+    let phi = (-p.z()).atan2(p.x()) + PI;
+    let theta = (-p.y()).clamp(-1., 1.).acos();
+    (phi / (2. * PI), theta / PI)
+}
+
+// shared by Sphere and MovingSphere
+pub(crate) fn sphere_hit_record(
+    t: f64,
+    p: Vec3,
+    center: Vec3,
+    radius: f64,
+    r: Ray,
+    material: Arc<dyn Material>,
+) -> HitRecord {
+    let outward_normal = (p - center) / radius;
+    let (u, v) = sphere_uv(outward_normal);
+    let phi = 2. * PI * u;
+    let theta = PI * v;
+    // derivatives of p = center + radius * (-cos phi sin theta, -cos theta, sin phi sin theta)
+    let dpdu = 2. * PI * radius * Vec3::new(phi.sin() * theta.sin(), 0., phi.cos() * theta.sin());
+    let dpdv = PI
+        * radius
+        * Vec3::new(
+            -phi.cos() * theta.cos(),
+            theta.sin(),
+            phi.sin() * theta.cos(),
+        );
+    HitRecord::new(t, p, outward_normal, r, material).with_uv(u, v, dpdu, dpdv)
+}
+
 impl Hittable for Sphere {
     fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let oc: Vec3 = r.origin() - self.center;
@@ -38,10 +71,11 @@ impl Hittable for Sphere {
                 let root_1 = (-half_b - disc_sqrt) / a;
                 if root_1 > t_min && root_1 < t_max {
                     let p = r.at(root_1);
-                    return Some(HitRecord::new(
+                    return Some(sphere_hit_record(
                         root_1,
                         p,
-                        (p - self.center) / self.radius,
+                        self.center,
+                        self.radius,
                         r,
                         self.material.clone(),
                     ));
@@ -50,10 +84,11 @@ impl Hittable for Sphere {
             let root_2 = (-half_b + disc_sqrt) / a;
             if root_2 > t_min && root_2 < t_max {
                 let p = r.at(root_2);
-                Some(HitRecord::new(
+                Some(sphere_hit_record(
                     root_2,
                     p,
-                    (p - self.center) / self.radius,
+                    self.center,
+                    self.radius,
                     r,
                     self.material.clone(),
                 ))
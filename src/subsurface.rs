@@ -0,0 +1,79 @@
+use crate::{
+    dielectric::Dielectric, hittable::HitRecord, material::Material, medium::HomogeneousMedium,
+    ray::Ray, vec3::Vec3,
+};
+
+// Translucent materials such as skin, wax, marble and milk. Light refracts into the object
+// through a smooth boundary and then random-walks through a scattering medium inside it until
+// it finds its way back out, so it must be used on closed objects (spheres, closed meshes).
+// The walk itself is done by the integrator, which follows interior_medium().
+#[derive(Clone, Debug)]
+pub struct Subsurface {
+    boundary: Dielectric,
+}
+
+impl Subsurface {
+    // albedo is the single-scattering albedo, the fraction of light surviving each scattering
+    // event (the object looks darker than it, as light scatters many times before leaving);
+    // mean_free_path is the average distance between events, per color channel, in scene
+    // units (skin scatters red furthest)
+    pub fn new(albedo: Vec3, mean_free_path: Vec3, refractive_index: f64) -> Self {
+        Self::from_medium(
+            HomogeneousMedium::from_albedo(albedo, mean_free_path),
+            refractive_index,
+        )
+    }
+    // from measured absorption and scattering coefficients
+    pub fn from_coefficients(absorption: Vec3, scattering: Vec3, refractive_index: f64) -> Self {
+        Self::from_medium(
+            HomogeneousMedium::new(absorption).with_scattering(scattering),
+            refractive_index,
+        )
+    }
+    pub fn from_medium(medium: HomogeneousMedium, refractive_index: f64) -> Self {
+        Self {
+            boundary: Dielectric::new(refractive_index).with_medium(medium),
+        }
+    }
+}
+
+impl Material for Subsurface {
+    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3, bool)> {
+        self.boundary.scatter(r_in, hit)
+    }
+
+    fn interior_medium(&self) -> Option<HomogeneousMedium> {
+        self.boundary.interior_medium()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // scattering / (absorption + scattering), per channel
+    fn single_scattering_albedo(material: &Subsurface) -> Vec3 {
+        let medium = material.interior_medium().unwrap();
+        let extinction = medium.absorption() + medium.scattering();
+        let channel = |axis: usize| medium.scattering()[axis] / extinction[axis];
+        Vec3::new(channel(0), channel(1), channel(2))
+    }
+
+    #[test]
+    fn albedo_is_per_scattering_event() {
+        let white = Vec3::new(1., 1., 1.);
+        let scattering = Vec3::new(2., 1., 0.5);
+        let non_absorbing = Subsurface::from_coefficients(Vec3::default(), scattering, 1.4);
+        assert!((single_scattering_albedo(&non_absorbing) - white).magnitude() < 1e-12);
+        // and a white albedo gives that same medium back
+        let medium = Subsurface::new(white, Vec3::new(0.5, 1., 2.), 1.4)
+            .interior_medium()
+            .unwrap();
+        assert_eq!(medium.absorption(), Vec3::default());
+        assert!((medium.scattering() - scattering).magnitude() < 1e-12);
+
+        let albedo = Vec3::new(0.9, 0.5, 0.2);
+        let colored = Subsurface::new(albedo, Vec3::new(0.5, 1., 2.), 1.4);
+        assert!((single_scattering_albedo(&colored) - albedo).magnitude() < 1e-12);
+    }
+}
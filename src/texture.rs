@@ -1,6 +1,6 @@
 use crate::{perlin::Perlin, vec3::Vec3};
-use std::{fmt::Debug, sync::Arc, path::Path};
-use image;
+use image::RgbImage;
+use std::{fmt::Debug, path::Path, sync::Arc};
 
 pub trait Texture: Sync + Send + Debug {
     fn value(&self, u: f64, v: f64, p: Vec3) -> Vec3;
@@ -79,46 +79,52 @@ impl Texture for NoiseTexture {
 
 #[derive(Clone, Debug)]
 pub struct ImageTexture {
-    data: ImageBuffer<Rgb<u8>, Vec<u8>>
+    // None if the image could not be loaded
+    data: Option<RgbImage>,
 }
 
 impl ImageTexture {
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
-        let image_result = image::open(path).unwrap().to_rgb();// TODO: that's not safe...
-        // auto components_per_pixel = bytes_per_pixel;
-
-        // data = stbi_load(
-        //     filename, &width, &height, &components_per_pixel, components_per_pixel);
-
-        // if (!data) {
-        //     std::cerr << "ERROR: Could not load texture image file '" << filename << "'.\n";
-        //     width = height = 0;
-        // }
-
-        // bytes_per_scanline = bytes_per_pixel * width;
+        let data = match image::open(path.as_ref()) {
+            Ok(image) => Some(image.to_rgb()),
+            Err(error) => {
+                eprintln!(
+                    "ERROR: Could not load texture image file {:?}: {}",
+                    path.as_ref(),
+                    error
+                );
+                None
+            }
+        };
+        // an empty image has no pixels to look up
+        let data = data.filter(|image| image.width() > 0 && image.height() > 0);
+        Self { data }
     }
 }
+
 impl Texture for ImageTexture {
-    fn value(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
+    fn value(&self, u: f64, v: f64, _p: Vec3) -> Vec3 {
+        let data = match &self.data {
+            Some(data) => data,
             // If we have no texture data, then return solid cyan as a debugging aid.
-            if self.data == nullptr {
-                return color(0,1,1);
-            }
-
-            // Clamp input texture coordinates to [0,1] x [1,0]
-            let u = u.max(0.).min(1.);
-            v = 1. - v.max(0.).min(1.);  // Flip V to image coordinates
-
-            let i = (u * width) as usize;
-            let j = (v * height) as usize;
-
-            // Clamp integer mapping, since actual coordinates should be less than 1.0
-            let i = if (i >= width)  {width-1} else {i};
-            let j = if (j >= height) {j = height-1} else {j};
-
-            let color_scale = 1. / 255.;
-            let pixel = self.data[j][i];
-
-            return Vec3::new(color_scale*pixel[0], color_scale*pixel[1], color_scale*pixel[2]);
+            None => return Vec3::new(0., 1., 1.),
+        };
+        let (width, height) = data.dimensions();
+
+        // Clamp input texture coordinates to [0,1] x [1,0]
+        let u = u.clamp(0., 1.);
+        let v = 1. - v.clamp(0., 1.); // Flip V to image coordinates
+
+        // Clamp integer mapping, since actual coordinates should be less than 1.0
+        let i = ((u * width as f64) as u32).min(width - 1);
+        let j = ((v * height as f64) as u32).min(height - 1);
+
+        let color_scale = 1. / 255.;
+        let pixel = data.get_pixel(i, j);
+        Vec3::new(
+            color_scale * pixel[0] as f64,
+            color_scale * pixel[1] as f64,
+            color_scale * pixel[2] as f64,
+        )
     }
 }
@@ -0,0 +1,262 @@
+use crate::{
+    spectrum::{wavelength_to_rgb_weight, LAMBDA_MAX, LAMBDA_MIN},
+    texture::{SolidColor, Texture},
+    vec3::Vec3,
+};
+use std::{
+    f64::consts::PI,
+    ops::{Add, Div, Mul, Sub},
+    sync::Arc,
+};
+
+// wavelengths used to turn the reflectance spectrum into RGB
+const RGB_SAMPLES: usize = 32;
+
+// A thin transparent layer on top of a surface, thin enough that light reflected off its top
+// and bottom interferes: soap bubbles, oil on water, heat-tinted metal. The reflected color
+// depends on thickness, viewing angle and wavelength.
+#[derive(Clone, Debug)]
+pub struct ThinFilm {
+    // in nanometres, from the texture's first channel
+    thickness: Arc<dyn Texture>,
+    refractive_index: f64,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: f64, refractive_index: f64) -> Self {
+        Self::textured(
+            Arc::new(SolidColor::new_from_rgb(thickness, thickness, thickness)),
+            refractive_index,
+        )
+    }
+    // thickness varying over the surface, e.g. a NoiseTexture scaled up to a few hundred nm
+    pub fn textured(thickness: Arc<dyn Texture>, refractive_index: f64) -> Self {
+        Self {
+            thickness,
+            refractive_index,
+        }
+    }
+
+    pub fn thickness(&self, u: f64, v: f64, p: Vec3) -> f64 {
+        self.thickness.value(u, v, p).x().max(0.)
+    }
+
+    // Reflectance at one wavelength for light arriving at cos_theta from a medium with index
+    // outside onto a substrate with complex index substrate(lambda) = eta + i*k
+    pub fn reflectance<F: Fn(f64) -> (f64, f64)>(
+        &self,
+        cos_theta: f64,
+        thickness: f64,
+        outside: f64,
+        substrate: F,
+        lambda: f64,
+    ) -> f64 {
+        let (eta, k) = substrate(lambda);
+        airy_reflectance(
+            cos_theta,
+            thickness,
+            lambda,
+            outside,
+            self.refractive_index,
+            Complex::new(eta, k),
+        )
+    }
+
+    // reflectance averaged against the RGB color matching functions
+    pub fn reflectance_rgb<F: Fn(f64) -> (f64, f64)>(
+        &self,
+        cos_theta: f64,
+        thickness: f64,
+        outside: f64,
+        substrate: F,
+    ) -> Vec3 {
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / RGB_SAMPLES as f64;
+        let mut rgb = Vec3::default();
+        for i in 0..RGB_SAMPLES {
+            let lambda = LAMBDA_MIN + (i as f64 + 0.5) * step;
+            rgb += wavelength_to_rgb_weight(lambda)
+                * self.reflectance(cos_theta, thickness, outside, &substrate, lambda);
+        }
+        rgb / RGB_SAMPLES as f64
+    }
+}
+
+// Airy summation of all the reflections inside a film of index n_film between a medium of
+// index n_outside and a (possibly absorbing) substrate, averaged over both polarizations
+fn airy_reflectance(
+    cos_theta: f64,
+    thickness: f64,
+    lambda: f64,
+    n_outside: f64,
+    n_film: f64,
+    n_substrate: Complex,
+) -> f64 {
+    let n1 = Complex::new(n_outside, 0.);
+    let n2 = Complex::new(n_film, 0.);
+    let n3 = n_substrate;
+    let cos_1 = Complex::new(cos_theta.abs().min(1.), 0.);
+    // Snell's law with complex angles covers total internal reflection and absorption
+    let sin_1_sq = Complex::new(1., 0.) - cos_1 * cos_1;
+    let cos_of = |n: Complex| (Complex::new(1., 0.) - sin_1_sq * n1 * n1 / (n * n)).sqrt();
+    let cos_2 = cos_of(n2);
+    let cos_3 = cos_of(n3);
+
+    // phase difference between successive reflections
+    let phase = Complex::new(0., 4. * PI * thickness / lambda) * n2 * cos_2;
+    let shift = phase.exp();
+
+    let polarization = |r12: Complex, r23: Complex| {
+        let r = (r12 + r23 * shift) / (Complex::new(1., 0.) + r12 * r23 * shift);
+        r.norm_sqr()
+    };
+    let s = polarization(
+        fresnel_s(n1, cos_1, n2, cos_2),
+        fresnel_s(n2, cos_2, n3, cos_3),
+    );
+    let p = polarization(
+        fresnel_p(n1, cos_1, n2, cos_2),
+        fresnel_p(n2, cos_2, n3, cos_3),
+    );
+    (0.5 * (s + p)).min(1.)
+}
+
+// Fresnel amplitude coefficients
+fn fresnel_s(n_i: Complex, cos_i: Complex, n_t: Complex, cos_t: Complex) -> Complex {
+    (n_i * cos_i - n_t * cos_t) / (n_i * cos_i + n_t * cos_t)
+}
+
+fn fresnel_p(n_i: Complex, cos_i: Complex, n_t: Complex, cos_t: Complex) -> Complex {
+    (n_t * cos_i - n_i * cos_t) / (n_t * cos_i + n_i * cos_t)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+    fn norm_sqr(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+    // principal square root
+    fn sqrt(self) -> Self {
+        let r = self.norm_sqr().sqrt();
+        let re = (0.5 * (r + self.re)).max(0.).sqrt();
+        let im = (0.5 * (r - self.re)).max(0.).sqrt();
+        Self::new(re, if self.im < 0. { -im } else { im })
+    }
+    fn exp(self) -> Self {
+        let scale = self.re.exp();
+        Self::new(scale * self.im.cos(), scale * self.im.sin())
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+    fn div(self, other: Complex) -> Complex {
+        let denominator = other.norm_sqr();
+        Complex::new(
+            (self.re * other.re + self.im * other.im) / denominator,
+            (self.im * other.re - self.re * other.im) / denominator,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{conductor::Conductor, material::tests::assert_consistent};
+
+    fn glass(_lambda: f64) -> (f64, f64) {
+        (1.5, 0.)
+    }
+
+    #[test]
+    fn vanishing_films_leave_plain_fresnel() {
+        // ((1 - 1.5) / (1 + 1.5))^2 at normal incidence
+        let bare = 0.04;
+        let film = ThinFilm::new(300., 1.33);
+        for &lambda in &[420., 550., 680.] {
+            assert!((film.reflectance(1., 0., 1., glass, lambda) - bare).abs() < 1e-12);
+        }
+        // a film with the index of the outside medium is no film at all
+        let air = ThinFilm::new(0., 1.);
+        for &thickness in &[50., 300., 1000.] {
+            assert!((air.reflectance(1., thickness, 1., glass, 550.) - bare).abs() < 1e-12);
+        }
+        let rgb = film.reflectance_rgb(1., 0., 1., glass);
+        for axis in 0..3 {
+            assert!((rgb[axis] - bare).abs() < 0.002, "{:?}", rgb);
+        }
+    }
+
+    #[test]
+    fn quarter_wave_coating_cancels_reflection() {
+        let n_film = 1.5f64.sqrt();
+        let lambda = 550.;
+        let film = ThinFilm::new(0., n_film);
+        let quarter_wave = lambda / (4. * n_film);
+        assert!(film.reflectance(1., quarter_wave, 1., glass, lambda) < 1e-12);
+        // a half-wave layer reflects like the bare glass
+        let half_wave = 2. * quarter_wave;
+        assert!((film.reflectance(1., half_wave, 1., glass, lambda) - 0.04).abs() < 1e-12);
+    }
+
+    #[test]
+    fn reflectance_stays_within_bounds() {
+        let film = ThinFilm::new(0., 1.33);
+        let gold = |_| (0.2, 3.);
+        for i in 0..=20 {
+            let cos_theta = i as f64 / 20.;
+            for &thickness in &[0., 120., 480.] {
+                for &lambda in &[400., 500., 600., 700.] {
+                    for r in &[
+                        film.reflectance(cos_theta, thickness, 1., glass, lambda),
+                        film.reflectance(cos_theta, thickness, 1., gold, lambda),
+                        // from inside the glass, past the critical angle
+                        film.reflectance(cos_theta, thickness, 1.5, |_| (1., 0.), lambda),
+                    ] {
+                        assert!((0. ..=1.).contains(r), "{}", r);
+                    }
+                }
+            }
+        }
+        // grazing light is reflected completely
+        assert!((film.reflectance(0., 200., 1., glass, 550.) - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn coated_conductor_is_consistent() {
+        let material = Arc::new(Conductor::gold(0.4).with_thin_film(ThinFilm::new(250., 2.4)));
+        assert_consistent(material.clone(), Vec3::new(0.3, 0.2, -1.).norm());
+        assert_consistent(material, Vec3::new(-0.8, 0.1, -0.4).norm());
+    }
+}
@@ -0,0 +1,242 @@
+use crate::{aabb::AABB, ray::Ray, vec3::Vec3};
+
+// Affine transform stored as the top 3 rows of a 4x4 matrix, along with its inverse
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    m: [[f64; 4]; 3],
+    inv: [[f64; 4]; 3],
+}
+
+const IDENTITY: [[f64; 4]; 3] = [[1., 0., 0., 0.], [0., 1., 0., 0.], [0., 0., 1., 0.]];
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Self {
+            m: IDENTITY,
+            inv: IDENTITY,
+        }
+    }
+
+    pub fn from_matrix(m: [[f64; 4]; 3]) -> Self {
+        Self { m, inv: invert(m) }
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        Self::from_matrix([
+            [1., 0., 0., offset.x()],
+            [0., 1., 0., offset.y()],
+            [0., 0., 1., offset.z()],
+        ])
+    }
+
+    pub fn scaling(factors: Vec3) -> Self {
+        Self::from_matrix([
+            [factors.x(), 0., 0., 0.],
+            [0., factors.y(), 0., 0.],
+            [0., 0., factors.z(), 0.],
+        ])
+    }
+
+    pub fn uniform_scaling(factor: f64) -> Self {
+        Self::scaling(Vec3::new(factor, factor, factor))
+    }
+
+    pub fn rotation_x(degrees: f64) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Self::from_matrix([[1., 0., 0., 0.], [0., cos, -sin, 0.], [0., sin, cos, 0.]])
+    }
+
+    pub fn rotation_y(degrees: f64) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Self::from_matrix([[cos, 0., sin, 0.], [0., 1., 0., 0.], [-sin, 0., cos, 0.]])
+    }
+
+    pub fn rotation_z(degrees: f64) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Self::from_matrix([[cos, -sin, 0., 0.], [sin, cos, 0., 0.], [0., 0., 1., 0.]])
+    }
+
+    // apply self first, then other
+    pub fn then(&self, other: Transform) -> Self {
+        Self {
+            m: multiply(other.m, self.m),
+            inv: multiply(self.inv, other.inv),
+        }
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            m: self.inv,
+            inv: self.m,
+        }
+    }
+
+    pub fn apply_point(&self, p: Vec3) -> Vec3 {
+        apply(self.m, p, 1.)
+    }
+
+    pub fn apply_vector(&self, v: Vec3) -> Vec3 {
+        apply(self.m, v, 0.)
+    }
+
+    // normals transform with the inverse transpose; result is not normalized
+    pub fn apply_normal(&self, n: Vec3) -> Vec3 {
+        let inv = self.inv;
+        Vec3::new(
+            inv[0][0] * n.x() + inv[1][0] * n.y() + inv[2][0] * n.z(),
+            inv[0][1] * n.x() + inv[1][1] * n.y() + inv[2][1] * n.z(),
+            inv[0][2] * n.x() + inv[1][2] * n.y() + inv[2][2] * n.z(),
+        )
+    }
+
+    // the ray parameter t is preserved, so hit distances are valid in both spaces
+    pub fn apply_ray(&self, r: Ray) -> Ray {
+        Ray::new(
+            self.apply_point(r.origin()),
+            self.apply_vector(r.direction()),
+            r.time(),
+        )
+    }
+
+    pub fn apply_box(&self, b: AABB) -> AABB {
+        let mut result: Option<AABB> = None;
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { b.min().x() } else { b.max().x() },
+                if i & 2 == 0 { b.min().y() } else { b.max().y() },
+                if i & 4 == 0 { b.min().z() } else { b.max().z() },
+            );
+            let p = self.apply_point(corner);
+            let corner_box = AABB::new(p, p);
+            result = Some(match result {
+                None => corner_box,
+                Some(acc) => acc.combine(corner_box),
+            });
+        }
+        result.unwrap()
+    }
+}
+
+fn apply(m: [[f64; 4]; 3], v: Vec3, w: f64) -> Vec3 {
+    Vec3::new(
+        m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z() + m[0][3] * w,
+        m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z() + m[1][3] * w,
+        m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z() + m[2][3] * w,
+    )
+}
+
+// a * b, treating both as 4x4 matrices with an implicit last row of (0, 0, 0, 1)
+fn multiply(a: [[f64; 4]; 3], b: [[f64; 4]; 3]) -> [[f64; 4]; 3] {
+    let mut result = [[0.; 4]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = a[i][0] * b[0][j] + a[i][1] * b[1][j] + a[i][2] * b[2][j];
+        }
+        row[3] += a[i][3];
+    }
+    result
+}
+
+fn invert(m: [[f64; 4]; 3]) -> [[f64; 4]; 3] {
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let det = m[0][0] * cofactor(1, 2, 1, 2) - m[0][1] * cofactor(1, 2, 0, 2)
+        + m[0][2] * cofactor(1, 2, 0, 1);
+    if det == 0. {
+        eprintln!("Singular matrix in Transform");
+    }
+    let inv_det = 1. / det;
+
+    let mut inv = [[0.; 4]; 3];
+    inv[0][0] = cofactor(1, 2, 1, 2) * inv_det;
+    inv[0][1] = -cofactor(0, 2, 1, 2) * inv_det;
+    inv[0][2] = cofactor(0, 1, 1, 2) * inv_det;
+    inv[1][0] = -cofactor(1, 2, 0, 2) * inv_det;
+    inv[1][1] = cofactor(0, 2, 0, 2) * inv_det;
+    inv[1][2] = -cofactor(0, 1, 0, 2) * inv_det;
+    inv[2][0] = cofactor(1, 2, 0, 1) * inv_det;
+    inv[2][1] = -cofactor(0, 2, 0, 1) * inv_det;
+    inv[2][2] = cofactor(0, 1, 0, 1) * inv_det;
+
+    // inverse translation is -(inverse linear part) * translation
+    for row in inv.iter_mut() {
+        row[3] = -(row[0] * m[0][3] + row[1] * m[1][3] + row[2] * m[2][3]);
+    }
+    inv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).magnitude() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    fn transforms() -> Vec<Transform> {
+        vec![
+            Transform::translation(Vec3::new(1., -2., 3.)),
+            Transform::scaling(Vec3::new(2., 0.5, -3.)),
+            Transform::rotation_x(30.)
+                .then(Transform::rotation_y(-75.))
+                .then(Transform::rotation_z(140.)),
+            Transform::scaling(Vec3::new(1., 4., 0.25))
+                .then(Transform::rotation_y(45.))
+                .then(Transform::translation(Vec3::new(-5., 0., 2.))),
+            // a shear, which none of the constructors produce
+            Transform::from_matrix([[1., 0.7, 0., 2.], [0., 1., -0.4, 0.], [0.3, 0., 1., -1.]]),
+        ]
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        let points = [
+            Vec3::new(0., 0., 0.),
+            Vec3::new(1., 2., 3.),
+            Vec3::new(-4., 0.5, 7.),
+        ];
+        for t in transforms() {
+            for &p in &points {
+                assert_close(t.inverse().apply_point(t.apply_point(p)), p);
+                assert_close(t.apply_point(t.inverse().apply_point(p)), p);
+                assert_close(t.inverse().apply_vector(t.apply_vector(p)), p);
+            }
+            let identity = t.then(t.inverse());
+            for &p in &points {
+                assert_close(identity.apply_point(p), p);
+            }
+        }
+    }
+
+    #[test]
+    fn then_applies_in_order() {
+        let move_then_turn =
+            Transform::translation(Vec3::new(1., 0., 0.)).then(Transform::rotation_z(90.));
+        assert_close(
+            move_then_turn.apply_point(Vec3::default()),
+            Vec3::new(0., 1., 0.),
+        );
+    }
+
+    #[test]
+    fn normals_stay_perpendicular() {
+        let n = Vec3::new(0., 0., 1.);
+        let tangents = [
+            Vec3::new(1., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            Vec3::new(1., -2., 0.),
+        ];
+        for t in transforms() {
+            let transformed = t.apply_normal(n);
+            for &tangent in &tangents {
+                assert!(transformed.dot(t.apply_vector(tangent)).abs() < 1e-9);
+            }
+        }
+    }
+}
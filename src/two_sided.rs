@@ -0,0 +1,154 @@
+use crate::{
+    aabb::AABB,
+    hittable::{hit_accepted, HitRecord, Hittable},
+    material::Material,
+    medium::HomogeneousMedium,
+    ray::Ray,
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+// Different materials on the two sides of a surface, e.g. a page printed on both sides or a
+// leaf with a paler underside. The front is the side the outward normal points to.
+#[derive(Clone, Debug)]
+pub struct TwoSidedMaterial {
+    front: Arc<dyn Material>,
+    back: Arc<dyn Material>,
+}
+
+impl TwoSidedMaterial {
+    pub fn new(front: Arc<dyn Material>, back: Arc<dyn Material>) -> Self {
+        Self { front, back }
+    }
+
+    fn side(&self, hit: &HitRecord) -> &Arc<dyn Material> {
+        if hit.front_face {
+            &self.front
+        } else {
+            &self.back
+        }
+    }
+}
+
+impl Material for TwoSidedMaterial {
+    fn scatter(&self, r_in: Ray, hit: &HitRecord) -> Option<(Ray, Vec3, bool)> {
+        self.side(hit).scatter(r_in, hit)
+    }
+
+    fn eval(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        self.side(hit).eval(r_in, hit, direction)
+    }
+
+    fn scattering_pdf(&self, r_in: Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        self.side(hit).scattering_pdf(r_in, hit, direction)
+    }
+
+    // entered through the front face
+    fn interior_medium(&self) -> Option<HomogeneousMedium> {
+        self.front.interior_medium()
+    }
+
+    // emission doesn't know which side was hit, so only the front can glow
+    fn emitted(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
+        self.front.emitted(u, v, p)
+    }
+
+    fn emitted_spectral(&self, u: f64, v: f64, p: Vec3, lambda: f64) -> f64 {
+        self.front.emitted_spectral(u, v, p, lambda)
+    }
+
+    fn is_wavelength_dependent(&self) -> bool {
+        self.front.is_wavelength_dependent() || self.back.is_wavelength_dependent()
+    }
+}
+
+// Makes the back faces of an object invisible, so rays pass straight through them. A camera
+// inside a closed object, or outside a room modelled with inward-facing normals, then sees
+// through the nearest walls, as in cutaway shots.
+#[derive(Clone, Debug)]
+pub struct BackFaceCulled {
+    object: Arc<dyn Hittable>,
+}
+
+impl BackFaceCulled {
+    pub fn new(object: Arc<dyn Hittable>) -> Self {
+        Self { object }
+    }
+}
+
+impl Hittable for BackFaceCulled {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        hit_accepted(self.object.as_ref(), r, t_min, t_max, |rec| rec.front_face)
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.object.bounding_box(t0, t1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bvh_node::BvhNode, diffuse::Lambertian, hittable_list::HittableList,
+        material::tests::assert_consistent, sphere::Sphere, texture::SolidColor,
+    };
+
+    fn lambertian(r: f64, g: f64, b: f64) -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Arc::new(SolidColor::new_from_rgb(r, g, b))))
+    }
+
+    #[test]
+    fn each_side_uses_its_own_material() {
+        let front = Vec3::new(0.8, 0.8, 0.8);
+        let back = Vec3::new(0.2, 0.5, 0.1);
+        let material: Arc<dyn Material> = Arc::new(TwoSidedMaterial::new(
+            lambertian(front.x(), front.y(), front.z()),
+            lambertian(back.x(), back.y(), back.z()),
+        ));
+        let normal = Vec3::new(0., 0., 1.);
+        for &(incoming, albedo) in &[
+            (Vec3::new(0., 0., -1.), front),
+            (Vec3::new(0., 0., 1.), back),
+        ] {
+            let r_in = Ray::new(-incoming, incoming, 0.);
+            let hit = HitRecord::new(1., Vec3::default(), normal, r_in, material.clone());
+            // a lambertian reflects albedo / pi * cos back towards the ray's origin
+            let f = material.eval(r_in, &hit, -incoming);
+            assert!((f * std::f64::consts::PI - albedo).magnitude() < 1e-12);
+        }
+        // and is sampled like it, from either side
+        assert_consistent(material.clone(), Vec3::new(0.3, 0.1, -1.).norm());
+        assert_consistent(material, Vec3::new(0.3, 0.1, 1.).norm());
+    }
+
+    #[test]
+    fn culled_back_faces_are_seen_through() {
+        let mut list = HittableList::new();
+        // a room around the origin and a ball inside it
+        list.add(Arc::new(Sphere::new(
+            Vec3::new(0., 0., 0.),
+            10.,
+            lambertian(0.5, 0.5, 0.5),
+        )));
+        list.add(Arc::new(Sphere::new(
+            Vec3::new(0., 0., 5.),
+            1.,
+            lambertian(0.5, 0.5, 0.5),
+        )));
+        let world = BackFaceCulled::new(Arc::new(BvhNode::new_from_hittable(&list, 0., 1.)));
+
+        let towards_ball = Ray::new(Vec3::default(), Vec3::new(0., 0., 1.), 0.);
+        let rec = world.hit(towards_ball, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 4.).abs() < 1e-9);
+        assert!(rec.front_face);
+        assert!(world.hit(towards_ball, 0.001, 3.).is_none());
+        // the room's walls face inwards
+        let away = Ray::new(Vec3::default(), Vec3::new(0., 0., -1.), 0.);
+        assert!(world.hit(away, 0.001, f64::INFINITY).is_none());
+        // but are seen from outside
+        let from_outside = Ray::new(Vec3::new(0., 0., -20.), Vec3::new(0., 0., 1.), 0.);
+        let rec = world.hit(from_outside, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 10.).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,440 @@
+use crate::{
+    aabb::AABB,
+    bvh_node::{BvhChild, BvhNode},
+    hittable::{HitRecord, Hittable},
+    ray::Ray,
+    ray_packet::{RayPacket, PACKET_SIZE},
+};
+use std::sync::Arc;
+
+const WIDTH: usize = 4;
+
+#[derive(Clone, Debug)]
+enum WideChild {
+    Empty,
+    Node(usize),
+    Leaf(Arc<dyn Hittable>),
+}
+
+// Child boxes are stored as structure-of-arrays so that one SIMD register holds the same
+// coordinate of all four boxes
+#[derive(Clone, Debug)]
+struct WideNode {
+    // indexed by [axis][child]
+    min: [[f64; WIDTH]; 3],
+    max: [[f64; WIDTH]; 3],
+    // bit i is set if children[i] is not Empty
+    valid: u8,
+    children: [WideChild; WIDTH],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SimdLevel {
+    Scalar,
+    #[cfg(target_arch = "x86_64")]
+    Sse2,
+    #[cfg(target_arch = "x86_64")]
+    Avx,
+}
+
+#[cfg(target_arch = "x86_64")]
+fn detect_simd_level() -> SimdLevel {
+    if is_x86_feature_detected!("avx") {
+        SimdLevel::Avx
+    } else {
+        // always available on x86_64
+        SimdLevel::Sse2
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn detect_simd_level() -> SimdLevel {
+    SimdLevel::Scalar
+}
+
+// per-ray values shared by every box test
+struct RayData {
+    origin: [f64; 3],
+    inv_direction: [f64; 3],
+    // true where the direction is negative, so the ray enters through the max plane
+    negative: [bool; 3],
+}
+
+impl RayData {
+    fn new(r: &Ray) -> Self {
+        let mut data = Self {
+            origin: r.origin().data,
+            inv_direction: [0.; 3],
+            negative: [false; 3],
+        };
+        for axis in 0..3 {
+            data.inv_direction[axis] = 1. / r.direction()[axis];
+            data.negative[axis] = data.inv_direction[axis] < 0.;
+        }
+        data
+    }
+}
+
+// A 4-ary BVH collapsed from a binary BvhNode. Each node tests all four child boxes at
+// once, using AVX or SSE2 when available and a scalar loop otherwise. Hits are identical
+// to the binary tree's; see src/bin/bvh_bench.rs for a speed comparison.
+#[derive(Clone, Debug)]
+pub struct WideBvh {
+    nodes: Vec<WideNode>,
+    bounds: AABB,
+    simd_level: SimdLevel,
+}
+
+impl WideBvh {
+    pub fn from_binary(bvh: &BvhNode, time_0: f64, time_1: f64) -> Self {
+        let mut wide = Self {
+            nodes: vec![],
+            bounds: bvh.bounds,
+            simd_level: detect_simd_level(),
+        };
+        wide.collapse(bvh, time_0, time_1);
+        wide
+    }
+
+    // Force the portable code path, e.g. for benchmarking against the SIMD versions
+    pub fn without_simd(mut self) -> Self {
+        self.simd_level = SimdLevel::Scalar;
+        self
+    }
+
+    // returns the index of the new node
+    fn collapse(&mut self, bvh: &BvhNode, time_0: f64, time_1: f64) -> usize {
+        let mut children: Vec<&BvhChild> = vec![];
+        push_unique(&mut children, &bvh.left);
+        push_unique(&mut children, &bvh.right);
+        // pull grandchildren up into this node, opening the largest boxes first
+        while children.len() < WIDTH {
+            let largest = children
+                .iter()
+                .enumerate()
+                .filter_map(|(i, child)| match child {
+                    BvhChild::Node(node) => Some((i, node.bounds.surface_area())),
+                    BvhChild::Leaf(_, _) => None,
+                })
+                .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+            match largest {
+                None => break,
+                Some((i, _)) => {
+                    if let BvhChild::Node(node) = children.remove(i) {
+                        push_unique(&mut children, &node.left);
+                        push_unique(&mut children, &node.right);
+                    }
+                }
+            }
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(WideNode {
+            min: [[0.; WIDTH]; 3],
+            max: [[0.; WIDTH]; 3],
+            valid: 0,
+            children: [
+                WideChild::Empty,
+                WideChild::Empty,
+                WideChild::Empty,
+                WideChild::Empty,
+            ],
+        });
+
+        for (lane, child) in children.into_iter().enumerate() {
+            let bounds = child.bounding_box(time_0, time_1).unwrap_or_else(|| {
+                eprintln!("No bounding box in WideBvh constructor");
+                AABB::default()
+            });
+            let wide_child = match child {
+                BvhChild::Node(node) => WideChild::Node(self.collapse(node, time_0, time_1)),
+                BvhChild::Leaf(_, object) => WideChild::Leaf(object.clone()),
+            };
+            let node = &mut self.nodes[index];
+            for axis in 0..3 {
+                node.min[axis][lane] = bounds.min()[axis];
+                node.max[axis][lane] = bounds.max()[axis];
+            }
+            node.valid |= 1 << lane;
+            node.children[lane] = wide_child;
+        }
+        index
+    }
+
+    // returns the entry distance for each child and a bitmask of the children that were hit
+    fn intersect(&self, node: &WideNode, ray: &RayData, t_min: f64, t_max: f64) -> ([f64; 4], u8) {
+        let (t_near, mask) = match self.simd_level {
+            SimdLevel::Scalar => intersect_scalar(node, ray, t_min, t_max),
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Sse2 => unsafe { x86::intersect_sse2(node, ray, t_min, t_max) },
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Avx => unsafe { x86::intersect_avx(node, ray, t_min, t_max) },
+        };
+        (t_near, mask & node.valid)
+    }
+
+    fn hit_node(
+        &self,
+        index: usize,
+        r: Ray,
+        ray: &RayData,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<HitRecord> {
+        let node = &self.nodes[index];
+        let (t_near, mask) = self.intersect(node, ray, t_min, t_max);
+        if mask == 0 {
+            return None;
+        }
+
+        // visit the nearest boxes first so that later ones can be culled
+        let mut order = [0; WIDTH];
+        let mut count = 0;
+        for lane in 0..WIDTH {
+            if mask & (1 << lane) != 0 {
+                order[count] = lane;
+                count += 1;
+            }
+        }
+        // insertion sort; there are at most four
+        for i in 1..count {
+            let mut j = i;
+            while j > 0 && t_near[order[j]] < t_near[order[j - 1]] {
+                order.swap(j, j - 1);
+                j -= 1;
+            }
+        }
+
+        let mut closest_so_far = t_max;
+        let mut closest_hit = None;
+        for &lane in &order[..count] {
+            if t_near[lane] >= closest_so_far {
+                continue;
+            }
+            let hit = match &node.children[lane] {
+                WideChild::Empty => None,
+                WideChild::Node(child) => self.hit_node(*child, r, ray, t_min, closest_so_far),
+                WideChild::Leaf(object) => object.hit(r, t_min, closest_so_far),
+            };
+            if let Some(rec) = hit {
+                closest_so_far = rec.t;
+                closest_hit = Some(rec);
+            }
+        }
+        closest_hit
+    }
+
+    // Any-hit query for shadow rays, answering like BvhNode::occluded_packet. Each ray walks
+    // the tree on its own and stops at the first thing it hits, in whatever order.
+    pub fn occluded_packet(&self, packet: &RayPacket, t_min: f64, t_max: [f64; PACKET_SIZE]) -> u8 {
+        if self.nodes.is_empty() {
+            return 0;
+        }
+        let mut occluded = 0;
+        for (i, r) in packet.rays.iter().enumerate() {
+            if packet.is_active(i) && self.occluded_node(0, *r, &RayData::new(r), t_min, t_max[i]) {
+                occluded |= 1 << i;
+            }
+        }
+        occluded
+    }
+
+    fn occluded_node(&self, index: usize, r: Ray, ray: &RayData, t_min: f64, t_max: f64) -> bool {
+        let node = &self.nodes[index];
+        let (_, mask) = self.intersect(node, ray, t_min, t_max);
+        (0..WIDTH)
+            .filter(|lane| mask & (1 << lane) != 0)
+            .any(|lane| match &node.children[lane] {
+                WideChild::Empty => false,
+                WideChild::Node(child) => self.occluded_node(*child, r, ray, t_min, t_max),
+                WideChild::Leaf(object) => object.hit(r, t_min, t_max).is_some(),
+            })
+    }
+}
+
+fn push_unique<'a>(children: &mut Vec<&'a BvhChild>, child: &'a BvhChild) {
+    // single-object nodes store the same leaf on both sides
+    if let BvhChild::Leaf(index, _) = child {
+        let duplicate = children.iter().any(|c| match c {
+            BvhChild::Leaf(other, _) => other == index,
+            BvhChild::Node(_) => false,
+        });
+        if duplicate {
+            return;
+        }
+    }
+    children.push(child);
+}
+
+fn intersect_scalar(node: &WideNode, ray: &RayData, t_min: f64, t_max: f64) -> ([f64; 4], u8) {
+    let mut t_near = [t_min; WIDTH];
+    let mut t_far = [t_max; WIDTH];
+    for axis in 0..3 {
+        let (near_planes, far_planes) = if ray.negative[axis] {
+            (&node.max[axis], &node.min[axis])
+        } else {
+            (&node.min[axis], &node.max[axis])
+        };
+        for lane in 0..WIDTH {
+            let t0 = (near_planes[lane] - ray.origin[axis]) * ray.inv_direction[axis];
+            let t1 = (far_planes[lane] - ray.origin[axis]) * ray.inv_direction[axis];
+            t_near[lane] = if t0 > t_near[lane] { t0 } else { t_near[lane] };
+            t_far[lane] = if t1 < t_far[lane] { t1 } else { t_far[lane] };
+        }
+    }
+    let mut mask = 0;
+    for lane in 0..WIDTH {
+        if t_near[lane] < t_far[lane] {
+            mask |= 1 << lane;
+        }
+    }
+    (t_near, mask)
+}
+
+// Same computation as intersect_scalar. max/min return their second operand when either is
+// NaN, so putting the running value second ignores NaN slabs just like the scalar compares.
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::{RayData, WideNode, WIDTH};
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn intersect_avx(
+        node: &WideNode,
+        ray: &RayData,
+        t_min: f64,
+        t_max: f64,
+    ) -> ([f64; 4], u8) {
+        let mut t_near = _mm256_set1_pd(t_min);
+        let mut t_far = _mm256_set1_pd(t_max);
+        for axis in 0..3 {
+            let (near_planes, far_planes) = if ray.negative[axis] {
+                (&node.max[axis], &node.min[axis])
+            } else {
+                (&node.min[axis], &node.max[axis])
+            };
+            let origin = _mm256_set1_pd(ray.origin[axis]);
+            let inv_direction = _mm256_set1_pd(ray.inv_direction[axis]);
+            let t0 = _mm256_mul_pd(
+                _mm256_sub_pd(_mm256_loadu_pd(near_planes.as_ptr()), origin),
+                inv_direction,
+            );
+            let t1 = _mm256_mul_pd(
+                _mm256_sub_pd(_mm256_loadu_pd(far_planes.as_ptr()), origin),
+                inv_direction,
+            );
+            t_near = _mm256_max_pd(t0, t_near);
+            t_far = _mm256_min_pd(t1, t_far);
+        }
+        let mask = _mm256_movemask_pd(_mm256_cmp_pd(t_near, t_far, _CMP_LT_OQ)) as u8;
+        let mut result = [0.; WIDTH];
+        _mm256_storeu_pd(result.as_mut_ptr(), t_near);
+        (result, mask)
+    }
+
+    // two lanes at a time
+    pub(super) unsafe fn intersect_sse2(
+        node: &WideNode,
+        ray: &RayData,
+        t_min: f64,
+        t_max: f64,
+    ) -> ([f64; 4], u8) {
+        let mut t_near = [_mm_set1_pd(t_min); 2];
+        let mut t_far = [_mm_set1_pd(t_max); 2];
+        for axis in 0..3 {
+            let (near_planes, far_planes) = if ray.negative[axis] {
+                (&node.max[axis], &node.min[axis])
+            } else {
+                (&node.min[axis], &node.max[axis])
+            };
+            let origin = _mm_set1_pd(ray.origin[axis]);
+            let inv_direction = _mm_set1_pd(ray.inv_direction[axis]);
+            for half in 0..2 {
+                let t0 = _mm_mul_pd(
+                    _mm_sub_pd(_mm_loadu_pd(near_planes[2 * half..].as_ptr()), origin),
+                    inv_direction,
+                );
+                let t1 = _mm_mul_pd(
+                    _mm_sub_pd(_mm_loadu_pd(far_planes[2 * half..].as_ptr()), origin),
+                    inv_direction,
+                );
+                t_near[half] = _mm_max_pd(t0, t_near[half]);
+                t_far[half] = _mm_min_pd(t1, t_far[half]);
+            }
+        }
+        let mut result = [0.; WIDTH];
+        let mut mask = 0;
+        for half in 0..2 {
+            mask |= (_mm_movemask_pd(_mm_cmplt_pd(t_near[half], t_far[half])) as u8) << (2 * half);
+            _mm_storeu_pd(result[2 * half..].as_mut_ptr(), t_near[half]);
+        }
+        (result, mask)
+    }
+}
+
+impl Hittable for WideBvh {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        self.hit_node(0, r, &RayData::new(&r), t_min, t_max)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bvh_node::tests::{closest, rays, shadow_t_max, spheres},
+        vec3::Vec3,
+    };
+
+    #[test]
+    fn hits_match_the_binary_bvh() {
+        let bvh = BvhNode::new_from_hittable(&spheres(2000, 0.), 0., 1.);
+        let simd = WideBvh::from_binary(&bvh, 0., 1.);
+        let scalar = simd.clone().without_simd();
+        // axis-aligned rays have infinite inverse directions in the slab test
+        let axis_aligned = (0..300).map(|i| {
+            let i = i as f64;
+            let origin = Vec3::new(50. * (0.9 * i).sin(), 50. * (1.3 * i).cos(), -80.);
+            Ray::new(origin, Vec3::new(0., 0., 1.), 0.)
+        });
+        for r in rays(2000).chain(axis_aligned) {
+            let expected = closest(&bvh, r);
+            assert_eq!(closest(&simd, r), expected);
+            assert_eq!(closest(&scalar, r), expected);
+        }
+    }
+
+    #[test]
+    fn occluded_packets_match_the_binary_bvh() {
+        let bvh = BvhNode::new_from_hittable(&spheres(2000, 0.), 0., 1.);
+        let simd = WideBvh::from_binary(&bvh, 0., 1.);
+        let scalar = simd.clone().without_simd();
+        let rays: Vec<Ray> = rays(8 * 300).collect();
+        for (n, chunk) in rays.chunks(PACKET_SIZE).enumerate() {
+            let packet = RayPacket::new(&chunk[..1 + n % PACKET_SIZE]);
+            let t_max = shadow_t_max(&bvh, chunk, n);
+            let expected = bvh.occluded_packet(&packet, 0.001, t_max);
+            assert_eq!(simd.occluded_packet(&packet, 0.001, t_max), expected);
+            assert_eq!(scalar.occluded_packet(&packet, 0.001, t_max), expected);
+        }
+    }
+
+    #[test]
+    fn small_trees_fit_in_one_node() {
+        for count in 1..=WIDTH {
+            let bvh = BvhNode::new_from_hittable(&spheres(count, 0.), 0., 1.);
+            let wide = WideBvh::from_binary(&bvh, 0., 1.);
+            assert_eq!(wide.nodes.len(), 1);
+            for r in rays(200) {
+                assert_eq!(closest(&wide, r), closest(&bvh, r));
+            }
+        }
+    }
+}